@@ -0,0 +1,98 @@
+//! A fixed search workload for catching functional and speed regressions.
+
+use crate::bitboard::Board;
+use crate::engine::Engine;
+use crate::search_limits::SearchLimits;
+use crate::skill::Skill;
+use std::time::Instant;
+
+/// The default depth searched for every bench position.
+pub const BENCH_DEPTH: i32 = 6;
+
+/// Openings, middlegames and endgames, with both sides to move.
+pub const BENCH_FENS: [&str; 30] = [
+    "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1",
+    "r1bakabr1/9/n5nc1/p1p1p1p1p/9/9/PcP1P1P1P/C1C1B1N2/9/RNBAKA1R1 b - - 0 1",
+    "rnbak1bnr/4a4/9/pcp1p1p2/7P1/9/P1P1P1P2/3C2C2/4A3c/RNBAK1BNR w - - 0 1",
+    "r2a1kbnr/3na2C1/8b/pcp1p1p1p/9/2P3C2/P3P1P1P/9/9/RNBAKABNR b - - 0 1",
+    "1nb1kabnr/9/rc3a3/C1p1p1p1p/9/8P/PR2P1c2/2N4C1/9/2BAKABNR w - - 0 1",
+    "1n1a1k3/r1C1a4/b2cb2c1/p7r/1C2pnp1p/2B6/P1P1P1P1P/R8/5K3/1N1A1ABNR b - - 0 1",
+    "rnbaka1n1/9/c3b4/2p1p1p1p/p7P/P1B1c1P1R/2P6/1NC4r1/9/R2AKABN1 w - - 0 1",
+    "1nbakab1r/3C1n3/2r6/p1p1p1p1p/9/1R4P2/P1P1P3P/2C1B3N/4A4/4KAB1R b - - 0 1",
+    "rnbak1bnr/9/c4a2c/p3p1C2/3N5/6P1p/P3P4/B7C/9/R2AKABNR w - - 0 1",
+    "rnbak1bnr/4a4/7c1/4R3p/2p3p2/8C/P1P1P1PcP/1CN1B1N2/4A4/4KAB1R b - - 0 1",
+    "rnbak1bn1/5C3/3a5/9/P5C2/4P3P/2P3Pr1/9/R8/1NBAKABNR w - - 0 1",
+    "2bak1bR1/r3a4/2n2c2n/p1p3p1p/4pN3/9/2P5P/C1C1B4/9/RNBAKA3 b - - 0 1",
+    "3aka3/2c6/b1n1b2nc/pr2p4/2p3p1p/P7P/2P1P1P2/R1N1B4/4A4/2B1KA2R w - - 0 1",
+    "4ka1nr/1c7/2n1ba3/p1p1p3p/6b2/PNP6/4P3P/2CK5/4AN3/c1B2AB1R b - - 0 1",
+    "1rbakab2/9/6ncr/p7p/4N4/P1P6/4P1p1P/1C2B1N2/9/R2AKAR2 w - - 0 1",
+    "3a1ab2/2Ck5/4b4/pn1cpCp1p/2p6/2P1P1P1P/P7c/8B/3KA3r/1NB2A1N1 b - - 0 1",
+    "2b1kabn1/4a4/r1n6/p8/2p6/C5B1P/P1P1P4/N3K2N1/7R1/1RBA1c3 w - - 0 1",
+    "1nba1a1r1/4k4/8R/P1pc3Cn/8p/9/2P1P1P1P/6N2/4K4/2BA1ABR1 b - - 0 1",
+    "3akabr1/9/b8/p1c1p1p2/8p/P3P4/9/3AB1N2/4K4/2R2A2r w - - 0 1",
+    "2ba3r1/3k5/3ab4/p3p1p2/1np5p/6B2/PrP1P1PcP/8R/9/3KC4 b - - 0 1",
+    "2baka1n1/1r3c3/nC2b3r/p8/4P3p/P3P3P/6N2/9/1R2A4/2BAK1B1C w - - 0 1",
+    "2bak4/4a4/n7C/p5p2/2n6/P2RP3P/2Pc5/5rr2/3K5/9 b - - 0 1",
+    "3akab2/7n1/4b4/2p2R3/4p4/2P3N1P/N8/4B4/4A4/3AK1Br1 w - - 0 1",
+    "4k1b2/1c1nan2C/4ba3/p3p4/9/2p3B2/P8/2RAK1crB/3rA4/8R b - - 0 1",
+    "1nbaka2r/9/4b1n2/4R4/p8/P1P5P/5c3/4B3B/8C/1N1AKA3 w - - 0 1",
+    "3ak4/4a3R/4b4/8p/p5p2/p1B3P2/4R3P/4N4/3KAr3/n8 b - - 0 1",
+    "2naka3/9/4b3b/9/9/8p/6n2/3AB4/5r3/2R1K1B2 w - - 0 1",
+    "4kn3/9/3a5/9/p1b1p1R2/8P/P1P1R1P2/9/8N/3AKA3 b - - 0 1",
+    "5a3/9/4k4/9/p8/P8/2n4C1/R1N5B/3K5/2BA1A3 w - - 0 1",
+    "3a5/3k5/9/9/2b1C4/2B6/2P6/8B/4K4/9 w - - 0 1",
+];
+
+/// The outcome of `Engine::bench`.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchResult {
+    /// Nodes searched over all positions.
+    pub nodes: u64,
+    /// A hash of the nodes, best moves and scores of every position. It changes
+    /// whenever the search changes functionally.
+    pub signature: u64,
+    pub elapsed_ms: u128,
+}
+
+impl BenchResult {
+    pub fn nps(&self) -> u128 {
+        self.nodes as u128 * 1000 / self.elapsed_ms.max(1)
+    }
+}
+
+impl Engine {
+    /// Searches every `BENCH_FENS` position to `depth`, each as a new game. The
+    /// opening book, MultiPV, skill level and evaluation noise are switched off
+    /// meanwhile, so the result only depends on the search itself.
+    pub fn bench(&mut self, depth: i32) -> BenchResult {
+        let settings = (self.own_book, self.multi_pv, self.skill, self.eval_noise);
+        self.own_book = false;
+        self.multi_pv = 1;
+        self.skill = Skill::default();
+        self.eval_noise = 0;
+
+        // FNV-1a over the per-position results.
+        let mut signature: u64 = 0xcbf29ce484222325;
+        let mut hash = |value: u64| {
+            for byte in value.to_le_bytes() {
+                signature = (signature ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        };
+
+        let mut nodes = 0;
+        let start_time = Instant::now();
+        for fen in BENCH_FENS {
+            self.new_game();
+            let mut board = Board::from_fen(fen);
+            let result = self.search(&mut board, &SearchLimits::depth(depth));
+            nodes += self.nodes_searched;
+            hash(self.nodes_searched);
+            hash((result.best_move.from_sq() << 8 | result.best_move.to_sq()) as u64);
+            hash(result.score as u64);
+        }
+        let elapsed_ms = start_time.elapsed().as_millis();
+
+        (self.own_book, self.multi_pv, self.skill, self.eval_noise) = settings;
+        BenchResult { nodes, signature, elapsed_ms }
+    }
+}
@@ -1,31 +1,215 @@
 //! The core board representation for the Xiangqi engine.
 
+pub mod utils;
+
 use crate::constants::{Piece, Player};
+use crate::evaluate::Score;
+use crate::r#move::Move;
+use crate::square::Square;
 use crate::zobrist;
 use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Shl, Shr};
 use crate::movelist::MoveList;
 
-// C's __int128_t is u128 in Rust.
-pub type Bitboard = u128;
+/// A set of squares, one bit per square: squares 0-63 in the first word and 64-89 in
+/// the second. Most operations touch both words, but bit scans and population counts
+/// stay single-word operations on each, which a `u128` does not guarantee.
+///
+/// `trailing_zeros` and `leading_zeros` count as if the two words were one 128-bit
+/// integer, so the lowest square is `trailing_zeros()` and the highest is
+/// `127 - leading_zeros()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Bitboard([u64; 2]);
+
+impl Bitboard {
+    pub const EMPTY: Bitboard = Bitboard([0, 0]);
+
+    pub const fn from_u128(bits: u128) -> Self {
+        Bitboard([bits as u64, (bits >> 64) as u64])
+    }
+
+    pub const fn to_u128(self) -> u128 {
+        self.0[0] as u128 | (self.0[1] as u128) << 64
+    }
+
+    /// The two words, low squares first.
+    pub const fn words(self) -> [u64; 2] {
+        self.0
+    }
+
+    #[inline(always)]
+    pub const fn is_empty(self) -> bool {
+        (self.0[0] | self.0[1]) == 0
+    }
+
+    #[inline(always)]
+    pub const fn count_ones(self) -> u32 {
+        self.0[0].count_ones() + self.0[1].count_ones()
+    }
+
+    #[inline(always)]
+    pub const fn trailing_zeros(self) -> u32 {
+        if self.0[0] != 0 { self.0[0].trailing_zeros() } else { 64 + self.0[1].trailing_zeros() }
+    }
+
+    #[inline(always)]
+    pub const fn leading_zeros(self) -> u32 {
+        if self.0[1] != 0 { self.0[1].leading_zeros() } else { 64 + self.0[0].leading_zeros() }
+    }
+
+    /// Removes the lowest square, which must be there, and returns it.
+    #[inline(always)]
+    pub fn pop_lsb(&mut self) -> usize {
+        if self.0[0] != 0 {
+            let sq = self.0[0].trailing_zeros() as usize;
+            self.0[0] &= self.0[0] - 1;
+            sq
+        } else {
+            let sq = 64 + self.0[1].trailing_zeros() as usize;
+            self.0[1] &= self.0[1] - 1;
+            sq
+        }
+    }
+}
+
+/// The squares of a bitboard, lowest first, from its `IntoIterator` impl.
+pub struct Squares(Bitboard);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<usize> {
+        if self.0.is_empty() { None } else { Some(self.0.pop_lsb()) }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = self.0.count_ones() as usize;
+        (count, Some(count))
+    }
+}
+
+impl ExactSizeIterator for Squares {}
+
+impl IntoIterator for Bitboard {
+    type Item = usize;
+    type IntoIter = Squares;
+
+    #[inline(always)]
+    fn into_iter(self) -> Squares {
+        Squares(self)
+    }
+}
+
+impl BitAnd for Bitboard {
+    type Output = Bitboard;
+
+    #[inline(always)]
+    fn bitand(self, other: Bitboard) -> Bitboard {
+        Bitboard([self.0[0] & other.0[0], self.0[1] & other.0[1]])
+    }
+}
+
+impl BitOr for Bitboard {
+    type Output = Bitboard;
+
+    #[inline(always)]
+    fn bitor(self, other: Bitboard) -> Bitboard {
+        Bitboard([self.0[0] | other.0[0], self.0[1] | other.0[1]])
+    }
+}
+
+impl BitXor for Bitboard {
+    type Output = Bitboard;
+
+    #[inline(always)]
+    fn bitxor(self, other: Bitboard) -> Bitboard {
+        Bitboard([self.0[0] ^ other.0[0], self.0[1] ^ other.0[1]])
+    }
+}
+
+impl Not for Bitboard {
+    type Output = Bitboard;
 
-const MAX_HISTORY: usize = 256;
+    #[inline(always)]
+    fn not(self) -> Bitboard {
+        Bitboard([!self.0[0], !self.0[1]])
+    }
+}
+
+impl BitAndAssign for Bitboard {
+    #[inline(always)]
+    fn bitand_assign(&mut self, other: Bitboard) {
+        *self = *self & other;
+    }
+}
+
+impl BitOrAssign for Bitboard {
+    #[inline(always)]
+    fn bitor_assign(&mut self, other: Bitboard) {
+        *self = *self | other;
+    }
+}
+
+impl BitXorAssign for Bitboard {
+    #[inline(always)]
+    fn bitxor_assign(&mut self, other: Bitboard) {
+        *self = *self ^ other;
+    }
+}
+
+impl Shl<usize> for Bitboard {
+    type Output = Bitboard;
+
+    fn shl(self, n: usize) -> Bitboard {
+        Bitboard::from_u128(self.to_u128() << n)
+    }
+}
+
+impl Shr<usize> for Bitboard {
+    type Output = Bitboard;
+
+    fn shr(self, n: usize) -> Bitboard {
+        Bitboard::from_u128(self.to_u128() >> n)
+    }
+}
+
+/// The number of plies a new board has room for in its history before it has to grow,
+/// which is enough for most games and searches.
+const HISTORY_CAPACITY: usize = 256;
 
 // --- Pre-computed Masks ---
 pub const SQUARE_MASKS: [Bitboard; 90] = {
-    let mut masks = [0; 90];
+    let mut masks = [Bitboard::EMPTY; 90];
     let mut i = 0;
     while i < 90 {
-        masks[i] = 1 << i;
+        masks[i] = Bitboard::from_u128(1 << i);
         i += 1;
     }
     masks
 };
 
 pub const RANK_MASKS: [Bitboard; 10] = {
-    let mut masks = [0; 10];
+    let mut masks = [Bitboard::EMPTY; 10];
     let mut i = 0;
     while i < 10 {
-        masks[i] = 0x1FF << (i * 9);
+        masks[i] = Bitboard::from_u128(0x1FF << (i * 9));
+        i += 1;
+    }
+    masks
+};
+
+pub const FILE_MASKS: [Bitboard; 9] = {
+    let mut masks = [Bitboard::EMPTY; 9];
+    let mut i = 0;
+    while i < 9 {
+        let mut bits: u128 = 0;
+        let mut r = 0;
+        while r < 10 {
+            bits |= 1 << (r * 9 + i);
+            r += 1;
+        }
+        masks[i] = Bitboard::from_u128(bits);
         i += 1;
     }
     masks
@@ -37,82 +221,292 @@ pub enum MoveGenType {
     Quiets,
 }
 
+/// What is needed to take back a move, saved for every move made on a board.
+#[derive(Debug, Clone, Copy)]
+pub struct StateInfo {
+    pub mv: Move,
+    pub captured: Piece,
+    /// The halfmove clock before the move.
+    pub halfmove_clock: u32,
+    /// The hash key before the move.
+    pub hash_key: u64,
+}
+
+/// The letters of a FEN. `Board::from_fen` reads both dialects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FenDialect {
+    /// The letters of chess: `w` for Red to move, `n` for horses and `b` for bishops.
+    #[default]
+    Chess,
+    /// The letters of the Xiangqi names, as many Xiangqi tools write them: `r` for Red
+    /// to move, `h` for horses and `e` for elephants.
+    Xiangqi,
+}
+
+impl FenDialect {
+    fn piece_char(self, piece: Piece) -> char {
+        let ch = piece.to_fen_char();
+        if self == FenDialect::Chess {
+            return ch;
+        }
+        let xiangqi_ch = match ch.to_ascii_lowercase() {
+            'n' => 'h',
+            'b' => 'e',
+            other => other,
+        };
+        if ch.is_ascii_uppercase() { xiangqi_ch.to_ascii_uppercase() } else { xiangqi_ch }
+    }
+
+    fn red_char(self) -> char {
+        match self {
+            FenDialect::Chess => 'w',
+            FenDialect::Xiangqi => 'r',
+        }
+    }
+}
+
+/// How a repetition of positions ends, see `Board::repetition_outcome`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepetitionOutcome {
+    Draw,
+    /// The player gave check with each of its moves, and loses.
+    PerpetualCheck(Player),
+    /// The player chased the same piece with each of its moves, checks aside, and loses.
+    PerpetualChase(Player),
+}
+
+impl RepetitionOutcome {
+    /// The player who loses, if not a draw.
+    pub fn loser(self) -> Option<Player> {
+        match self {
+            RepetitionOutcome::Draw => None,
+            RepetitionOutcome::PerpetualCheck(player) | RepetitionOutcome::PerpetualChase(player) => Some(player),
+        }
+    }
+}
+
 /// Represents the state of the Xiangqi board at any point in time.
 #[derive(Debug, Clone)]
 pub struct Board {
     pub piece_bitboards: [Bitboard; 14],
     pub color_bitboards: [Bitboard; 2],
+    /// The number of pieces of each kind, indexed like `piece_bitboards`.
+    pub piece_counts: [u8; 14],
     pub board: [Piece; 90],
     pub player_to_move: Player,
     pub hash_key: u64,
     pub mirrored_hash_key: u64,
-    pub history: [u64; MAX_HISTORY],
+    /// The hash key of every position since the board was set up, the current one at
+    /// `history_ply`. Grows as needed, so games and searches of any length fit.
+    pub history: Vec<u64>,
     pub history_ply: usize,
-    pub material_score: i32, // Score for material balance
-    pub mg_pst_score: i32,   // Midgame score from piece-square tables
-    pub eg_pst_score: i32,   // Endgame score from piece-square tables
+    /// Plies since the last capture, for the move-count draw rules.
+    pub halfmove_clock: u32,
+    /// Starts at 1 and goes up after every Black move.
+    pub fullmove_number: u32,
+    /// The move made at each history ply, for `undo_move`.
+    states: Vec<StateInfo>,
+    pub psq_score: Score, // Material and piece-square table score, midgame and endgame
+    pub phase: i32,       // Game phase, see `evaluate::OPENING_PHASE`
 }
 
 impl Board {
     pub fn new() -> Self {
         Self {
-            piece_bitboards: [0; 14],
-            color_bitboards: [0; 2],
+            piece_bitboards: [Bitboard::EMPTY; 14],
+            color_bitboards: [Bitboard::EMPTY; 2],
+            piece_counts: [0; 14],
             board: [Piece::Empty; 90],
             player_to_move: Player::Red,
             hash_key: 0,
             mirrored_hash_key: 0,
-            history: [0; MAX_HISTORY],
+            history: Self::new_history(),
             history_ply: 0,
-            material_score: 0,
-            mg_pst_score: 0,
-            eg_pst_score: 0,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            states: Vec::with_capacity(HISTORY_CAPACITY),
+            psq_score: Score::ZERO,
+            phase: 0,
         }
     }
 
+    /// The history of a new board: one slot for the current position.
+    fn new_history() -> Vec<u64> {
+        let mut history = Vec::with_capacity(HISTORY_CAPACITY + 1);
+        history.push(0);
+        history
+    }
+
+    /// Sets up a position from a trusted FEN, such as a constant. Panics on an invalid
+    /// one; use `try_from_fen` for FENs from users or other programs.
     pub fn from_fen(fen: &str) -> Self {
+        Self::try_from_fen(fen).unwrap_or_else(|e| panic!("Invalid FEN '{}': {}", fen, e))
+    }
+
+    /// Sets up a position from a FEN in either `FenDialect`, with or without the unused
+    /// castling and en passant fields and the counters. Fails on a malformed FEN and
+    /// on a position `validate` rejects.
+    pub fn try_from_fen(fen: &str) -> Result<Self, String> {
         let mut board = Board::new();
         let mut parts = fen.split_whitespace();
 
-        let layout = parts.next().unwrap();
-        let mut rank = 0;
-        let mut file = 0;
-        for ch in layout.chars() {
-            if ch == '/' {
-                rank += 1;
-                file = 0;
-            } else if let Some(digit) = ch.to_digit(10) {
-                file += digit as usize;
-            } else {
-                let piece = Piece::from_fen_char(ch).unwrap();
-                let sq = rank * 9 + file;
-                board.set_piece(sq, piece);
-                file += 1;
+        let layout = parts.next().ok_or("empty FEN")?;
+        let ranks: Vec<&str> = layout.split('/').collect();
+        if ranks.len() != 10 {
+            return Err(format!("{} ranks instead of 10", ranks.len()));
+        }
+        for (rank, text) in ranks.iter().enumerate() {
+            let mut file = 0;
+            for ch in text.chars() {
+                if let Some(digit) = ch.to_digit(10).filter(|&digit| digit > 0) {
+                    file += digit as usize;
+                } else {
+                    let piece = Piece::from_fen_char(ch).ok_or_else(|| format!("unknown piece '{}'", ch))?;
+                    if file < 9 {
+                        board.set_piece(rank * 9 + file, piece);
+                    }
+                    file += 1;
+                }
+                if file > 9 {
+                    return Err(format!("rank {} is longer than 9 files", 9 - rank));
+                }
+            }
+            if file < 9 {
+                return Err(format!("rank {} is shorter than 9 files", 9 - rank));
             }
         }
 
-        let player = parts.next().unwrap();
-        board.player_to_move = if player == "w" {
-            Player::Red
-        } else {
-            Player::Black
+        board.player_to_move = match parts.next() {
+            Some("w" | "r") => Player::Red,
+            Some("b") => Player::Black,
+            Some(player) => return Err(format!("unknown side to move '{}'", player)),
+            None => return Err("no side to move".to_string()),
         };
-        if board.player_to_move == Player::Black {
-            board.hash_key ^= zobrist::ZOBRIST_PLAYER;
-            board.mirrored_hash_key ^= zobrist::ZOBRIST_PLAYER;
+
+        // Skip the unused castling and en passant fields, whichever are there; the
+        // counters are optional.
+        let mut counters = parts.filter(|part| *part != "-");
+        board.halfmove_clock = counters.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+        board.fullmove_number = counters.next().and_then(|s| s.parse().ok()).unwrap_or(1);
+
+        board.finish_setup();
+        board.validate()?;
+        Ok(board)
+    }
+
+    /// Checks that this is a position a game could reach: one king per side in its
+    /// palace, no more pieces of a kind than a side starts with, guards, bishops and pawns
+    /// on squares they can reach, kings not facing each other, and the bitboards agreeing
+    /// with the board array.
+    pub fn validate(&self) -> Result<(), String> {
+        const MAX_COUNTS: [u32; 8] = [0, 1, 2, 2, 2, 2, 2, 5]; // Indexed by `abs_val`
+        const GUARD_SQUARES: [(usize, usize); 5] = [(0, 3), (0, 5), (1, 4), (2, 3), (2, 5)]; // As (rank from the own side, file)
+        const BISHOP_SQUARES: [(usize, usize); 7] = [(0, 2), (0, 6), (2, 0), (2, 4), (2, 8), (4, 2), (4, 6)]; // As (rank from the own side, file)
+
+        let mut color_bitboards = [Bitboard::EMPTY; 2];
+        for (sq, &piece) in self.board.iter().enumerate() {
+            let mask = SQUARE_MASKS[sq];
+            for (idx, &bb) in self.piece_bitboards.iter().enumerate() {
+                if (bb & mask).is_empty() == (piece.get_bb_index() == Some(idx)) {
+                    return Err(format!("piece bitboard {} disagrees with the board at square {}", idx, sq));
+                }
+            }
+            let Some(player) = piece.player() else { continue };
+            color_bitboards[player.get_bb_idx()] |= mask;
+
+            let square = Square::from_index(sq).unwrap();
+            let (rank, file) = (square.rank().relative_to(player), square.file().index());
+            let on_legal_square = match piece.abs_val() {
+                1 => square.in_palace(player),
+                2 => GUARD_SQUARES.contains(&(rank, file)),
+                3 => BISHOP_SQUARES.contains(&(rank, file)),
+                7 => square.across_river(player) || (rank >= 3 && file % 2 == 0),
+                _ => true,
+            };
+            if !on_legal_square {
+                return Err(format!("{:?} on an unreachable square {}", piece, square));
+            }
+        }
+        if color_bitboards != self.color_bitboards {
+            return Err("color bitboards disagree with the board".to_string());
+        }
+
+        for (idx, &bb) in self.piece_bitboards.iter().enumerate() {
+            let piece = Piece::from_abs(if idx < 7 { idx as i8 + 1 } else { 6 - idx as i8 });
+            let count = bb.count_ones();
+            if self.piece_counts[idx] as u32 != count {
+                return Err(format!("piece count of {:?} disagrees with its bitboard", piece));
+            }
+            if piece.abs_val() == 1 && count != 1 {
+                return Err(format!("{} {:?} pieces instead of one", count, piece));
+            }
+            if count > MAX_COUNTS[piece.abs_val() as usize] {
+                return Err(format!("too many {:?} pieces: {}", piece, count));
+            }
+        }
+
+        let red_king = self.piece_bitboards[Piece::RKing.get_bb_index().unwrap()];
+        let black_king = self.piece_bitboards[Piece::BKing.get_bb_index().unwrap()];
+        let red_king_sq = red_king.trailing_zeros() as usize;
+        if !(crate::move_generator::get_rook_moves_bb(red_king_sq, self.occupied_bitboard()) & black_king).is_empty() {
+            return Err("the kings face each other".to_string());
+        }
+        Ok(())
+    }
+
+    /// Completes a board whose pieces and side to move have been set: adds the side to
+    /// move to the hash keys, calculates the evaluation scores and starts the history.
+    fn finish_setup(&mut self) {
+        if self.player_to_move == Player::Black {
+            self.hash_key ^= zobrist::ZOBRIST_PLAYER;
+            self.mirrored_hash_key ^= zobrist::ZOBRIST_PLAYER;
         }
 
         // Calculate and store the initial evaluation scores
-        let (material, mg_pst, eg_pst) = crate::evaluate::calculate_full_scores(&board);
-        board.material_score = material;
-        board.mg_pst_score = mg_pst;
-        board.eg_pst_score = eg_pst;
+        self.psq_score = crate::evaluate::calculate_psq_score(self);
+        self.phase = crate::evaluate::calculate_phase(self);
+
+        self.history[self.history_ply] = self.hash_key;
+    }
+
+    /// The position mirrored left to right, without the move history. Its hash key is
+    /// this position's mirrored hash key.
+    pub fn mirror_horizontal(&self) -> Board {
+        self.transformed(self.player_to_move, |sq, piece| ((sq / 9) * 9 + 8 - sq % 9, piece))
+    }
+
+    /// The position with the colors swapped, without the move history: the board turned
+    /// upside down, every piece given to the other side, and the other side to move.
+    /// Both positions are the same for the side to move.
+    pub fn flip_colors(&self) -> Board {
+        self.transformed(self.player_to_move.opponent(), |sq, piece| {
+            ((9 - sq / 9) * 9 + sq % 9, Piece::from_abs(-(piece as i8)))
+        })
+    }
 
-        board.history[board.history_ply] = board.hash_key;
+    /// A new board with every piece moved by `transform`, which maps a square and piece
+    /// to the new square and piece.
+    fn transformed(&self, player: Player, transform: impl Fn(usize, Piece) -> (usize, Piece)) -> Board {
+        let mut board = Board::new();
+        for (sq, &piece) in self.board.iter().enumerate() {
+            if piece != Piece::Empty {
+                let (sq, piece) = transform(sq, piece);
+                board.set_piece(sq, piece);
+            }
+        }
+        board.player_to_move = player;
+        board.halfmove_clock = self.halfmove_clock;
+        board.fullmove_number = self.fullmove_number;
+        board.finish_setup();
         board
     }
 
     pub fn to_fen(&self) -> String {
+        self.to_fen_in(FenDialect::Chess)
+    }
+
+    /// The position as a FEN in `dialect`.
+    pub fn to_fen_in(&self, dialect: FenDialect) -> String {
         let mut fen = String::with_capacity(128);
         for r in 0..10 {
             let mut empty_count = 0;
@@ -125,7 +519,7 @@ impl Board {
                         fen.push_str(&empty_count.to_string());
                         empty_count = 0;
                     }
-                    fen.push(piece.to_fen_char());
+                    fen.push(dialect.piece_char(piece));
                 }
             }
             if empty_count > 0 {
@@ -138,10 +532,10 @@ impl Board {
 
         // Active color
         fen.push(' ');
-        fen.push(if self.player_to_move == Player::Red { 'w' } else { 'b' });
+        fen.push(if self.player_to_move == Player::Red { dialect.red_char() } else { 'b' });
 
-        // Other fields (can be placeholders as they are not used by this engine)
-        fen.push_str(" - - 0 1");
+        // Castling and en passant do not exist in Xiangqi
+        fen.push_str(&format!(" - - {} {}", self.halfmove_clock, self.fullmove_number));
 
         fen
     }
@@ -155,21 +549,193 @@ impl Board {
         self.board[sq] = piece;
         self.piece_bitboards[piece.get_bb_index().unwrap()] |= mask;
         self.color_bitboards[player.get_bb_idx()] |= mask;
+        self.piece_counts[piece.get_bb_index().unwrap()] += 1;
         self.hash_key ^= zobrist::ZOBRIST_KEYS[piece.get_zobrist_idx().unwrap()][r][c];
         let mirrored_c = 8 - c;
         self.mirrored_hash_key ^=
             zobrist::ZOBRIST_KEYS[piece.get_zobrist_idx().unwrap()][r][mirrored_c];
     }
 
+    /// Every piece on the board with its square, from Black's back rank down.
+    pub fn pieces(&self) -> impl Iterator<Item = (Square, Piece)> + '_ {
+        self.occupied_bitboard().into_iter().map(|sq| (Square::from_index(sq).unwrap(), self.board[sq]))
+    }
+
+    /// The piece on `sq`, `Piece::Empty` if there is none.
+    pub fn piece_at(&self, sq: Square) -> Piece {
+        self.board[sq.index()]
+    }
+
+    /// The squares whose contents differ between this board and `other`: for a move, its
+    /// from and to squares, so a user interface can redraw or animate just those.
+    pub fn diff(&self, other: &Board) -> Bitboard {
+        self.piece_bitboards.iter().zip(&other.piece_bitboards).fold(Bitboard::EMPTY, |changed, (&a, &b)| changed | (a ^ b))
+    }
+
     pub fn occupied_bitboard(&self) -> Bitboard {
         self.color_bitboards[0] | self.color_bitboards[1]
     }
 
-    pub fn move_piece(&mut self, mv: crate::r#move::Move) -> Piece {
+    /// The number of pieces of the kind of `piece` on the board.
+    pub fn piece_count(&self, piece: Piece) -> u32 {
+        piece.get_bb_index().map_or(0, |idx| self.piece_counts[idx] as u32)
+    }
+
+    /// The number of rooks, horses and cannons of `player`.
+    pub fn major_piece_count(&self, player: Player) -> u32 {
+        [Piece::RRook, Piece::RHorse, Piece::RCannon].iter().map(|&piece| self.piece_count(Piece::from_abs(piece as i8 * player as i8))).sum()
+    }
+
+    /// The last move made, if any.
+    pub fn last_move(&self) -> Option<Move> {
+        self.last_state().map(|state| state.mv)
+    }
+
+    /// How many times the current position occurred earlier in a game whose earlier
+    /// positions have the hash keys `game_history`, oldest first. Unlike the search's
+    /// repetition check, which only sees the positions in `history`, this covers the whole
+    /// game, however long, so it is what the game's repetition rules should use.
+    pub fn repetition_count(&self, game_history: &[u64]) -> usize {
+        game_history.iter().filter(|&&hash| hash == self.hash_key).count()
+    }
+
+    /// The history ply of the first of three occurrences of the current position among
+    /// the positions in `history`, if it occurs for the third time.
+    pub fn third_repetition_start(&self) -> Option<usize> {
+        if self.history_ply < 4 {
+            return None;
+        }
+        (0..self.history_ply - 1)
+            .rev()
+            .step_by(2)
+            .filter(|&i| self.history[i] == self.hash_key)
+            .nth(1)
+    }
+
+    /// Whether the game is drawn by the move rule, after `plies` plies without a capture,
+    /// unless the side to move is checkmated. See `Rules::move_rule_plies`.
+    pub fn is_move_rule_draw(&mut self, plies: u32) -> bool {
+        if self.halfmove_clock < plies {
+            return false;
+        }
+        if !crate::move_generator::is_king_in_check(self, self.player_to_move) {
+            return true;
+        }
+        let mut legal_moves = MoveList::new();
+        self.generate_legal_moves(&mut legal_moves);
+        !legal_moves.is_empty()
+    }
+
+    /// How the repetition of the positions since history ply `start` ends under Asian
+    /// rules: a side that gave check with each of its moves loses, and failing that, so
+    /// does a side that chased the same piece with each of its moves that was not a check.
+    /// Takes the moves back and makes them again to find the checks and chases; a null
+    /// move in between makes it a draw.
+    pub fn repetition_outcome(&mut self, start: usize) -> RepetitionOutcome {
+        let moves: Vec<StateInfo> = self.states[start..].to_vec();
+        if moves.iter().any(|state| state.mv == Move::new(0, 0, None)) {
+            return RepetitionOutcome::Draw;
+        }
+        for _ in &moves {
+            self.undo_move();
+        }
+        let mut checked_throughout = [true, true];
+        // The opponent's pieces each side chased with every move so far, following them
+        // as they move.
+        let mut chased = [!Bitboard::EMPTY; 2];
+        for state in &moves {
+            let player = self.player_to_move;
+            let side = player.get_bb_idx();
+            let (from_sq, to_sq) = (state.mv.from_sq(), state.mv.to_sq());
+            let attacked_before = self.attacked_by(from_sq);
+            self.make_move(state.mv);
+            if !(chased[1 - side] & SQUARE_MASKS[from_sq]).is_empty() {
+                chased[1 - side] = (chased[1 - side] & !SQUARE_MASKS[from_sq]) | SQUARE_MASKS[to_sq];
+            }
+            if !crate::move_generator::is_king_in_check(self, player.opponent()) {
+                checked_throughout[side] = false;
+                chased[side] &= self.chased_by(to_sq, attacked_before);
+            }
+        }
+        match (checked_throughout, chased.map(Bitboard::is_empty)) {
+            ([true, false], _) => RepetitionOutcome::PerpetualCheck(Player::Red),
+            ([false, true], _) => RepetitionOutcome::PerpetualCheck(Player::Black),
+            ([false, false], [false, true]) => RepetitionOutcome::PerpetualChase(Player::Red),
+            ([false, false], [true, false]) => RepetitionOutcome::PerpetualChase(Player::Black),
+            _ => RepetitionOutcome::Draw,
+        }
+    }
+
+    /// The opponent's pieces, kings aside, that the piece on `sq` attacks.
+    fn attacked_by(&self, sq: usize) -> Bitboard {
+        let player = self.board[sq].player().expect("no piece on the square");
+        let opponent = player.opponent();
+        let king = Piece::from_abs(Piece::RKing as i8 * opponent as i8);
+        let targets = self.color_bitboards[opponent.get_bb_idx()] & !self.piece_bitboards[king.get_bb_index().unwrap()];
+        let mut attacked = Bitboard::EMPTY;
+        for target in targets {
+            if !(crate::move_generator::attackers_to(self, target, player) & SQUARE_MASKS[sq]).is_empty() {
+                attacked |= SQUARE_MASKS[target];
+            }
+        }
+        attacked
+    }
+
+    /// The opponent's pieces that the piece just moved to `sq` chases: those it attacks
+    /// and did not before, as `attacked_before` tells, and could take without losing out,
+    /// because they are unprotected or rank above it. Kings and pawns may attack freely,
+    /// pawns that have not crossed the river may be attacked freely, and attacking a
+    /// piece that can take back is an offer to exchange rather than a chase.
+    fn chased_by(&mut self, sq: usize, attacked_before: Bitboard) -> Bitboard {
+        let attacker = self.board[sq];
+        if matches!(attacker.abs_val(), 1 | 7) {
+            return Bitboard::EMPTY;
+        }
+        let player = attacker.player().unwrap();
+        let opponent = player.opponent();
+        let mut chased = Bitboard::EMPTY;
+        // The captures are tried as if it were the attacker's move again.
+        self.make_null_move();
+        for target in self.attacked_by(sq) & !attacked_before {
+            let victim = self.board[target];
+            let target_square = Square::from_index(target).unwrap();
+            if victim.abs_val() == Piece::RPawn as u8 && !target_square.across_river(opponent) {
+                continue;
+            }
+            if !(crate::move_generator::attackers_to(self, sq, opponent) & SQUARE_MASKS[target]).is_empty() {
+                continue;
+            }
+            let capture = Move::new(sq, target, Some(victim));
+            if !self.is_legal(capture) {
+                continue;
+            }
+            self.make_move(capture);
+            let protected = !crate::move_generator::attackers_to(self, target, opponent).is_empty();
+            self.undo_move();
+            if !protected || chase_rank(victim) > chase_rank(attacker) {
+                chased |= SQUARE_MASKS[target];
+            }
+        }
+        self.unmake_null_move();
+        chased
+    }
+
+    fn last_state(&self) -> Option<StateInfo> {
+        self.states.last().copied()
+    }
+
+    /// Makes a move, which `undo_move` takes back.
+    pub fn make_move(&mut self, mv: Move) {
         let from_sq = mv.from_sq();
         let to_sq = mv.to_sq();
         let moving_piece = self.board[from_sq];
         let captured_piece = self.board[to_sq];
+        self.states.push(StateInfo {
+            mv,
+            captured: captured_piece,
+            halfmove_clock: self.halfmove_clock,
+            hash_key: self.hash_key,
+        });
 
         self.update_scores_for_move(moving_piece, captured_piece, from_sq, to_sq);
         self.update_board_and_bitboards_for_move(moving_piece, captured_piece, from_sq, to_sq);
@@ -178,47 +744,116 @@ impl Board {
         self.hash_key ^= zobrist::ZOBRIST_PLAYER;
         self.mirrored_hash_key ^= zobrist::ZOBRIST_PLAYER;
 
+        self.halfmove_clock = if captured_piece == Piece::Empty { self.halfmove_clock + 1 } else { 0 };
+        if self.player_to_move == Player::Red {
+            self.fullmove_number += 1;
+        }
+
         self.history_ply += 1;
-        self.history[self.history_ply] = self.hash_key;
+        self.history.push(self.hash_key);
 
-        captured_piece
+        #[cfg(debug_assertions)]
+        self.verify_incremental_state();
     }
 
-    pub fn unmove_piece(&mut self, mv: crate::r#move::Move, captured_piece: Piece) {
+    /// Takes back the last move made, from the state saved when it was made, and returns
+    /// it, or `None` at the start of the history.
+    pub fn undo_move(&mut self) -> Option<Move> {
+        let state = self.states.pop()?;
         self.history_ply -= 1;
-        let from_sq = mv.from_sq();
-        let to_sq = mv.to_sq();
+        self.history.pop();
+        let from_sq = state.mv.from_sq();
+        let to_sq = state.mv.to_sq();
         let moving_piece = self.board[to_sq];
+        let captured_piece = state.captured;
 
         self.player_to_move = self.player_to_move.opponent();
         self.hash_key ^= zobrist::ZOBRIST_PLAYER;
         self.mirrored_hash_key ^= zobrist::ZOBRIST_PLAYER;
 
+        self.halfmove_clock = state.halfmove_clock;
+        if self.player_to_move == Player::Black {
+            self.fullmove_number -= 1;
+        }
+
         self.update_scores_for_unmove(moving_piece, captured_piece, from_sq, to_sq);
         self.update_board_and_bitboards_for_unmove(moving_piece, captured_piece, from_sq, to_sq);
         self.update_hash_for_unmove(moving_piece, captured_piece, from_sq, to_sq);
-    }
 
-    fn update_scores_for_move(&mut self, moving_piece: Piece, captured_piece: Piece, from_sq: usize, to_sq: usize) {
-        let (mg_from, eg_from) = crate::evaluate::get_pst_scores(moving_piece, from_sq);
-        self.mg_pst_score -= mg_from;
-        self.eg_pst_score -= eg_from;
+        #[cfg(debug_assertions)]
+        self.verify_incremental_state();
+        Some(state.mv)
+    }
 
-        if captured_piece != Piece::Empty {
-            let captured_value = crate::evaluate::MATERIAL_VALUES[captured_piece.abs_val() as usize];
-            if captured_piece.player().unwrap() == Player::Black {
-                self.material_score += captured_value;
-            } else {
-                self.material_score -= captured_value;
+    /// Recalculates the hash keys and evaluation scores from scratch and panics if the
+    /// incrementally updated ones differ, so that a bug in the updates shows up in the
+    /// first test that makes the move.
+    #[cfg(debug_assertions)]
+    fn verify_incremental_state(&self) {
+        let (mut hash_key, mut mirrored_hash_key) = (0, 0);
+        for (sq, &piece) in self.board.iter().enumerate() {
+            if let Some(zobrist_idx) = piece.get_zobrist_idx() {
+                hash_key ^= zobrist::ZOBRIST_KEYS[zobrist_idx][sq / 9][sq % 9];
+                mirrored_hash_key ^= zobrist::ZOBRIST_KEYS[zobrist_idx][sq / 9][8 - sq % 9];
             }
-            let (mg_cap, eg_cap) = crate::evaluate::get_pst_scores(captured_piece, to_sq);
-            self.mg_pst_score -= mg_cap;
-            self.eg_pst_score -= eg_cap;
+        }
+        if self.player_to_move == Player::Black {
+            hash_key ^= zobrist::ZOBRIST_PLAYER;
+            mirrored_hash_key ^= zobrist::ZOBRIST_PLAYER;
+        }
+        assert_eq!(self.hash_key, hash_key, "incremental hash key is wrong in {}", self.to_fen());
+        assert_eq!(self.mirrored_hash_key, mirrored_hash_key, "incremental mirrored hash key is wrong in {}", self.to_fen());
+        assert_eq!(self.psq_score, crate::evaluate::calculate_psq_score(self), "incremental psq score is wrong in {}", self.to_fen());
+        assert_eq!(self.phase, crate::evaluate::calculate_phase(self), "incremental phase is wrong in {}", self.to_fen());
+        for (idx, &bb) in self.piece_bitboards.iter().enumerate() {
+            assert_eq!(self.piece_counts[idx] as u32, bb.count_ones(), "incremental piece count {} is wrong in {}", idx, self.to_fen());
+        }
+    }
+
+    /// Passes the turn without moving a piece, for null move pruning. Taken back by
+    /// `unmake_null_move`, not by `undo_move`.
+    pub fn make_null_move(&mut self) {
+        self.states.push(StateInfo {
+            mv: Move::new(0, 0, None),
+            captured: Piece::Empty,
+            halfmove_clock: self.halfmove_clock,
+            hash_key: self.hash_key,
+        });
+        self.player_to_move = self.player_to_move.opponent();
+        self.hash_key ^= zobrist::ZOBRIST_PLAYER;
+        self.mirrored_hash_key ^= zobrist::ZOBRIST_PLAYER;
+
+        self.halfmove_clock += 1;
+        if self.player_to_move == Player::Red {
+            self.fullmove_number += 1;
         }
 
-        let (mg_to, eg_to) = crate::evaluate::get_pst_scores(moving_piece, to_sq);
-        self.mg_pst_score += mg_to;
-        self.eg_pst_score += eg_to;
+        self.history_ply += 1;
+        self.history.push(self.hash_key);
+    }
+
+    /// Takes back a move made with `make_null_move`.
+    pub fn unmake_null_move(&mut self) {
+        self.history_ply -= 1;
+        self.history.pop();
+        let state = self.states.pop().unwrap();
+        self.player_to_move = self.player_to_move.opponent();
+        self.hash_key ^= zobrist::ZOBRIST_PLAYER;
+        self.mirrored_hash_key ^= zobrist::ZOBRIST_PLAYER;
+
+        self.halfmove_clock = state.halfmove_clock;
+        if self.player_to_move == Player::Black {
+            self.fullmove_number -= 1;
+        }
+    }
+
+    fn update_scores_for_move(&mut self, moving_piece: Piece, captured_piece: Piece, from_sq: usize, to_sq: usize) {
+        self.psq_score -= crate::evaluate::psq_score(moving_piece, from_sq);
+        if captured_piece != Piece::Empty {
+            self.phase -= crate::evaluate::phase_value(captured_piece);
+            self.psq_score -= crate::evaluate::psq_score(captured_piece, to_sq);
+        }
+        self.psq_score += crate::evaluate::psq_score(moving_piece, to_sq);
     }
 
     fn update_board_and_bitboards_for_move(&mut self, moving_piece: Piece, captured_piece: Piece, from_sq: usize, to_sq: usize) {
@@ -233,6 +868,7 @@ impl Board {
             let captured_player = captured_piece.player().unwrap();
             self.piece_bitboards[captured_piece.get_bb_index().unwrap()] &= !SQUARE_MASKS[to_sq];
             self.color_bitboards[captured_player.get_bb_idx()] &= !SQUARE_MASKS[to_sq];
+            self.piece_counts[captured_piece.get_bb_index().unwrap()] -= 1;
         }
     }
 
@@ -259,24 +895,11 @@ impl Board {
     }
 
     fn update_scores_for_unmove(&mut self, moving_piece: Piece, captured_piece: Piece, from_sq: usize, to_sq: usize) {
-        let (mg_to, eg_to) = crate::evaluate::get_pst_scores(moving_piece, to_sq);
-        self.mg_pst_score -= mg_to;
-        self.eg_pst_score -= eg_to;
-
-        let (mg_from, eg_from) = crate::evaluate::get_pst_scores(moving_piece, from_sq);
-        self.mg_pst_score += mg_from;
-        self.eg_pst_score += eg_from;
-
+        self.psq_score -= crate::evaluate::psq_score(moving_piece, to_sq);
+        self.psq_score += crate::evaluate::psq_score(moving_piece, from_sq);
         if captured_piece != Piece::Empty {
-            let captured_value = crate::evaluate::MATERIAL_VALUES[captured_piece.abs_val() as usize];
-            if captured_piece.player().unwrap() == Player::Black {
-                self.material_score -= captured_value;
-            } else {
-                self.material_score += captured_value;
-            }
-            let (mg_cap, eg_cap) = crate::evaluate::get_pst_scores(captured_piece, to_sq);
-            self.mg_pst_score += mg_cap;
-            self.eg_pst_score += eg_cap;
+            self.phase += crate::evaluate::phase_value(captured_piece);
+            self.psq_score += crate::evaluate::psq_score(captured_piece, to_sq);
         }
     }
 
@@ -292,6 +915,7 @@ impl Board {
             let captured_player = captured_piece.player().unwrap();
             self.piece_bitboards[captured_piece.get_bb_index().unwrap()] |= SQUARE_MASKS[to_sq];
             self.color_bitboards[captured_player.get_bb_idx()] |= SQUARE_MASKS[to_sq];
+            self.piece_counts[captured_piece.get_bb_index().unwrap()] += 1;
         }
     }
 
@@ -340,14 +964,13 @@ impl Board {
         };
 
         for i in piece_start_idx..piece_end_idx {
-            let mut piece_bb = self.piece_bitboards[i];
-            if piece_bb == 0 {
+            let piece_bb = self.piece_bitboards[i];
+            if piece_bb.is_empty() {
                 continue;
             }
             let piece_type = self.board[piece_bb.trailing_zeros() as usize];
 
-            while piece_bb != 0 {
-                let from_sq = piece_bb.trailing_zeros() as usize;
+            for from_sq in piece_bb {
                 let moves_bb = self.get_piece_moves(piece_type, from_sq, occupied, player_idx);
 
                 match move_gen_type {
@@ -362,8 +985,6 @@ impl Board {
                         self.add_moves(moves, from_sq, moves_bb & !occupied, false);
                     }
                 }
-
-                piece_bb &= !SQUARE_MASKS[from_sq];
             }
         }
     }
@@ -373,81 +994,148 @@ impl Board {
             Piece::RKing | Piece::BKing => crate::move_generator::ATTACK_TABLES.king[from_sq],
             Piece::RGuard | Piece::BGuard => crate::move_generator::ATTACK_TABLES.guard[from_sq],
             Piece::RBishop => {
-                let mut moves_bb = 0;
+                let mut moves_bb = Bitboard::EMPTY;
                 let mut potential_moves = crate::move_generator::ATTACK_TABLES.bishop[from_sq];
                 potential_moves &= crate::move_generator::ATTACK_TABLES.red_half_mask;
-                while potential_moves != 0 {
-                    let to_sq = potential_moves.trailing_zeros() as usize;
+                for to_sq in potential_moves {
                     let leg_sq = crate::move_generator::ATTACK_TABLES.bishop_legs[from_sq][to_sq];
-                    if (occupied & SQUARE_MASKS[leg_sq]) == 0 { moves_bb |= SQUARE_MASKS[to_sq]; }
-                    potential_moves &= !SQUARE_MASKS[to_sq];
+                    if (occupied & SQUARE_MASKS[leg_sq]).is_empty() { moves_bb |= SQUARE_MASKS[to_sq]; }
                 }
                 moves_bb
             }
             Piece::BBishop => {
-                let mut moves_bb = 0;
+                let mut moves_bb = Bitboard::EMPTY;
                 let mut potential_moves = crate::move_generator::ATTACK_TABLES.bishop[from_sq];
                 potential_moves &= crate::move_generator::ATTACK_TABLES.black_half_mask;
-                while potential_moves != 0 {
-                    let to_sq = potential_moves.trailing_zeros() as usize;
+                for to_sq in potential_moves {
                     let leg_sq = crate::move_generator::ATTACK_TABLES.bishop_legs[from_sq][to_sq];
-                    if (occupied & SQUARE_MASKS[leg_sq]) == 0 { moves_bb |= SQUARE_MASKS[to_sq]; }
-                    potential_moves &= !SQUARE_MASKS[to_sq];
+                    if (occupied & SQUARE_MASKS[leg_sq]).is_empty() { moves_bb |= SQUARE_MASKS[to_sq]; }
                 }
                 moves_bb
             }
             Piece::RHorse | Piece::BHorse => {
-                let mut moves_bb = 0;
-                let mut potential_moves = crate::move_generator::ATTACK_TABLES.horse[from_sq];
-                while potential_moves != 0 {
-                    let to_sq = potential_moves.trailing_zeros() as usize;
+                let mut moves_bb = Bitboard::EMPTY;
+                for to_sq in crate::move_generator::ATTACK_TABLES.horse[from_sq] {
                     let leg_sq = crate::move_generator::ATTACK_TABLES.horse_legs[from_sq][to_sq];
-                    if (occupied & SQUARE_MASKS[leg_sq]) == 0 {
+                    if (occupied & SQUARE_MASKS[leg_sq]).is_empty() {
                         moves_bb |= SQUARE_MASKS[to_sq];
                     }
-                    potential_moves &= !SQUARE_MASKS[to_sq];
                 }
                 moves_bb
             }
             Piece::RPawn | Piece::BPawn => crate::move_generator::ATTACK_TABLES.pawn[player_idx][from_sq],
             Piece::RRook | Piece::BRook => crate::move_generator::get_rook_moves_bb(from_sq, occupied),
             Piece::RCannon | Piece::BCannon => crate::move_generator::get_cannon_moves_bb(from_sq, occupied),
-            _ => 0,
+            _ => Bitboard::EMPTY,
         }
     }
 
-    fn add_moves(&self, moves: &mut MoveList, from_sq: usize, mut moves_bb: Bitboard, is_capture: bool) {
-        while moves_bb != 0 {
-            let to_sq = moves_bb.trailing_zeros() as usize;
-            moves.add(crate::r#move::Move::new(
+    fn add_moves(&self, moves: &mut MoveList, from_sq: usize, moves_bb: Bitboard, is_capture: bool) {
+        for to_sq in moves_bb {
+            moves.add(Move::new(
                 from_sq,
                 to_sq,
                 if is_capture { Some(self.board[to_sq]) } else { None },
             ));
-            moves_bb &= !SQUARE_MASKS[to_sq];
         }
     }
 
+    /// Generates the legal moves. The checkers and the pieces pinned to the king are
+    /// worked out once, see `move_generator::legal_targets`, and king moves are checked
+    /// for attacks on their target square, so no move is tried on the board.
+    /// Without a king, there are none.
     pub fn generate_legal_moves(&mut self, moves: &mut MoveList) {
+        let player = self.player_to_move;
+        let king = if player == Player::Red { Piece::RKing } else { Piece::BKing };
+        let king_bb = self.piece_bitboards[king.get_bb_index().unwrap()];
+        if king_bb.is_empty() {
+            return;
+        }
+        let king_sq = king_bb.trailing_zeros() as usize;
+
         let mut pseudo_legal_moves = MoveList::new();
         self.generate_capture_moves(&mut pseudo_legal_moves);
         self.generate_quiet_moves(&mut pseudo_legal_moves);
 
-        for i in 0..pseudo_legal_moves.len() {
-            let mv = pseudo_legal_moves[i];
-            let captured = self.move_piece(mv);
-            if !crate::move_generator::is_king_in_check(self, self.player_to_move.opponent()) {
+        let targets = crate::move_generator::legal_targets(self, player);
+        for &mv in pseudo_legal_moves.as_slice() {
+            let legal = if mv.from_sq() == king_sq {
+                crate::move_generator::is_king_move_safe(self, player, king_sq, mv.to_sq())
+            } else {
+                !(targets[mv.from_sq()] & SQUARE_MASKS[mv.to_sq()]).is_empty()
+            };
+            if legal {
                 moves.add(mv);
             }
-            self.unmove_piece(mv, captured);
         }
     }
 
+    /// Whether the side to move's move generator could produce `mv` here, including the
+    /// piece it captures. The move may still leave the own king in check.
+    pub fn is_pseudo_legal(&self, mv: Move) -> bool {
+        let (from_sq, to_sq) = (mv.from_sq(), mv.to_sq());
+        if from_sq >= 90 || to_sq >= 90 {
+            return false;
+        }
+        let piece = self.board[from_sq];
+        let player_idx = self.player_to_move.get_bb_idx();
+        if piece.player() != Some(self.player_to_move)
+            || self.board[to_sq] != mv.captured()
+            || !(self.color_bitboards[player_idx] & SQUARE_MASKS[to_sq]).is_empty()
+        {
+            return false;
+        }
+        !(self.get_piece_moves(piece, from_sq, self.occupied_bitboard(), player_idx) & SQUARE_MASKS[to_sq]).is_empty()
+    }
+
+    /// Whether `mv` is pseudo-legal and doesn't leave the own king in check.
+    pub fn is_legal(&mut self, mv: Move) -> bool {
+        if !self.is_pseudo_legal(mv) {
+            return false;
+        }
+        self.make_move(mv);
+        let legal = !crate::move_generator::is_king_in_check(self, self.player_to_move.opponent());
+        self.undo_move();
+        legal
+    }
+
+    /// All pieces of `by_player` attacking `sq`.
+    pub fn attackers_to(&self, sq: Square, by_player: Player) -> Bitboard {
+        crate::move_generator::attackers_to(self, sq.index(), by_player)
+    }
+
+    /// The pieces giving check to the side to move, see `move_generator::checkers`.
+    pub fn checkers(&self) -> Bitboard {
+        crate::move_generator::checkers(self, self.player_to_move)
+    }
+
+    /// The pieces of `player` pinned to its king, see `move_generator::pinned`.
+    pub fn pinned(&self, player: Player) -> Bitboard {
+        crate::move_generator::pinned(self, player)
+    }
+
+    /// Parses a move in ICCS coordinates, such as `h2e2`, returning it only if it is one
+    /// of the legal moves in this position.
+    pub fn parse_uci_move(&mut self, text: &str) -> Option<Move> {
+        let (from, to) = crate::notation::iccs::parse_squares(text).ok()?;
+        let mut legal_moves = MoveList::new();
+        self.generate_legal_moves(&mut legal_moves);
+        legal_moves.as_slice().iter().copied().find(|mv| mv.from() == from && mv.to() == to)
+    }
+
     pub fn get_mirrored_hash(&self) -> u64 {
         self.mirrored_hash_key
     }
 }
 
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Draws the board with FEN letters, or with the alternate flag (`{:#}`) in Chinese
+/// characters, see `notation::chinese::format_board`.
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(
@@ -455,6 +1143,9 @@ impl fmt::Display for Board {
             "(Player: {:?}, Hash: {:016x})",
             self.player_to_move, self.hash_key
         )?;
+        if f.alternate() {
+            return f.write_str(&crate::notation::chinese::format_board(self));
+        }
         writeln!(f, "  +-------------------+")?;
         for r in 0..10 {
             write!(f, "{} | ", 9 - r)?;
@@ -471,16 +1162,13 @@ impl fmt::Display for Board {
 
 // --- Bitboard Helper Functions ---
 
-#[inline]
-pub fn popcount(bb: Bitboard) -> u32 {
-    bb.count_ones()
-}
-
-#[inline]
-pub fn get_lsb_index(bb: Bitboard) -> i32 {
-    if bb == 0 {
-        -1
-    } else {
-        bb.trailing_zeros() as i32
+/// How pieces rank for the chase rule: attacking a piece of a higher rank is a chase
+/// even when it is protected.
+fn chase_rank(piece: Piece) -> u8 {
+    match piece.abs_val() {
+        5 => 2,
+        4 | 6 => 1,
+        _ => 0,
     }
 }
+
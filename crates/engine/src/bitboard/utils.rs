@@ -0,0 +1,113 @@
+//! Bitboard helpers that know the board's geometry: shifting by a direction without
+//! wrapping around files, and the squares between and along two squares.
+//!
+//! A bitboard's squares are iterated with its `IntoIterator` impl, as square indices,
+//! or with `squares` as `Square`s.
+
+use super::{Bitboard, FILE_MASKS, RANK_MASKS};
+use crate::square::Square;
+
+/// Every square of the board.
+pub const BOARD_MASK: Bitboard = Bitboard::from_u128((1 << 90) - 1);
+
+/// A direction on the board, as seen from Red's side: north is towards Black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::North,
+        Direction::East,
+        Direction::South,
+        Direction::West,
+    ];
+}
+
+/// Every square of `bb` moved one step in `dir`. Squares moved off the board, or off
+/// the side across to the other edge, are dropped.
+pub const fn shift(bb: Bitboard, dir: Direction) -> Bitboard {
+    let bits = bb.to_u128();
+    let shifted = match dir {
+        Direction::North => bits >> 9,
+        Direction::South => bits << 9,
+        Direction::East => (bits & !FILE_MASKS[8].to_u128()) << 1,
+        Direction::West => (bits & !FILE_MASKS[0].to_u128()) >> 1,
+    };
+    Bitboard::from_u128(shifted & BOARD_MASK.to_u128())
+}
+
+/// The squares strictly between `a` and `b` if they share a rank or a file, otherwise
+/// none.
+#[inline(always)]
+pub fn between(a: usize, b: usize) -> Bitboard {
+    BETWEEN[a][b]
+}
+
+/// The whole rank or file through `a` and `b` if they share one, otherwise none.
+#[inline(always)]
+pub fn line(a: usize, b: usize) -> Bitboard {
+    LINE[a][b]
+}
+
+/// The squares of `bb`, from Black's back rank down.
+pub fn squares(bb: Bitboard) -> impl Iterator<Item = Square> {
+    bb.into_iter().map(|sq| Square::from_index(sq).unwrap())
+}
+
+static BETWEEN: [[Bitboard; 90]; 90] = square_pair_table(false);
+static LINE: [[Bitboard; 90]; 90] = square_pair_table(true);
+
+/// The table of `line_squares` for `whole_line`, otherwise of `between_squares`, for
+/// every pair of squares.
+const fn square_pair_table(whole_line: bool) -> [[Bitboard; 90]; 90] {
+    let mut table = [[Bitboard::EMPTY; 90]; 90];
+    let mut a = 0;
+    while a < 90 {
+        let mut b = 0;
+        while b < 90 {
+            table[a][b] = if whole_line {
+                line_squares(a, b)
+            } else {
+                between_squares(a, b)
+            };
+            b += 1;
+        }
+        a += 1;
+    }
+    table
+}
+
+const fn between_squares(a: usize, b: usize) -> Bitboard {
+    let (low, high) = if a < b { (a, b) } else { (b, a) };
+    let step = if low % 9 == high % 9 {
+        9
+    } else if low / 9 == high / 9 {
+        1
+    } else {
+        return Bitboard::EMPTY;
+    };
+    let mut bits = 0u128;
+    let mut sq = low + step;
+    while sq < high {
+        bits |= 1 << sq;
+        sq += step;
+    }
+    Bitboard::from_u128(bits)
+}
+
+const fn line_squares(a: usize, b: usize) -> Bitboard {
+    if a == b {
+        Bitboard::EMPTY
+    } else if a % 9 == b % 9 {
+        FILE_MASKS[a % 9]
+    } else if a / 9 == b / 9 {
+        RANK_MASKS[a / 9]
+    } else {
+        Bitboard::EMPTY
+    }
+}
@@ -1,34 +1,316 @@
 //! Configuration for the Xiangqi engine.
+//!
+//! A `Config` can be loaded from a TOML or JSON file with [`Config::from_file`].
+//! Fields left out of the file keep their default values. Evaluation bonuses are
+//! `Score`s, given as one value for the midgame and the endgame or as `[mg, eg]`, e.g.
+//!
+//! ```toml
+//! tempo_bonus = 15
+//! bonus_crossed_pawn = [5, 30]
+//! king_safety_weight = 120
+//! ```
 
+use crate::evaluate::Score;
+use crate::rules::Rules;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The default config file, looked for in the working directory.
+pub const CONFIG_FILE: &str = "engine_config.toml";
+
+/// Evaluation constants must lie within this many centipawns of zero.
+const MAX_EVAL_CONSTANT: i32 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     // Evaluation constants
-    pub bonus_bottom_cannon: i32,
-    pub bonus_palace_heart_horse: i32,
-    pub king_safety_penalty_per_guard: i32,
-    pub dynamic_bonus_attack_per_missing_defender: i32,
-    pub mobility_bonus_rook: i32,
-    pub mobility_bonus_horse: i32,
-    pub mobility_bonus_cannon: i32,
-    pub bonus_rook_on_open_file: i32,
-    pub bonus_rook_on_semi_open_file: i32,
+    pub bonus_bottom_cannon: Score,
+    pub bonus_palace_heart_horse: Score,
+    /// A cannon facing the enemy king with nothing, or a single piece of the palace, between.
+    pub bonus_palace_cannon: Score,
+    /// Two cannons on one file.
+    pub bonus_doubled_cannons: Score,
+    /// A cannon attacking an enemy rook through a screen.
+    pub bonus_cannon_attacks_rook: Score,
+    /// A cannon checking the enemy king with its own horse as the screen (马后炮).
+    pub bonus_horse_cannon_check: Score,
+    /// A horse and a cannon both attacking the enemy palace.
+    pub bonus_horse_cannon_palace_attack: Score,
+    /// Scales the king-safety penalty, in percent.
+    pub king_safety_weight: i32,
+    /// Per enemy rook and cannon when no friendly piece stands in front of the king
+    /// on its file.
+    pub penalty_exposed_king: Score,
+    /// For a king that keeps the enemy king off its file through the flying-general
+    /// rule, in the endgame. Tapered to nothing in the opening.
+    pub bonus_king_file_control: Score,
+    pub dynamic_bonus_attack_per_missing_defender: Score,
+    pub mobility_bonus_rook: Score,
+    pub mobility_bonus_horse: Score,
+    pub mobility_bonus_cannon: Score,
+    pub bonus_rook_on_open_file: Score,
+    pub bonus_rook_on_semi_open_file: Score,
+    /// A rook with at most one move, e.g. shut in a corner behind its own pieces.
+    pub penalty_trapped_rook: Score,
+    /// A horse on the edge of the board with every exit blocked.
+    pub penalty_trapped_horse: Score,
+    /// A bonus for the side to move, so quiet positions don't evaluate the same for both
+    /// sides and scores oscillate less between odd and even depths.
+    pub tempo_bonus: Score,
+    /// Quiescence search skips the dynamic terms when material and PST alone are
+    /// outside the window by this much.
+    pub lazy_eval_margin: i32,
+    pub bonus_doubled_rooks: Score,
+    /// For each rook or cannon on the file of the enemy king.
+    pub bonus_rook_on_king_file: Score,
+    pub bonus_cannon_on_king_file: Score,
+    /// Per pawn beyond the first on a file.
+    pub penalty_doubled_pawn: Score,
+    /// Per pair of side-by-side pawns across the river, which protect each other.
+    pub bonus_connected_pawns: Score,
+    /// Per pawn short of the river without a friendly pawn within two files.
+    pub penalty_isolated_pawn: Score,
+    /// Per pawn across the river, on top of the PST. Crossed pawns are worth more as the
+    /// pieces come off.
+    pub bonus_crossed_pawn: Score,
+    /// Per pawn inside the files and ranks of the enemy palace.
+    pub bonus_pawn_near_palace: Score,
+    /// Two bishops protecting each other, with the central point of their half covered.
+    pub bonus_connected_bishops: Score,
+    /// Per guard on a home square or the palace heart.
+    pub bonus_guard_on_ideal_square: Score,
+    /// Per bishop move blocked by a piece on the eye, or whose eye the enemy controls.
+    pub penalty_bishop_eye: Score,
 
     // Search constants
+    /// The largest amount, in centipawns, by which the side ahead at the root scores
+    /// a draw below zero.
+    pub max_contempt: i32,
+    /// How many plies less late quiet moves are searched, before a re-search if they
+    /// beat alpha.
     pub lmr_reduction: i32,
+    /// Late move pruning is only applied at or below this remaining depth.
+    pub lmp_max_depth: i32,
+    /// Quiet moves after `lmp_base_moves + depth * depth` legal moves are pruned.
+    pub lmp_base_moves: i32,
+    /// Nodes without a TT move are searched one ply shallower from this depth on.
+    pub iir_min_depth: i32,
+
+    /// How the search scores repetitions and long stretches without captures.
+    pub rules: Rules,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            bonus_bottom_cannon: 80,
-            bonus_palace_heart_horse: 70,
-            king_safety_penalty_per_guard: 50,
-            dynamic_bonus_attack_per_missing_defender: 15,
-            mobility_bonus_rook: 1,
-            mobility_bonus_horse: 3,
-            mobility_bonus_cannon: 1,
-            bonus_rook_on_open_file: 20,
-            bonus_rook_on_semi_open_file: 10,
+            bonus_bottom_cannon: Score::flat(80),
+            bonus_palace_heart_horse: Score::flat(70),
+            bonus_palace_cannon: Score::flat(40),
+            bonus_doubled_cannons: Score::flat(20),
+            bonus_cannon_attacks_rook: Score::flat(15),
+            bonus_horse_cannon_check: Score::flat(60),
+            bonus_horse_cannon_palace_attack: Score::flat(15),
+            king_safety_weight: 100,
+            penalty_exposed_king: Score::flat(10),
+            bonus_king_file_control: Score::new(0, 30),
+            dynamic_bonus_attack_per_missing_defender: Score::flat(15),
+            mobility_bonus_rook: Score::flat(1),
+            mobility_bonus_horse: Score::flat(3),
+            mobility_bonus_cannon: Score::flat(1),
+            bonus_rook_on_open_file: Score::flat(20),
+            bonus_rook_on_semi_open_file: Score::flat(10),
+            penalty_trapped_rook: Score::flat(40),
+            penalty_trapped_horse: Score::flat(30),
+            tempo_bonus: Score::flat(10),
+            lazy_eval_margin: 400,
+            bonus_doubled_rooks: Score::flat(15),
+            bonus_rook_on_king_file: Score::flat(15),
+            bonus_cannon_on_king_file: Score::flat(10),
+            penalty_doubled_pawn: Score::flat(15),
+            bonus_connected_pawns: Score::flat(20),
+            penalty_isolated_pawn: Score::flat(10),
+            bonus_crossed_pawn: Score::new(5, 25),
+            bonus_pawn_near_palace: Score::flat(15),
+            bonus_connected_bishops: Score::flat(20),
+            bonus_guard_on_ideal_square: Score::flat(10),
+            penalty_bishop_eye: Score::flat(8),
+            max_contempt: 20,
             lmr_reduction: 1,
+            lmp_max_depth: 3,
+            lmp_base_moves: 3,
+            iir_min_depth: 4,
+            rules: Rules::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads and validates a config file. Files ending in `.json` are read as JSON,
+    /// anything else as TOML.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let config: Config = if is_json {
+            serde_json::from_str(&text)
+                .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?
+        } else {
+            toml::from_str(&text).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?
+        };
+        config
+            .validate()
+            .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        Ok(config)
+    }
+
+    /// The config in TOML, as read by `from_file`.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("Config always serializes")
+    }
+
+    /// Checks that every constant is in a range the engine can work with.
+    pub fn validate(&self) -> Result<(), String> {
+        for tunable in &TUNABLES {
+            tunable.check((tunable.get)(self))?;
+        }
+        Ok(())
+    }
+
+    /// The value of the constant called `name`, one of `TUNABLES`.
+    pub fn get(&self, name: &str) -> Option<i32> {
+        TUNABLES.iter().find(|t| t.name == name).map(|t| (t.get)(self))
+    }
+
+    /// Sets the constant called `name`, which must be one of `TUNABLES`, checking its range.
+    pub fn set(&mut self, name: &str, value: i32) -> Result<(), String> {
+        let tunable = TUNABLES
+            .iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| format!("unknown config field `{}`", name))?;
+        tunable.check(value)?;
+        (tunable.set)(self, value);
+        Ok(())
+    }
+}
+
+/// A config constant that can be changed at runtime, e.g. by a UCI option or a tuner.
+/// A `Score` field is two of them, its midgame and endgame halves, named after the
+/// field with `_mg` and `_eg` appended.
+#[derive(Debug, Clone, Copy)]
+pub struct Tunable {
+    pub name: &'static str,
+    pub min: i32,
+    pub max: i32,
+    get: fn(&Config) -> i32,
+    set: fn(&mut Config, i32),
+}
+
+impl Tunable {
+    fn check(&self, value: i32) -> Result<(), String> {
+        if (self.min..=self.max).contains(&value) {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} = {} is out of range ({}..={})",
+                self.name, value, self.min, self.max
+            ))
         }
     }
 }
+
+/// Defines `TUNABLES` from one list of fields and ranges. `Score` fields are marked
+/// `(mg, eg)` and give a tunable for each half, with the same range.
+macro_rules! tunables {
+    (@munch [$($names:expr,)*] [$($tunables:expr,)*]) => {
+        /// Every config constant, in declaration order, with the range `validate` accepts.
+        pub const TUNABLES: [Tunable; [$($names),*].len()] = [$($tunables),*];
+    };
+    (@munch [$($names:expr,)*] [$($tunables:expr,)*]
+        $field:ident (mg, eg): $min:expr, $max:expr; $($rest:tt)*) => {
+        tunables!(@munch
+            [$($names,)* concat!(stringify!($field), "_mg"), concat!(stringify!($field), "_eg"),]
+            [$($tunables,)*
+                Tunable {
+                    name: concat!(stringify!($field), "_mg"),
+                    min: $min,
+                    max: $max,
+                    get: |config| config.$field.mg(),
+                    set: |config, value| config.$field = Score::new(value, config.$field.eg()),
+                },
+                Tunable {
+                    name: concat!(stringify!($field), "_eg"),
+                    min: $min,
+                    max: $max,
+                    get: |config| config.$field.eg(),
+                    set: |config, value| config.$field = Score::new(config.$field.mg(), value),
+                },
+            ]
+            $($rest)*);
+    };
+    (@munch [$($names:expr,)*] [$($tunables:expr,)*]
+        $field:ident: $min:expr, $max:expr; $($rest:tt)*) => {
+        tunables!(@munch
+            [$($names,)* stringify!($field),]
+            [$($tunables,)*
+                Tunable {
+                    name: stringify!($field),
+                    min: $min,
+                    max: $max,
+                    get: |config| config.$field,
+                    set: |config, value| config.$field = value,
+                },
+            ]
+            $($rest)*);
+    };
+    ($($entries:tt)*) => {
+        tunables!(@munch [] [] $($entries)*);
+    };
+}
+
+tunables! {
+    bonus_bottom_cannon (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_palace_heart_horse (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_palace_cannon (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_doubled_cannons (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_cannon_attacks_rook (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_horse_cannon_check (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_horse_cannon_palace_attack (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    king_safety_weight: 0, 1000;
+    penalty_exposed_king (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_king_file_control (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    dynamic_bonus_attack_per_missing_defender (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    mobility_bonus_rook (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    mobility_bonus_horse (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    mobility_bonus_cannon (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_rook_on_open_file (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_rook_on_semi_open_file (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    penalty_trapped_rook (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    penalty_trapped_horse (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    tempo_bonus (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    lazy_eval_margin: 1, 10000;
+    bonus_doubled_rooks (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_rook_on_king_file (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_cannon_on_king_file (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    penalty_doubled_pawn (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_connected_pawns (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    penalty_isolated_pawn (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_crossed_pawn (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_pawn_near_palace (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_connected_bishops (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    bonus_guard_on_ideal_square (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    penalty_bishop_eye (mg, eg): -MAX_EVAL_CONSTANT, MAX_EVAL_CONSTANT;
+    max_contempt: 0, 200;
+    lmr_reduction: 0, 8;
+    lmp_max_depth: 0, 32;
+    lmp_base_moves: 0, 256;
+    iir_min_depth: 1, 64;
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -32,6 +32,8 @@ pub enum Player {
 // --- Search and Evaluation Constants ---
 pub const MATE_VALUE: i32 = 10000;
 pub const DRAW_VALUE: i32 = 0;
+/// Plies without a capture after which the game is drawn: 60 moves each.
+pub const MOVE_RULE_PLIES: u32 = 120;
 
 // --- Piece Base Values ---
 // Indexed by `abs(piece as i8)`.
@@ -49,7 +51,7 @@ pub const PIECE_VALUES: [i32; 8] = [
 impl Piece {
     /// Get the value of a piece.
     pub fn value(self) -> i32 {
-        PIECE_VALUES[ (self as i8).abs() as usize ]
+        PIECE_VALUES[(self as i8).unsigned_abs() as usize]
     }
 
     pub fn is_major(self) -> bool {
@@ -101,19 +103,20 @@ impl Piece {
         }
     }
 
+    /// Also reads the `h` and `e` of `bitboard::FenDialect::Xiangqi`.
     pub fn from_fen_char(c: char) -> Option<Piece> {
         match c {
             'k' => Some(Piece::BKing),
             'a' => Some(Piece::BGuard),
-            'b' => Some(Piece::BBishop),
-            'n' => Some(Piece::BHorse),
+            'b' | 'e' => Some(Piece::BBishop),
+            'n' | 'h' => Some(Piece::BHorse),
             'r' => Some(Piece::BRook),
             'c' => Some(Piece::BCannon),
             'p' => Some(Piece::BPawn),
             'K' => Some(Piece::RKing),
             'A' => Some(Piece::RGuard),
-            'B' => Some(Piece::RBishop),
-            'N' => Some(Piece::RHorse),
+            'B' | 'E' => Some(Piece::RBishop),
+            'N' | 'H' => Some(Piece::RHorse),
             'R' => Some(Piece::RRook),
             'C' => Some(Piece::RCannon),
             'P' => Some(Piece::RPawn),
@@ -122,7 +125,7 @@ impl Piece {
     }
 
     pub fn abs_val(self) -> u8 {
-        (self as i8).abs() as u8
+        (self as i8).unsigned_abs()
     }
 
     pub fn from_abs(val: i8) -> Self {
@@ -1,36 +1,138 @@
 //! The main search engine.
 
 use crate::r#move::Move;
-use crate::bitboard::{self, Board};
-use crate::constants::{MATE_VALUE, Piece, Player};
+use crate::bitboard::Board;
+use crate::constants::{DRAW_VALUE, MATE_VALUE, Piece, Player};
 use crate::evaluate;
 use crate::movelist::MoveList;
 use crate::move_generator;
-use crate::opening_book;
+use crate::opening_book::{self, OpeningBook};
+use crate::output::{self, Output};
+use crate::search_limits::SearchLimits;
+use crate::searcher::Searcher;
+use crate::skill::Skill;
+use crate::stats::SearchStats;
 use crate::tt::{TranspositionTable, TtFlag};
+use crate::stop_token::StopToken;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
-/// A struct to hold a move and its score for move ordering.
-
+/// A legal move at the root, with what the search learned about it so far.
 #[derive(Debug, Clone, Copy)]
-pub struct ScoredMove {
+pub struct RootMove {
     pub mv: Move,
+    /// The score from the current iteration, or `-MATE_VALUE` if the move did not raise alpha.
     pub score: i32,
+    /// The score from the previous iteration.
+    pub previous_score: i32,
+    /// Nodes spent below this move, summed over all iterations.
+    pub nodes: u64,
 }
 
-/// The search engine.
-
 const MAX_PLY: usize = 128;
 
+/// Number of (piece, destination) pairs a move can be keyed on.
+const PIECE_SQUARES: usize = 14 * 90;
+
+/// The root evaluation is divided by this for the contempt, before the cap of
+/// `Config::max_contempt`.
+const CONTEMPT_SCORE_DIVISOR: i32 = 10;
+
+/// The root move being searched is only reported after this long, in milliseconds,
+/// so short searches are not flooded with `currmove` lines.
+const CURRMOVE_DELAY_MS: u128 = 3000;
+
+/// The outcome of a call to `Engine::search`.
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Move,
+    /// The expected reply to `best_move`, used as the move to ponder on.
+    pub ponder_move: Option<Move>,
+    pub score: i32,
+    pub depth: i32,
+    /// The principal variation, starting with `best_move`.
+    pub pv: Vec<Move>,
+    /// The best root lines, best first. Holds more than one line in MultiPV mode.
+    pub lines: Vec<PvLine>,
+}
+
+/// A scored principal variation for one root move.
+#[derive(Debug, Clone)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+/// The search engine.
 pub struct Engine {
     pub tt: TranspositionTable,
     pub history_table: [[i32; 90]; 14],
+    /// Continuation history, indexed by an earlier move and the current move,
+    /// each as a (piece, destination) pair. See `continuation_index`.
+    continuation_history: Vec<i32>,
+    /// The (piece index, destination) of the move made at each ply, `None` for a null move.
+    ply_moves: [Option<(usize, usize)>; MAX_PLY],
     pub killer_moves: [[Move; 2]; MAX_PLY],
+    /// Triangular PV table: row `ply` holds the best line found from that ply.
+    pv_table: [[Move; MAX_PLY]; MAX_PLY],
+    pv_length: [usize; MAX_PLY],
     pub nodes_searched: u64,
-    pub stop_search: bool,
+    /// Set once the search runs into its own time or node limit.
+    limit_reached: bool,
+    /// Stops the running search from another thread; checked at every node.
+    pub stop: StopToken,
+    /// Set from another thread to deliver a `ponderhit` to the running search.
+    pub ponderhit_signal: Arc<AtomicBool>,
     pub start_time: Instant,
     pub time_limit_ms: Option<u128>,
+    /// Hard limit on `nodes_searched` for the current search.
+    pub node_limit: Option<u64>,
+    /// Set for `go mate N`: only forced mates within N moves are looked for.
+    pub mate_limit: Option<i32>,
+    /// While set, the search is thinking on the opponent's time and ignores the time limit.
+    pub ponder: bool,
+    /// The number of best root lines to search and report.
+    pub multi_pv: usize,
+    /// Whether the search plays moves from the opening book.
+    pub own_book: bool,
+    /// The opening book, `None` for `opening_book::DEFAULT_BOOK`.
+    pub book: Option<Arc<OpeningBook>>,
+    /// Skips the opening book for the rest of the current game, even with `own_book`.
+    /// `new_game` clears it.
+    pub skip_book: bool,
+    /// Whether the opening book also looks up the mirrored position, see
+    /// `OpeningBook::query`.
+    pub book_mirror: bool,
+    /// Whether the search reports `info` lines and book moves.
+    pub print_info: bool,
+    /// Receives the reported lines, stdout unless a front end sets its own.
+    pub output: Output,
+    /// Limits the playing strength; full strength by default.
+    pub skill: Skill,
+    /// Maximum evaluation noise in centipawns, for opening variety. Zero disables it.
+    pub eval_noise: i32,
+    /// The noise is only added during the first this many moves of each side.
+    pub eval_noise_moves: usize,
+    /// Seeds the noise, so a position gets the same noise throughout a game.
+    pub eval_noise_seed: u64,
+    /// Whether the current search adds noise to the evaluation.
+    noise_active: bool,
+    /// Set to `Some` to collect statistics, which every search starts over.
+    pub stats: Option<SearchStats>,
+    /// Root moves skipped by the current MultiPV pass because they were already reported.
+    excluded_root_moves: Vec<Move>,
+    /// Root moves the current search is restricted to (`go searchmoves`). Empty means all moves.
+    search_moves: Vec<Move>,
+    /// The legal root moves, sorted best first after every iteration.
+    root_moves: Vec<RootMove>,
     pub config: crate::config::Config,
+    /// The side to move at the root of the current search.
+    root_player: Player,
+    /// See `root_contempt`.
+    contempt: i32,
+    /// Static evaluations of the current search.
+    eval_cache: evaluate::EvalCache,
 }
 
 impl Engine {
@@ -39,12 +141,40 @@ impl Engine {
         Self {
             tt: TranspositionTable::new(tt_size_mb),
             history_table: [[0; 90]; 14],
+            continuation_history: vec![0; PIECE_SQUARES * PIECE_SQUARES],
+            ply_moves: [None; MAX_PLY],
             killer_moves: [[Move::new(0, 0, None); 2]; MAX_PLY],
+            pv_table: [[Move::new(0, 0, None); MAX_PLY]; MAX_PLY],
+            pv_length: [0; MAX_PLY],
             nodes_searched: 0,
-            stop_search: false,
+            limit_reached: false,
+            stop: StopToken::new(),
+            ponderhit_signal: Arc::new(AtomicBool::new(false)),
             start_time: Instant::now(),
             time_limit_ms: None,
+            node_limit: None,
+            mate_limit: None,
+            ponder: false,
+            multi_pv: 1,
+            own_book: true,
+            book: None,
+            skip_book: false,
+            book_mirror: true,
+            print_info: true,
+            output: output::stdout(),
+            skill: Skill::default(),
+            eval_noise: 0,
+            eval_noise_moves: 10,
+            eval_noise_seed: rand::random(),
+            noise_active: false,
+            stats: None,
+            excluded_root_moves: Vec::new(),
+            search_moves: Vec::new(),
+            root_moves: Vec::new(),
             config: crate::config::Config::default(),
+            root_player: Player::Red,
+            contempt: 0,
+            eval_cache: evaluate::EvalCache::new(),
         }
     }
 
@@ -53,103 +183,321 @@ impl Engine {
         self.killer_moves = [[Move::new(0, 0, None); 2]; MAX_PLY];
     }
 
-    /// Clears the history table, resetting all move scores to zero.
+    /// Clears the history tables, resetting all move scores to zero.
     pub fn clear_history(&mut self) {
         self.history_table = [[0; 90]; 14];
+        self.continuation_history.fill(0);
     }
 
-    /// Counts the number of major pieces (Rook, Horse, Cannon) for a given player.
-    /// This is used for null move pruning.
-    fn get_major_piece_count(&self, board: &Board, player: Player) -> u32 {
-        let mut count = 0;
-        let (start_idx, end_idx) = if player == Player::Red {
-            (0, 7)
-        } else {
-            (7, 14)
-        };
+    /// Scales the history tables down between searches, so the move ordering learned
+    /// on earlier moves still helps but quickly gives way to fresh results.
+    fn age_history(&mut self) {
+        self.history_table
+            .iter_mut()
+            .flatten()
+            .for_each(|score| *score /= 2);
+        self.continuation_history.iter_mut().for_each(|score| *score /= 2);
+    }
 
-        for i in start_idx..end_idx {
-            let piece = Piece::from_abs((i + 1) as i8);
-            if piece.is_major() {
-                count += bitboard::popcount(board.piece_bitboards[i]);
-            }
-        }
-        count
+    /// Forgets everything learned from the previous game. Call this before searching
+    /// a position that does not follow from the previous searches.
+    /// The evaluation noise is reseeded, so the next game takes a different line, and
+    /// the opening book is used again if `skip_book` was set.
+    pub fn new_game(&mut self) {
+        self.clear_history();
+        self.clear_killers();
+        self.tt.clear();
+        self.eval_noise_seed = rand::random();
+        self.skip_book = false;
+    }
+
+    fn continuation_index(previous: (usize, usize), current: (usize, usize)) -> usize {
+        (previous.0 * 90 + previous.1) * PIECE_SQUARES + current.0 * 90 + current.1
+    }
+
+    /// Returns the moves played 1 and 2 plies before `ply`, if any.
+    fn previous_moves(&self, ply: usize) -> [Option<(usize, usize)>; 2] {
+        [
+            ply.checked_sub(1).and_then(|p| self.ply_moves[p]),
+            ply.checked_sub(2).and_then(|p| self.ply_moves[p]),
+        ]
+    }
+
+    /// Switches a ponder search to a normal timed search.
+    ///
+    /// Called when the opponent played the predicted move. The time limit is
+    /// measured from this point on, so the time spent pondering is a bonus.
+    pub fn ponderhit(&mut self) {
+        self.ponder = false;
+        self.start_time = Instant::now();
     }
 
     /// The main search function, using iterative deepening.
     ///
-    /// This function iteratively deepens the search depth, starting from 1 up to `max_depth`.
-    /// It also handles opening book moves and time management.
-    pub fn search(
-        &mut self,
-        board: &mut Board,
-        max_depth: i32,
-        time_limit_ms: Option<u128>,
-    ) -> (Move, i32, i32) {
-        self.clear_history();
+    /// This function iteratively deepens the search depth, starting from 1 up to the
+    /// depth allowed by `limits`. It also handles opening book moves and time management.
+    /// For a ponder search, the time limit only applies after `ponderhit` is called.
+    /// With `self.multi_pv` above one, each iteration re-searches the root with the
+    /// already reported moves excluded, so the `multi_pv` best lines are returned.
+    /// A reduced `self.skill` caps the depth and nodes, and may play a weaker line.
+    /// The transposition table is kept between calls; see `new_game`.
+    pub fn search(&mut self, board: &mut Board, limits: &SearchLimits) -> SearchResult {
+        self.age_history();
         self.clear_killers();
-        self.tt.clear();
+        self.tt.new_search();
+        // `config` may have changed since the last search.
+        self.eval_cache.clear();
         self.nodes_searched = 0;
-        self.stop_search = false;
+        if let Some(stats) = &mut self.stats {
+            *stats = SearchStats::default();
+        }
+        self.limit_reached = false;
         self.start_time = Instant::now();
-        self.time_limit_ms = time_limit_ms;
+        self.time_limit_ms = limits.time_limit_ms(board.player_to_move);
+        self.node_limit = if limits.infinite { None } else { limits.nodes };
+        self.mate_limit = limits.mate;
+        self.search_moves = limits.searchmoves.clone();
+        self.ponder = limits.ponder;
+        self.noise_active = self.eval_noise > 0 && board.history_ply < self.eval_noise_moves * 2;
+        self.root_player = board.player_to_move;
+        self.contempt = self.root_contempt(board);
+        let mut max_depth = limits.max_depth();
+        let mut multi_pv = self.multi_pv.max(1);
+        if self.skill.enabled() {
+            max_depth = max_depth.min(self.skill.max_depth());
+            let skill_nodes = self.skill.node_limit();
+            self.node_limit = Some(self.node_limit.map_or(skill_nodes, |n| n.min(skill_nodes)));
+            multi_pv = multi_pv.max(self.skill.multi_pv());
+        }
+
+        self.init_root_moves(board);
 
-        let mut best_move_overall = Move::new(0, 0, None);
-        let mut best_score_overall = -MATE_VALUE;
+        let mut lines_overall: Vec<PvLine> = Vec::new();
         let mut searched_depth = 1;
 
         for current_depth in 1..=max_depth {
-            // Query the opening book
-            if let Some(book_move) = opening_book::query_opening_book(board) {
-                println!(
-                    "Move from opening book: {} -> {}",
-                    book_move.from_sq(),
-                    book_move.to_sq()
-                );
-
-                return (book_move, 0, current_depth); // Return book move with a neutral score
+            // Query the opening book, unless the caller picked the root moves itself or
+            // analyses the position until stopped
+            if self.own_book
+                && !self.skip_book
+                && self.search_moves.is_empty()
+                && !limits.infinite
+                && let Some(book_move) = self
+                    .book
+                    .as_deref()
+                    .unwrap_or(&opening_book::DEFAULT_BOOK)
+                    .query(board, self.book_mirror)
+            {
+                if self.print_info {
+                    (self.output)(&format!("info string book move {}", book_move.to_uci_string()));
+                }
+
+                // Return book move with a neutral score
+                return SearchResult {
+                    best_move: book_move,
+                    ponder_move: None,
+                    score: 0,
+                    depth: current_depth,
+                    pv: vec![book_move],
+                    lines: vec![PvLine { score: 0, pv: vec![book_move] }],
+                };
             }
 
-            let (best_move_this_depth, best_score_this_depth) =
-                self.negamax(board, current_depth, -MATE_VALUE, MATE_VALUE, 0);
+            // Search the best moves of the previous iteration first. The sort is stable,
+            // so moves that never raised alpha keep their relative order.
+            self.root_moves.sort_by_key(|rm| std::cmp::Reverse(rm.score));
+            for rm in &mut self.root_moves {
+                rm.previous_score = rm.score;
+                rm.score = -MATE_VALUE;
+            }
+
+            let mut lines: Vec<PvLine> = Vec::new();
+            self.excluded_root_moves.clear();
+            for _ in 0..multi_pv {
+                let (best_move_this_line, best_score_this_line) =
+                    self.negamax(board, current_depth, -MATE_VALUE, MATE_VALUE, 0);
+
+                if self.is_stopped()
+                    || (best_move_this_line.from_sq() == 0 && best_move_this_line.to_sq() == 0)
+                {
+                    break;
+                }
 
-            if self.stop_search {
+                lines.push(PvLine {
+                    score: best_score_this_line,
+                    pv: self.root_pv(best_move_this_line),
+                });
+                self.excluded_root_moves.push(best_move_this_line);
+            }
+            self.excluded_root_moves.clear();
+
+            if self.is_stopped() {
                 break;
             }
 
-            if best_move_this_depth.from_sq() != 0 || best_move_this_depth.to_sq() != 0 {
-                best_move_overall = best_move_this_depth;
-                best_score_overall = best_score_this_depth;
+            if !lines.is_empty() {
+                // Later lines are searched with fewer candidates, but may still score higher.
+                lines.sort_by_key(|line| std::cmp::Reverse(line.score));
+                lines_overall = lines;
                 searched_depth = current_depth;
             }
 
-            // The score from negamax is from the perspective of the player whose turn it is.
-            // To display it consistently from Red's perspective (assuming Red is the human player),
-            // we check whose turn it was at the root of the search.
-            let display_score = if board.player_to_move == Player::Red {
-                best_score_overall
-            } else {
-                // If it was Black's turn, a positive score means Black is winning.
-                // To show this from Red's perspective, we negate it.
-                -best_score_overall
-            };
+            if self.print_info {
+                for (index, line) in lines_overall.iter().enumerate() {
+                    let multi_pv_field = if multi_pv > 1 {
+                        format!(" multipv {}", index + 1)
+                    } else {
+                        String::new()
+                    };
+                    let pv = line
+                        .pv
+                        .iter()
+                        .map(|mv| mv.to_uci_string())
+                        .collect::<Vec<_>>()
+                        .join(" ");
 
-            println!(
-                "info depth {} score cp {} nodes {} time {} pv {}",
-                current_depth,
-                display_score,
-                self.nodes_searched,
-                self.start_time.elapsed().as_millis(),
-                best_move_overall.to_uci_string()
-            );
+                    let elapsed_ms = self.start_time.elapsed().as_millis();
+                    let nps = self.nodes_searched as u128 * 1000 / elapsed_ms.max(1);
+
+                    (self.output)(&format!(
+                        "info depth {}{} score {} nodes {} nps {} hashfull {} time {} pv {}",
+                        current_depth,
+                        multi_pv_field,
+                        // UCI scores are from the side to move's point of view, as negamax's are.
+                        format_uci_score(line.score),
+                        self.nodes_searched,
+                        nps,
+                        self.tt.hashfull(),
+                        elapsed_ms,
+                        pv
+                    ));
+                }
+            }
 
-            if best_score_overall.abs() > MATE_VALUE - 100 {
+            if lines_overall
+                .first()
+                .is_some_and(|line| line.score.abs() > MATE_VALUE - 100)
+            {
+                break;
+            }
+
+            // A mate search has nothing to report but mates.
+            if self.mate_limit.is_some()
+                && lines_overall
+                    .first()
+                    .is_some_and(|line| line.score > MATE_VALUE - MAX_PLY as i32)
+            {
                 break;
             }
         }
 
-        (best_move_overall, best_score_overall, searched_depth)
+        let chosen_line = if self.skill.enabled() {
+            self.skill.pick_line(&lines_overall)
+        } else {
+            0
+        };
+        let (best_move_overall, best_score_overall, pv_overall) =
+            match lines_overall.get(chosen_line) {
+                Some(line) => (line.pv[0], line.score, line.pv.clone()),
+                // Stopped before the first iteration finished: any legal move beats none.
+                None => match self.root_moves.first() {
+                    Some(rm) => (rm.mv, rm.score, vec![rm.mv]),
+                    None => (Move::new(0, 0, None), -MATE_VALUE, Vec::new()),
+                },
+            };
+
+        // The PV can be cut short by a TT hit right after the root; fall back to the TT then.
+        let ponder_move = match pv_overall.get(1) {
+            Some(&reply) => Some(reply),
+            None => self.find_ponder_move(board, best_move_overall),
+        };
+
+        SearchResult {
+            best_move: best_move_overall,
+            ponder_move,
+            score: best_score_overall,
+            depth: searched_depth,
+            pv: pv_overall,
+            lines: lines_overall,
+        }
+    }
+
+    /// Returns the principal variation collected at the root by the last iteration.
+    fn root_pv(&self, best_move: Move) -> Vec<Move> {
+        let line = &self.pv_table[0][..self.pv_length[0]];
+        if line.first() == Some(&best_move) {
+            line.to_vec()
+        } else {
+            vec![best_move]
+        }
+    }
+
+    /// Collects the legal root moves allowed by `searchmoves`, in the usual move order.
+    fn init_root_moves(&mut self, board: &mut Board) {
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+
+        legal_moves.retain(|mv| self.is_root_move_allowed(mv));
+        legal_moves.sort_by_score(|mv| self.score_move(board, mv, Move::new(0, 0, None), 0));
+
+        self.root_moves = legal_moves
+            .into_iter()
+            .map(|mv| RootMove {
+                mv,
+                score: -MATE_VALUE,
+                previous_score: -MATE_VALUE,
+                nodes: 0,
+            })
+            .collect();
+    }
+
+    /// Records the result of searching root move `mv`. Only a move that raised alpha
+    /// (or the first move, searched with the full window) gets an exact score.
+    fn update_root_move(&mut self, mv: Move, score: i32, raised_alpha: bool, nodes: u64) {
+        if let Some(rm) = self.root_moves.iter_mut().find(|rm| rm.mv == mv) {
+            rm.score = if raised_alpha { score } else { -MATE_VALUE };
+            rm.nodes += nodes;
+        }
+    }
+
+    /// Whether `mv` may be searched at the root, given `searchmoves` and the MultiPV
+    /// lines already reported. `searchmoves` are matched on squares only.
+    fn is_root_move_allowed(&self, mv: Move) -> bool {
+        let in_search_moves = self.search_moves.is_empty()
+            || self
+                .search_moves
+                .iter()
+                .any(|m| m.from_sq() == mv.from_sq() && m.to_sq() == mv.to_sq());
+        in_search_moves && !self.excluded_root_moves.contains(&mv)
+    }
+
+    /// Records `mv` as the best move at `ply`, followed by the child's principal variation.
+    fn update_pv(&mut self, ply: usize, mv: Move) {
+        self.pv_table[ply][ply] = mv;
+        let child_len = self.pv_length[ply + 1];
+        for next_ply in (ply + 1)..child_len {
+            self.pv_table[ply][next_ply] = self.pv_table[ply + 1][next_ply];
+        }
+        self.pv_length[ply] = child_len.max(ply + 1);
+    }
+
+    /// Predicts the opponent's reply to `best_move` by looking up the resulting
+    /// position in the transposition table. The stored move is only returned if
+    /// it is legal and captures the same piece, since the entry may belong to a
+    /// colliding position.
+    fn find_ponder_move(&self, board: &mut Board, best_move: Move) -> Option<Move> {
+        if best_move.from_sq() == 0 && best_move.to_sq() == 0 {
+            return None;
+        }
+
+        board.make_move(best_move);
+        let reply = self
+            .tt
+            .probe(board.hash_key)
+            .map(|entry| entry.best_move)
+            .filter(|&mv| board.is_legal(mv));
+        board.undo_move();
+        reply
     }
 
     fn negamax(
@@ -165,18 +513,50 @@ impl Engine {
         }
 
         self.nodes_searched += 1;
+        self.record(|stats| stats.main_nodes += 1);
+        self.pv_length[ply] = ply;
+
+        if ply >= MAX_PLY - 1 {
+            return (Move::new(0, 0, None), self.evaluate(board));
+        }
 
+        if ply > 0
+            && let Some(draw_score) = self.handle_repetition(board, ply)
+        {
+            return (Move::new(0, 0, None), draw_score);
+        }
+
+        if ply > 0
+            && let Some(plies) = self.config.rules.move_rule_plies()
+            && board.is_move_rule_draw(plies)
+        {
+            return (Move::new(0, 0, None), self.draw_score(board));
+        }
+
+        // --- Mate Distance Pruning ---
+        // Even mating on the next move cannot beat a shorter mate already found.
         if ply > 0 {
-            if let Some(draw_score) = self.handle_repetition(board) {
-                return (Move::new(0, 0, None), draw_score);
+            alpha = alpha.max(-MATE_VALUE + ply as i32);
+            beta = beta.min(MATE_VALUE - ply as i32 - 1);
+            if alpha >= beta {
+                return (Move::new(0, 0, None), alpha);
             }
         }
 
+        // The TT knows nothing about restricted root moves, so its bounds must not be used there.
+        let restricting_root_moves =
+            ply == 0 && !(self.excluded_root_moves.is_empty() && self.search_moves.is_empty());
+
         let mut tt_best_move = Move::new(0, 0, None);
         let original_alpha = alpha;
-        if let Some(tt_result) = self.probe_tt_table(
+        if restricting_root_moves {
+            if let Some(tt_entry) = self.tt.probe(board.hash_key) {
+                tt_best_move = tt_entry.best_move;
+            }
+        } else if let Some(tt_result) = self.probe_tt_table(
             board.hash_key,
             depth,
+            ply,
             &mut alpha,
             &mut beta,
             &mut tt_best_move,
@@ -199,8 +579,24 @@ impl Engine {
             current_depth += 1;
         }
 
-        if let Some(pruning_result) =
-            self.perform_null_move_pruning(board, current_depth, beta, is_in_check, ply)
+        // A mate search must see every line to its full depth, so it skips null moves,
+        // reductions and late move pruning.
+        let mate_search = self.mate_limit.is_some();
+
+        // --- Internal Iterative Reduction (IIR) ---
+        // Without a TT move the ordering here is poor; search shallower and let the
+        // next iteration revisit the node with the move this search stores.
+        if ply > 0
+            && !mate_search
+            && tt_best_move == Move::new(0, 0, None)
+            && current_depth >= self.config.iir_min_depth
+        {
+            current_depth -= 1;
+        }
+
+        if !mate_search
+            && let Some(pruning_result) =
+                self.perform_null_move_pruning(board, current_depth, beta, is_in_check, ply)
         {
             return pruning_result;
         }
@@ -209,323 +605,515 @@ impl Engine {
         let mut best_score = -MATE_VALUE;
         let mut best_move = Move::new(0, 0, None);
 
+        // The root moves are already sorted by the previous iteration.
         let mut moves = MoveList::new();
-        board.generate_capture_moves(&mut moves);
-        board.generate_quiet_moves(&mut moves);
-
-        let mut scored_moves: Vec<ScoredMove> = moves
-            .as_slice()
-            .iter()
-            .map(|mv| ScoredMove {
-                mv: *mv,
-                score: self.score_move(board, *mv, tt_best_move, ply),
-            })
-            .collect();
-        scored_moves.sort_by(|a, b| b.score.cmp(&a.score));
+        if ply == 0 {
+            moves.extend(self.root_moves.iter().map(|rm| rm.mv));
+        } else {
+            board.generate_capture_moves(&mut moves);
+            board.generate_quiet_moves(&mut moves);
+            moves.sort_by_score(|mv| self.score_move(board, mv, tt_best_move, ply));
+        }
 
-            for sm in scored_moves {
-                let captured = board.move_piece(sm.mv);
-                if move_generator::is_king_in_check(board, board.player_to_move.opponent()) {
-                    board.unmove_piece(sm.mv, captured);
-                    continue;
-                }
-                legal_moves_found += 1;
+        for mv in moves {
+            if restricting_root_moves && !self.is_root_move_allowed(mv) {
+                continue;
+            }
 
-                let mut score;
-                if legal_moves_found == 1 {
-                    // Full window search for the first move
-                    score = -self
-                        .negamax(board, current_depth - 1, -beta, -alpha, ply + 1)
-                        .1;
+            board.make_move(mv);
+            self.tt.prefetch(board.hash_key);
+            if move_generator::is_king_in_check(board, board.player_to_move.opponent()) {
+                board.undo_move();
+                continue;
+            }
+            legal_moves_found += 1;
+            if ply == 0
+                && self.print_info
+                && self.start_time.elapsed().as_millis() >= CURRMOVE_DELAY_MS
+            {
+                (self.output)(&format!(
+                    "info depth {} currmove {} currmovenumber {}",
+                    current_depth,
+                    mv.to_uci_string(),
+                    legal_moves_found
+                ));
+            }
+            self.ply_moves[ply] = board.board[mv.to_sq()]
+                .get_bb_index()
+                .map(|idx| (idx, mv.to_sq()));
+
+            // --- Late Move Pruning (LMP) ---
+            // Near the leaves, late quiet moves rarely matter and are skipped entirely.
+            // The checks are ordered so the costly gives-check test runs last.
+            if ply > 0
+                && !mate_search
+                && !is_in_check
+                && current_depth <= self.config.lmp_max_depth
+                && legal_moves_found > self.config.lmp_base_moves + current_depth * current_depth
+                && best_score > -MATE_VALUE + MAX_PLY as i32
+                && !mv.is_capture()
+                && mv != tt_best_move
+                && !self.is_killer_move(mv, ply)
+                && !move_generator::is_king_in_check(board, board.player_to_move)
+            {
+                board.undo_move();
+                continue;
+            }
+
+            let nodes_before = self.nodes_searched;
+            let mut score;
+            if legal_moves_found == 1 {
+                // Full window search for the first move
+                score = -self
+                    .negamax(board, current_depth - 1, -beta, -alpha, ply + 1)
+                    .1;
+            } else {
+                // --- Late Move Reduction (LMR) ---
+                let reduction = if !mate_search
+                    && current_depth >= 3
+                    && legal_moves_found > 3
+                    && !is_in_check
+                    && !mv.is_capture()
+                {
+                    self.config.lmr_reduction
                 } else {
-                    // --- Late Move Reduction (LMR) ---
-                    let reduction = if current_depth >= 3
-                        && legal_moves_found > 3
-                        && !is_in_check
-                        && !sm.mv.is_capture()
-                    {
-                        1
-                    } else {
-                        0
-                    };
+                    0
+                };
 
+                score = -self
+                    .negamax(
+                        board,
+                        current_depth - 1 - reduction,
+                        -alpha - 1,
+                        -alpha,
+                        ply + 1,
+                    )
+                    .1;
+
+                if reduction > 0 {
+                    self.record(|stats| stats.lmr_searches += 1);
+                }
+
+                // Re-search if LMR was too aggressive
+                if score > alpha && reduction > 0 {
+                    self.record(|stats| stats.lmr_researches += 1);
                     score = -self
-                        .negamax(
-                            board,
-                            current_depth - 1 - reduction,
-                            -alpha - 1,
-                            -alpha,
-                            ply + 1,
-                        )
+                        .negamax(board, current_depth - 1, -beta, -alpha, ply + 1)
                         .1;
-
-                    // Re-search if LMR was too aggressive
-                    if score > alpha && reduction > 0 {
-                        score = -self
-                            .negamax(board, current_depth - 1, -beta, -alpha, ply + 1)
-                            .1;
-                    }
                 }
+            }
 
-                board.unmove_piece(sm.mv, captured);
+            board.undo_move();
 
-                if score > best_score {
-                    best_score = score;
-                    best_move = sm.mv;
-                }
-                if best_score > alpha {
-                    alpha = best_score;
-                }
-                if alpha >= beta {
-                    if !sm.mv.is_capture() {
-                        self.store_killer_move(sm.mv, ply);
-                        let moving_piece = board.board[sm.mv.from_sq()];
-                        if let Some(idx) = moving_piece.get_bb_index() {
-                            self.history_table[idx][sm.mv.to_sq()] += depth * depth;
+            if ply == 0 && !self.is_stopped() {
+                let raised_alpha = legal_moves_found == 1 || score > alpha;
+                let nodes = self.nodes_searched - nodes_before;
+                self.update_root_move(mv, score, raised_alpha, nodes);
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = mv;
+            }
+            if best_score > alpha {
+                alpha = best_score;
+                self.update_pv(ply, mv);
+            }
+            if alpha >= beta {
+                if !mv.is_capture() {
+                    self.store_killer_move(mv, ply);
+                    let moving_piece = board.board[mv.from_sq()];
+                    if let Some(idx) = moving_piece.get_bb_index() {
+                        let bonus = depth * depth;
+                        self.history_table[idx][mv.to_sq()] += bonus;
+                        for previous in self.previous_moves(ply).into_iter().flatten() {
+                            let index = Self::continuation_index(previous, (idx, mv.to_sq()));
+                            self.continuation_history[index] += bonus;
                         }
                     }
-                    break; // Beta cutoff
                 }
+                self.record(|stats| stats.record_cutoff(legal_moves_found as usize));
+                break; // Beta cutoff
             }
+        }
 
-            if legal_moves_found == 0 {
-                return (
-                    Move::new(0, 0, None),
-                    if is_in_check {
-                        -MATE_VALUE + ply as i32
-                    } else {
-                        0
-                    },
-                );
-            }
+        // A side without legal moves has lost, in check or not.
+        if legal_moves_found == 0 {
+            return (Move::new(0, 0, None), -MATE_VALUE + ply as i32);
+        }
 
+        if !restricting_root_moves {
             self.store_in_tt_table(
                 board.hash_key,
                 depth,
+                ply,
                 best_score,
-                original_alpha,
-                beta,
+                (original_alpha, beta),
                 best_move,
             );
+        }
+
+        (best_move, best_score)
+    }
 
-            (best_move, best_score)
+    /// Whether the search was stopped, either by its limits or through `self.stop`.
+    fn is_stopped(&self) -> bool {
+        self.limit_reached || self.stop.is_stopped()
+    }
+
+    /// Updates the statistics, if they are being collected.
+    fn record(&mut self, update: impl FnOnce(&mut SearchStats)) {
+        if let Some(stats) = &mut self.stats {
+            update(stats);
         }
+    }
 
-        /// Checks if the time limit for the search has been exceeded.
-        fn check_time_limit(&mut self) -> bool {
-            if self.nodes_searched % 2048 == 0 {
-                if let Some(limit) = self.time_limit_ms {
-                    if self.start_time.elapsed().as_millis() >= limit {
-                        self.stop_search = true;
-                    }
-                }
-            }
-            self.stop_search
-        }
-
-        /// Detects if the current position is a draw by repetition.
-        fn handle_repetition(&self, board: &Board) -> Option<i32> {
-            if board.history_ply >= 4 {
-                let mut repetitions = 0;
-                for i in (0..board.history_ply - 1).rev().step_by(2) {
-                    if board.history[i] == board.hash_key {
-                        repetitions += 1;
-                        if repetitions >= 2 {
-                            return Some(0); // Draw
-                        }
-                    }
-                }
-            }
-            None
-        }
-
-        /// Probes the transposition table for the current position.
-        fn probe_tt_table(
-            &mut self,
-            hash_key: u64,
-            depth: i32,
-            alpha: &mut i32,
-            beta: &mut i32,
-            tt_best_move: &mut Move,
-        ) -> Option<(Move, i32)> {
-            if let Some(tt_entry) = self.tt.probe(hash_key) {
-                *tt_best_move = tt_entry.best_move;
-                if tt_entry.depth >= depth {
-                    let score = tt_entry.score;
-                    match tt_entry.flag {
-                        TtFlag::Exact => return Some((tt_entry.best_move, score)),
-                        TtFlag::LowerBound => *alpha = (*alpha).max(score),
-                        TtFlag::UpperBound => *beta = (*beta).min(score),
-                    }
-                    if *alpha >= *beta {
-                        return Some((tt_entry.best_move, score));
-                    }
-                }
-            }
-            None
-        }
-
-        /// Performs null move pruning.
-        fn perform_null_move_pruning(
-            &mut self,
-            board: &mut Board,
-            depth: i32,
-            beta: i32,
-            is_in_check: bool,
-            ply: usize,
-        ) -> Option<(Move, i32)> {
-            if !is_in_check && depth >= 3 && self.get_major_piece_count(board, board.player_to_move) > 1
-            {
-                let r = if depth > 6 { 3 } else { 2 };
-                board.player_to_move = board.player_to_move.opponent();
-                board.hash_key ^= crate::zobrist::ZOBRIST_PLAYER;
-                board.history_ply += 1;
-                board.history[board.history_ply] = board.hash_key;
+    /// Evaluates the position for the side to move, adding the evaluation noise
+    /// early in the game when it is enabled.
+    fn evaluate(&mut self, board: &Board) -> i32 {
+        let score = self.eval_cache.evaluate(board, &self.config);
+        self.add_noise(board, score)
+    }
 
-                let (_, null_move_score) =
-                    self.negamax(board, depth - 1 - r, -beta, -beta + 1, ply + 1);
-                let score = -null_move_score;
+    /// Like `evaluate`, but may skip the costly terms when the score is far outside
+    /// [alpha, beta], see `evaluate::lazy_score`.
+    fn evaluate_lazy(&mut self, board: &Board, alpha: i32, beta: i32) -> i32 {
+        let score = self.eval_cache.evaluate_lazy(board, &self.config, alpha, beta);
+        self.add_noise(board, score)
+    }
 
-                board.history_ply -= 1;
-                board.hash_key ^= crate::zobrist::ZOBRIST_PLAYER;
-                board.player_to_move = board.player_to_move.opponent();
+    fn add_noise(&self, board: &Board, score: i32) -> i32 {
+        if !self.noise_active {
+            return score;
+        }
 
-                if score >= beta {
-                    return Some((Move::new(0, 0, None), beta));
-                }
-            }
-            None
+        // SplitMix64 finalizer: the same position and seed always get the same noise.
+        let mut x = board.hash_key ^ self.eval_noise_seed;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^= x >> 31;
+        let span = 2 * self.eval_noise as u64 + 1;
+        score + (x % span) as i32 - self.eval_noise
+    }
+
+    /// Checks if the time or node limit for the search has been exceeded, or if
+    /// another thread asked the search to stop.
+    /// The limits are not enforced while pondering.
+    fn check_time_limit(&mut self) -> bool {
+        if self.nodes_searched.is_multiple_of(2048)
+            && self.ponder
+            && self.ponderhit_signal.swap(false, Ordering::Relaxed)
+        {
+            self.ponderhit();
+        }
+        if !self.ponder
+            && let Some(limit) = self.node_limit
+            && self.nodes_searched >= limit
+        {
+            self.limit_reached = true;
         }
+        if self.nodes_searched.is_multiple_of(2048)
+            && !self.ponder
+            && let Some(limit) = self.time_limit_ms
+            && self.start_time.elapsed().as_millis() >= limit
+        {
+            self.limit_reached = true;
+        }
+        self.is_stopped()
+    }
 
-        fn store_in_tt_table(
-            &mut self,
-            hash_key: u64,
-            depth: i32,
-            best_score: i32,
-            original_alpha: i32,
-            beta: i32,
-            best_move: Move,
-        ) {
-            let flag = if best_score >= beta {
-                TtFlag::LowerBound
-            } else if best_score > original_alpha {
-                TtFlag::Exact
-            } else {
-                TtFlag::UpperBound
-            };
-            self.tt.store(hash_key, depth, best_score, flag, best_move);
+    /// The contempt for the root position: how much worse than `DRAW_VALUE` a draw is
+    /// for the side to move at the root. It grows with the root evaluation, so the side
+    /// that is ahead avoids repetitions and the side that is behind welcomes them, and
+    /// shrinks with the material left, as endgame advantages are harder to convert.
+    fn root_contempt(&mut self, board: &Board) -> i32 {
+        let max_contempt = self.config.max_contempt;
+        let score = (self.evaluate(board) / CONTEMPT_SCORE_DIVISOR).clamp(-max_contempt, max_contempt);
+        score * board.phase.min(evaluate::OPENING_PHASE) / evaluate::OPENING_PHASE
+    }
+
+    /// The score of a drawn position for the side to move on `board`.
+    fn draw_score(&self, board: &Board) -> i32 {
+        if board.player_to_move == self.root_player {
+            DRAW_VALUE - self.contempt
+        } else {
+            DRAW_VALUE + self.contempt
         }
+    }
+
+    /// Scores the current position if it repeats for the third time: a draw, unless the
+    /// rules make the side that forced it by perpetual check or chase lose.
+    fn handle_repetition(&self, board: &mut Board, ply: usize) -> Option<i32> {
+        let start = board.third_repetition_start()?;
+        Some(match self.config.rules.repetition_outcome(board, start).loser() {
+            Some(player) if player == board.player_to_move => -MATE_VALUE + ply as i32,
+            Some(_) => MATE_VALUE - ply as i32,
+            None => self.draw_score(board),
+        })
+    }
 
-        fn store_killer_move(&mut self, mv: Move, ply: usize) {
-            if ply < MAX_PLY {
-                self.killer_moves[ply][1] = self.killer_moves[ply][0];
-                self.killer_moves[ply][0] = mv;
+    /// Probes the transposition table for the current position.
+    fn probe_tt_table(
+        &mut self,
+        hash_key: u64,
+        depth: i32,
+        ply: usize,
+        alpha: &mut i32,
+        beta: &mut i32,
+        tt_best_move: &mut Move,
+    ) -> Option<(Move, i32)> {
+        let tt_entry = self.tt.probe(hash_key);
+        self.record(|stats| {
+            stats.tt_probes += 1;
+            stats.tt_hits += tt_entry.is_some() as u64;
+        });
+        if let Some(tt_entry) = tt_entry {
+            *tt_best_move = tt_entry.best_move;
+            if tt_entry.depth >= depth {
+                let score = score_from_tt(tt_entry.score, ply);
+                match tt_entry.flag {
+                    TtFlag::Exact => return Some((tt_entry.best_move, score)),
+                    TtFlag::LowerBound => *alpha = (*alpha).max(score),
+                    TtFlag::UpperBound => *beta = (*beta).min(score),
+                }
+                if *alpha >= *beta {
+                    return Some((tt_entry.best_move, score));
+                }
             }
         }
+        None
+    }
 
-        /// Helper to score a move for move ordering.
-        fn score_move(&self, board: &Board, mv: Move, tt_best_move: Move, ply: usize) -> i32 {
-            const TT_BEST_MOVE_SCORE: i32 = 1_000_000;
-            const KILLER_MOVE_SCORE: i32 = 500_000;
-            const CAPTURE_BONUS: i32 = 800_000;
+    /// Performs null move pruning.
+    fn perform_null_move_pruning(
+        &mut self,
+        board: &mut Board,
+        depth: i32,
+        beta: i32,
+        is_in_check: bool,
+        ply: usize,
+    ) -> Option<(Move, i32)> {
+        if !is_in_check && depth >= 3 && board.major_piece_count(board.player_to_move) > 1
+        {
+            self.record(|stats| stats.null_move_tries += 1);
+            let r = if depth > 6 { 3 } else { 2 };
+            board.make_null_move();
+            self.ply_moves[ply] = None;
 
-            if mv.from_sq() == tt_best_move.from_sq() && mv.to_sq() == tt_best_move.to_sq() {
-                return TT_BEST_MOVE_SCORE;
-            }
+            let (_, null_move_score) =
+                self.negamax(board, depth - 1 - r, -beta, -beta + 1, ply + 1);
+            let score = -null_move_score;
 
-            // MVV-LVA (Most Valuable Victim - Least Valuable Aggressor)
-            let captured_piece = board.board[mv.to_sq()];
-            if captured_piece != Piece::Empty {
-                let moving_piece = board.board[mv.from_sq()];
-                return CAPTURE_BONUS + captured_piece.value() - moving_piece.value();
-            }
+            board.unmake_null_move();
 
-            // Killer moves
-            if ply < MAX_PLY {
-                if self.killer_moves[ply][0] == mv {
-                    return KILLER_MOVE_SCORE;
-                }
-                if self.killer_moves[ply][1] == mv {
-                    return KILLER_MOVE_SCORE - 10;
-                }
+            if score >= beta {
+                self.record(|stats| stats.null_move_cutoffs += 1);
+                return Some((Move::new(0, 0, None), beta));
             }
+        }
+        None
+    }
+
+    fn store_in_tt_table(
+        &mut self,
+        hash_key: u64,
+        depth: i32,
+        ply: usize,
+        best_score: i32,
+        (original_alpha, beta): (i32, i32),
+        best_move: Move,
+    ) {
+        let flag = if best_score >= beta {
+            TtFlag::LowerBound
+        } else if best_score > original_alpha {
+            TtFlag::Exact
+        } else {
+            TtFlag::UpperBound
+        };
+        self.tt.store(hash_key, depth, score_to_tt(best_score, ply), flag, best_move);
+    }
+
+    fn store_killer_move(&mut self, mv: Move, ply: usize) {
+        if ply < MAX_PLY {
+            self.killer_moves[ply][1] = self.killer_moves[ply][0];
+            self.killer_moves[ply][0] = mv;
+        }
+    }
+
+    fn is_killer_move(&self, mv: Move, ply: usize) -> bool {
+        ply < MAX_PLY && (self.killer_moves[ply][0] == mv || self.killer_moves[ply][1] == mv)
+    }
+
+    /// Helper to score a move for move ordering.
+    fn score_move(&self, board: &Board, mv: Move, tt_best_move: Move, ply: usize) -> i32 {
+        const TT_BEST_MOVE_SCORE: i32 = 1_000_000;
+        const KILLER_MOVE_SCORE: i32 = 500_000;
+        const CAPTURE_BONUS: i32 = 800_000;
 
-            // History heuristic
+        if mv.from_sq() == tt_best_move.from_sq() && mv.to_sq() == tt_best_move.to_sq() {
+            return TT_BEST_MOVE_SCORE;
+        }
+
+        // MVV-LVA (Most Valuable Victim - Least Valuable Aggressor)
+        let captured_piece = mv.captured();
+        if captured_piece != Piece::Empty {
             let moving_piece = board.board[mv.from_sq()];
-            if let Some(idx) = moving_piece.get_bb_index() {
-                return self.history_table[idx][mv.to_sq()];
+            return CAPTURE_BONUS + captured_piece.value() - moving_piece.value();
+        }
+
+        // Killer moves
+        if ply < MAX_PLY {
+            if self.killer_moves[ply][0] == mv {
+                return KILLER_MOVE_SCORE;
             }
-            0 // Default if piece not found (should not happen)
-        }
-
-        /// Quiescence search to evaluate noisy positions.
-        fn quiescence_search(
-            &mut self,
-            board: &mut Board,
-            mut alpha: i32,
-            beta: i32,
-            ply: usize,
-        ) -> i32 {
-            const Q_SEARCH_DEPTH: i32 = 8;
-            if ply >= MAX_PLY || (ply as i32) > Q_SEARCH_DEPTH {
-                return evaluate::evaluate(board, &self.config);
+            if self.killer_moves[ply][1] == mv {
+                return KILLER_MOVE_SCORE - 10;
             }
+        }
 
-            if self.check_time_limit() {
-                return 0;
+        // History heuristic, blended with the continuation history of the last two plies
+        let moving_piece = board.board[mv.from_sq()];
+        if let Some(idx) = moving_piece.get_bb_index() {
+            let mut score = self.history_table[idx][mv.to_sq()];
+            for previous in self.previous_moves(ply).into_iter().flatten() {
+                score += self.continuation_history[Self::continuation_index(previous, (idx, mv.to_sq()))];
             }
-            self.nodes_searched += 1;
+            return score;
+        }
+        0 // Default if piece not found (should not happen)
+    }
 
-            let stand_pat = evaluate::evaluate(board, &self.config);
-            if stand_pat >= beta {
-                return beta;
-            }
-            if stand_pat > alpha {
-                alpha = stand_pat;
-            }
+    /// Quiescence search to evaluate noisy positions.
+    fn quiescence_search(
+        &mut self,
+        board: &mut Board,
+        mut alpha: i32,
+        beta: i32,
+        ply: usize,
+    ) -> i32 {
+        const Q_SEARCH_DEPTH: i32 = 8;
+        if ply >= MAX_PLY || (ply as i32) > Q_SEARCH_DEPTH {
+            return self.evaluate(board);
+        }
 
-            let mut moves = MoveList::new();
-            board.generate_capture_moves(&mut moves);
-            
-            let mut quiet_moves = MoveList::new();
-            board.generate_quiet_moves(&mut quiet_moves);
-            for i in 0..quiet_moves.len() {
-                let mv = quiet_moves[i];
-                let captured = board.move_piece(mv);
-                if !move_generator::is_king_in_check(board, board.player_to_move.opponent()) {
-                    if move_generator::is_king_in_check(board, board.player_to_move) {
-                        moves.add(mv);
-                    }
-                }
-                board.unmove_piece(mv, captured);
-            }
+        if self.check_time_limit() {
+            return 0;
+        }
+        self.nodes_searched += 1;
+        self.record(|stats| stats.qsearch_nodes += 1);
 
-            let mut scored_moves: Vec<ScoredMove> = moves
-                .as_slice()
-                .iter()
-                .map(|mv| ScoredMove {
-                    mv: *mv,
-                    score: self.score_move(board, *mv, Move::new(0, 0, None), ply),
-                })
-                .collect();
-            scored_moves.sort_by(|a, b| b.score.cmp(&a.score));
-
-            for sm in scored_moves {
-                let captured = board.move_piece(sm.mv);
-                if !move_generator::is_king_in_check(board, board.player_to_move.opponent()) {
-                    let score = -self.quiescence_search(board, -beta, -alpha, ply + 1);
-                    board.unmove_piece(sm.mv, captured);
-
-                    if score >= beta {
-                        return beta;
-                    }
-                    if score > alpha {
-                        alpha = score;
+        // Quiescence results are stored at depth 0, so any entry is deep enough. The
+        // bounds are probed on copies, as this search fails hard within [alpha, beta].
+        let original_alpha = alpha;
+        let mut tt_move = Move::new(0, 0, None);
+        let (mut tt_alpha, mut tt_beta) = (alpha, beta);
+        if let Some((_, score)) =
+            self.probe_tt_table(board.hash_key, 0, ply, &mut tt_alpha, &mut tt_beta, &mut tt_move)
+        {
+            return score.clamp(alpha, beta);
+        }
+
+        let stand_pat = self.evaluate_lazy(board, alpha, beta);
+        if stand_pat >= beta {
+            return beta;
+        }
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
+
+        let mut moves = MoveList::new();
+        board.generate_capture_moves(&mut moves);
+        
+        let mut quiet_moves = MoveList::new();
+        board.generate_quiet_moves(&mut quiet_moves);
+        quiet_moves.retain(|mv| {
+            board.make_move(mv);
+            let gives_check = !move_generator::is_king_in_check(board, board.player_to_move.opponent())
+                && move_generator::is_king_in_check(board, board.player_to_move);
+            board.undo_move();
+            gives_check
+        });
+        moves.extend_from_slice(quiet_moves.as_slice());
+        moves.sort_by_score(|mv| self.score_move(board, mv, tt_move, ply));
+
+        let mut best_move = Move::new(0, 0, None);
+        for mv in moves {
+            board.make_move(mv);
+            if !move_generator::is_king_in_check(board, board.player_to_move.opponent()) {
+                self.ply_moves[ply] = board.board[mv.to_sq()]
+                    .get_bb_index()
+                    .map(|idx| (idx, mv.to_sq()));
+                let score = -self.quiescence_search(board, -beta, -alpha, ply + 1);
+                board.undo_move();
+
+                if score >= beta {
+                    if !self.is_stopped() {
+                        let bounds = (original_alpha, beta);
+                        self.store_in_tt_table(board.hash_key, 0, ply, beta, bounds, mv);
                     }
-                } else {
-                    board.unmove_piece(sm.mv, captured);
+                    return beta;
+                }
+                if score > alpha {
+                    alpha = score;
+                    best_move = mv;
                 }
+            } else {
+                board.undo_move();
             }
-            alpha
         }
+
+        if !self.is_stopped() {
+            let bounds = (original_alpha, beta);
+            self.store_in_tt_table(board.hash_key, 0, ply, alpha, bounds, best_move);
+        }
+        alpha
     }
+}
+
+impl Searcher for Engine {
+    fn search(&mut self, board: &mut Board, limits: &SearchLimits) -> SearchResult {
+        Engine::search(self, board, limits)
+    }
+
+    fn new_game(&mut self) {
+        Engine::new_game(self)
+    }
+
+    fn stop_token(&self) -> StopToken {
+        self.stop.clone()
+    }
+}
+
+/// Mate scores are stored relative to the node rather than the root, so that a
+/// transposition reached at a different ply still reports the right distance.
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_VALUE - MAX_PLY as i32 {
+        score + ply as i32
+    } else if score < -MATE_VALUE + MAX_PLY as i32 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+/// Converts a score read from the TT back to be relative to the root.
+pub fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_VALUE - MAX_PLY as i32 {
+        score - ply as i32
+    } else if score < -MATE_VALUE + MAX_PLY as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// Formats a score for a UCI `info` line, as `mate N` in moves when it is a mate score.
+fn format_uci_score(score: i32) -> String {
+    if score > MATE_VALUE - MAX_PLY as i32 {
+        format!("mate {}", (MATE_VALUE - score + 1) / 2)
+    } else if score < -MATE_VALUE + MAX_PLY as i32 {
+        format!("mate {}", -(MATE_VALUE + score) / 2)
+    } else {
+        format!("cp {}", score)
+    }
+}
@@ -1,339 +1,726 @@
 //! Evaluates a board position and returns a score.
 
+mod endgame;
+mod pawns;
+pub mod params;
 mod psts;
+mod score;
+
+pub use score::Score;
 
 use crate::move_generator;
 use crate::move_generator::sq_to_idx;
-use crate::bitboard::{self, Board};
+use crate::bitboard::{self, Bitboard, Board};
 use crate::config::Config;
 use crate::constants::{Piece, Player};
+use std::fmt;
 
 
 // --- Piece Values ---
+// The defaults; the values in use come from `params`.
 pub const MATERIAL_VALUES: [i32; 8] = [0, 10000, 200, 200, 450, 900, 500, 100]; // Indexed by Piece type (abs value)
 
+/// The material value of `piece`, from the loaded parameters.
+pub fn material_value(piece: Piece) -> i32 {
+    params::params().material[piece.abs_val() as usize]
+}
+
+// `Piece::Empty` gets the king's tables, but should be unreachable.
 pub fn get_pst_mg(p: Piece) -> &'static [[i32; 9]; 10] {
-    match p {
-        Piece::RKing | Piece::BKing => &psts::KING_PST_MG,
-        Piece::RGuard | Piece::BGuard => &psts::GUARD_PST_MG,
-        Piece::RBishop | Piece::BBishop => &psts::BISHOP_PST_MG,
-        Piece::RHorse | Piece::BHorse => &psts::HORSE_PST_MG,
-        Piece::RRook | Piece::BRook => &psts::ROOK_PST_MG,
-        Piece::RCannon | Piece::BCannon => &psts::CANNON_PST_MG,
-        Piece::RPawn | Piece::BPawn => &psts::PAWN_PST_MG,
-        Piece::Empty => &psts::KING_PST_MG, // Should be unreachable
-    }
+    &params::params().pst_mg[params::table_index(p)]
 }
 
 pub fn get_pst_eg(p: Piece) -> &'static [[i32; 9]; 10] {
-    match p {
-        Piece::RPawn | Piece::BPawn => &psts::PAWN_PST_EG,
-        _ => get_pst_mg(p),
-    }
+    &params::params().pst_eg[params::table_index(p)]
 }
 
-/// Returns the midgame and endgame PST scores for a given piece at a given square.
-pub fn get_pst_scores(piece: Piece, sq: usize) -> (i32, i32) {
+/// Returns the material and PST score of a given piece at a given square, from Red's
+/// perspective.
+pub fn psq_score(piece: Piece, sq: usize) -> Score {
     let player = piece.player().unwrap();
     let r = sq / 9;
     let c = sq % 9;
 
-    // Map board coordinates to PST coordinates (from Red's perspective)
-    let pst_r = 9 - r;
-    let pst_c = 8 - c;
+    // Map board coordinates to PST coordinates (from the piece owner's perspective)
+    let (pst_r, pst_c) = if player == Player::Red { (9 - r, 8 - c) } else { (r, c) };
 
     let mg_table = get_pst_mg(piece);
     let eg_table = get_pst_eg(piece);
 
-    let mg_pst = mg_table[pst_r][pst_c];
-    let eg_pst = eg_table[pst_r][pst_c];
+    let score = Score::flat(material_value(piece)) + Score::new(mg_table[pst_r][pst_c], eg_table[pst_r][pst_c]);
 
     // Return score from Red's perspective
-    if player == Player::Red {
-        (mg_pst, eg_pst)
-    } else {
-        (-mg_pst, -eg_pst)
-    }
+    if player == Player::Red { score } else { -score }
 }
 
-/// Calculates the full material and PST scores from scratch.
+/// Calculates the full material and PST score from scratch, see `Board::psq_score`.
 /// This is intended to be called only once when the board is set up.
-pub fn calculate_full_scores(board: &Board) -> (i32, i32, i32) {
-    let material_score = calculate_material_score(board);
-    let (mg_pst_score, eg_pst_score) = calculate_pst_scores(board);
-    (material_score, mg_pst_score, eg_pst_score)
+pub fn calculate_psq_score(board: &Board) -> Score {
+    let red = Score::flat(material_score(board, Player::Red)) + pst_scores(board, Player::Red);
+    let black = Score::flat(material_score(board, Player::Black)) + pst_scores(board, Player::Black);
+    red - black
 }
 
-fn calculate_material_score(board: &Board) -> i32 {
+/// The material of `player`.
+fn material_score(board: &Board, player: Player) -> i32 {
     let mut material_score = 0;
     for i in 1..=7 {
-        let piece = Piece::from_abs(i);
-        let red_piece = piece;
-        let black_piece = Piece::from_abs(-(i as i8));
-        material_score += bitboard::popcount(board.piece_bitboards[red_piece.get_bb_index().unwrap()]) as i32 * MATERIAL_VALUES[i as usize];
-        material_score -= bitboard::popcount(board.piece_bitboards[black_piece.get_bb_index().unwrap()]) as i32 * MATERIAL_VALUES[i as usize];
+        let piece = player_piece(Piece::from_abs(i), player);
+        material_score += board.piece_count(piece) as i32 * material_value(piece);
     }
     material_score
 }
 
-fn calculate_pst_scores(board: &Board) -> (i32, i32) {
-    let mut mg_pst_score = 0;
-    let mut eg_pst_score = 0;
+/// The midgame and endgame PST scores of the pieces of `player`, from their own perspective.
+fn pst_scores(board: &Board, player: Player) -> Score {
+    let mut pst_score = Score::ZERO;
 
     for i in 0..14 {
-        let mut piece_bb = board.piece_bitboards[i];
-        if piece_bb == 0 { continue; }
+        let piece_bb = board.piece_bitboards[i];
+        if piece_bb.is_empty() { continue; }
         let piece_type = board.board[piece_bb.trailing_zeros() as usize];
-        let player = piece_type.player().unwrap();
+        if piece_type.player() != Some(player) { continue; }
 
         let mg_table = get_pst_mg(piece_type);
         let eg_table = get_pst_eg(piece_type);
 
-        while piece_bb != 0 {
-            let sq = piece_bb.trailing_zeros() as usize;
+        for sq in piece_bb {
             let r = sq / 9; let c = sq % 9;
 
             let (pst_r, pst_c) = if player == Player::Red { (9 - r, 8 - c) } else { (r, c) };
 
-            let mg_pst = mg_table[pst_r][pst_c];
-            let eg_pst = eg_table[pst_r][pst_c];
-
-            if player == Player::Red {
-                mg_pst_score += mg_pst;
-                eg_pst_score += eg_pst;
-            } else {
-                mg_pst_score -= mg_pst;
-                eg_pst_score -= eg_pst;
-            }
-            piece_bb &= !crate::bitboard::SQUARE_MASKS[sq];
+            pst_score += Score::new(mg_table[pst_r][pst_c], eg_table[pst_r][pst_c]);
         }
     }
-    (mg_pst_score, eg_pst_score)
+    pst_score
 }
 
+/// Returns `piece` (given as the Red piece) in the colour of `player`.
+fn player_piece(piece: Piece, player: Player) -> Piece {
+    if player == Player::Red { piece } else { Piece::from_abs(-(piece as i8)) }
+}
 
-pub fn evaluate(board: &Board, config: &Config) -> i32 {
+/// The bitboard of `piece` (given as the Red piece) for `player`.
+fn pieces(board: &Board, piece: Piece, player: Player) -> bitboard::Bitboard {
+    board.piece_bitboards[player_piece(piece, player).get_bb_index().unwrap()]
+}
+
+// --- Game Phase ---
+// The phase is the material of the guards, bishops, horses, rooks and cannons on the
+// board. Scores are fully midgame from `OPENING_PHASE` up, and fully endgame at 0.
+pub const OPENING_PHASE: i32 = (900 + 450 + 500) * 2 + (200 + 200) * 2;
+
+/// The contribution of `piece` to the game phase.
+pub fn phase_value(piece: Piece) -> i32 {
+    match piece.abs_val() {
+        i @ 2..=6 => MATERIAL_VALUES[i as usize],
+        _ => 0,
+    }
+}
+
+/// Calculates the game phase from scratch, see `Board::phase`.
+pub fn calculate_phase(board: &Board) -> i32 {
+    board.board.iter().map(|&piece| phase_value(piece)).sum()
+}
+
+/// The game phase of `board`, capped at `OPENING_PHASE`.
+fn phase(board: &Board) -> i32 {
+    board.phase.min(OPENING_PHASE)
+}
+
+/// Blends the midgame and endgame halves of `score` by `phase`.
+fn taper(score: Score, phase: i32) -> i32 {
+    (score.mg() * phase + score.eg() * (OPENING_PHASE - phase)) / OPENING_PHASE
+}
+
+/// An evaluation term computed for one player, positive when it favours that player.
+type TermFn = fn(&Board, &Config, Player) -> Score;
+
+/// The terms calculated on the fly, on top of the incrementally updated material and PST.
+const DYNAMIC_TERMS: [(&str, TermFn); 11] = [
+    ("mobility", calculate_mobility_score),
+    ("trapped pieces", calculate_trapped_piece_score),
+    ("patterns", calculate_pattern_score),
+    ("king safety", calculate_king_safety_score),
+    ("exposed king", calculate_exposed_king_score),
+    ("king opposition", calculate_king_opposition_score),
+    ("dynamic bonus", calculate_dynamic_bonus_score),
+    ("rook placement", calculate_rook_placement_score),
+    ("file pressure", calculate_file_pressure_score),
+    ("pawn structure", calculate_pawn_structure_score),
+    ("defensive structure", calculate_defensive_structure_score),
+];
+
+/// The incrementally updated material and PST score, from Red's point of view.
+fn material_and_pst_score(board: &Board) -> i32 {
     // --- Tapered Evaluation ---
     // This blends the midgame and endgame scores based on the material on the board.
-    const OPENING_PHASE_MATERIAL: i32 = (900 + 450 + 500) * 2 + (200 + 200) * 2;
-    let mut current_phase_material = 0;
-    for i in 2..=6 { // Major pieces
-        let red_piece = Piece::from_abs(i);
-        let black_piece = Piece::from_abs(-(i as i8));
-        current_phase_material += bitboard::popcount(board.piece_bitboards[red_piece.get_bb_index().unwrap()]) as i32 * MATERIAL_VALUES[i as usize];
-        current_phase_material += bitboard::popcount(board.piece_bitboards[black_piece.get_bb_index().unwrap()]) as i32 * MATERIAL_VALUES[i as usize];
+    taper(board.psq_score, phase(board))
+}
+
+pub fn evaluate(board: &Board, config: &Config) -> i32 {
+    // The less expensive, dynamic scores are still calculated on the fly.
+    let dynamic_score: Score = DYNAMIC_TERMS
+        .iter()
+        .map(|(_, term)| term(board, config, Player::Red) - term(board, config, Player::Black))
+        .sum();
+
+    let phase = phase(board);
+    let final_score = endgame::evaluate_endgame(board, taper(board.psq_score + dynamic_score, phase));
+    let score = if board.player_to_move == Player::Red { final_score } else { -final_score };
+    score + taper(config.tempo_bonus, phase)
+}
+
+/// Lazy evaluation: returns the material and PST score alone, for the side to move, if
+/// it is so far outside [alpha, beta] that the dynamic terms are unlikely to bring it
+/// back in. Returns `None` when the full `evaluate` is needed.
+pub fn lazy_score(board: &Board, config: &Config, alpha: i32, beta: i32) -> Option<i32> {
+    let score = material_and_pst_score(board);
+    let score = if board.player_to_move == Player::Red { score } else { -score } + taper(config.tempo_bonus, phase(board));
+    if score - config.lazy_eval_margin >= beta || score + config.lazy_eval_margin <= alpha {
+        Some(score)
+    } else {
+        None
     }
-    let phase_weight = (current_phase_material as f64 / OPENING_PHASE_MATERIAL as f64).min(1.0);
+}
 
-    let pst_score = (board.mg_pst_score as f64 * phase_weight + board.eg_pst_score as f64 * (1.0 - phase_weight)) as i32;
-    let material_score = board.material_score;
+/// The number of entries in an `EvalCache`.
+const EVAL_CACHE_SIZE: usize = 1 << 16;
 
-    // The less expensive, dynamic scores are still calculated on the fly.
-    let mobility_score = calculate_mobility_score(board, config);
-    let pattern_score = calculate_pattern_score(board, config);
-    let king_safety_score = calculate_king_safety_score(board, config);
-    let dynamic_bonus_score = calculate_dynamic_bonus_score(board, config);
-    let rook_placement_score = calculate_rook_placement_score(board, config);
-
-    let final_score = material_score + pst_score + mobility_score + pattern_score + king_safety_score + dynamic_bonus_score + rook_placement_score;
-    if board.player_to_move == Player::Red { final_score } else { -final_score }
-}
-
-/// Calculates a score bonus for rooks on open or semi-open files.
-fn calculate_rook_placement_score(board: &Board, config: &Config) -> i32 {
-    let mut score = 0;
-    let red_pawns_bb = board.piece_bitboards[Piece::RPawn.get_bb_index().unwrap()];
-    let black_pawns_bb = board.piece_bitboards[Piece::BPawn.get_bb_index().unwrap()];
-
-    for c in 0..9 {
-        let file_mask = (0..10).fold(0, |acc, r| acc | bitboard::SQUARE_MASKS[r * 9 + c]);
-        
-        let red_pawns_on_file = (red_pawns_bb & file_mask) != 0;
-        let black_pawns_on_file = (black_pawns_bb & file_mask) != 0;
-
-        // Red Rooks
-        if !red_pawns_on_file {
-            let red_rooks_on_file = (board.piece_bitboards[Piece::RRook.get_bb_index().unwrap()] & file_mask) != 0;
-            if red_rooks_on_file {
-                if !black_pawns_on_file { // Open file
-                    score += config.bonus_rook_on_open_file;
-                } else { // Semi-open file for Red
-                    score += config.bonus_rook_on_semi_open_file;
-                }
-            }
+#[derive(Debug, Clone, Copy, Default)]
+struct EvalCacheEntry {
+    hash_key: u64,
+    score: i32,
+}
+
+/// Caches `evaluate` by hash key, as the search evaluates the same positions over and
+/// over, especially in quiescence search. The scores depend on the `Config` they were
+/// computed with, so the cache must be cleared whenever that changes.
+pub struct EvalCache {
+    entries: Vec<EvalCacheEntry>,
+}
+
+impl Default for EvalCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EvalCache {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![EvalCacheEntry::default(); EVAL_CACHE_SIZE],
         }
+    }
 
-        // Black Rooks
-        if !black_pawns_on_file {
-            let black_rooks_on_file = (board.piece_bitboards[Piece::BRook.get_bb_index().unwrap()] & file_mask) != 0;
-            if black_rooks_on_file {
-                if !red_pawns_on_file { // Open file
-                    score -= config.bonus_rook_on_open_file;
-                } else { // Semi-open file for Black
-                    score -= config.bonus_rook_on_semi_open_file;
-                }
+    /// Evaluates `board` like `evaluate`, looking the score up first. Falls back to
+    /// `lazy_score` for positions not in the cache; such scores are not stored.
+    pub fn evaluate_lazy(&mut self, board: &Board, config: &Config, alpha: i32, beta: i32) -> i32 {
+        let entry = &self.entries[board.hash_key as usize % EVAL_CACHE_SIZE];
+        if entry.hash_key == board.hash_key {
+            return entry.score;
+        }
+        lazy_score(board, config, alpha, beta).unwrap_or_else(|| self.evaluate(board, config))
+    }
+
+    /// Evaluates `board` like `evaluate`, looking the score up first.
+    pub fn evaluate(&mut self, board: &Board, config: &Config) -> i32 {
+        let entry = &mut self.entries[board.hash_key as usize % EVAL_CACHE_SIZE];
+        if entry.hash_key != board.hash_key {
+            *entry = EvalCacheEntry {
+                hash_key: board.hash_key,
+                score: evaluate(board, config),
+            };
+        }
+        entry.score
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(EvalCacheEntry::default());
+    }
+}
+
+/// One term of an `EvalTrace`, with the share of each player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalTerm {
+    pub name: &'static str,
+    pub red: i32,
+    pub black: i32,
+}
+
+impl EvalTerm {
+    /// The term from Red's point of view.
+    pub fn net(&self) -> i32 {
+        self.red - self.black
+    }
+}
+
+/// A breakdown of `evaluate` into its terms, see `evaluate_trace`.
+#[derive(Debug, Clone)]
+pub struct EvalTrace {
+    /// Material, the midgame, endgame and tapered PST scores, the dynamic terms, then tempo.
+    pub terms: Vec<EvalTerm>,
+    /// The weight of the midgame scores, from 1.0 in the opening to 0.0 in a bare endgame.
+    pub phase_weight: f64,
+    /// The evaluation from Red's point of view. The terms are tapered and rounded one
+    /// by one, so their sum can differ from this by a few points, and more in endings
+    /// recognised by the endgame knowledge, which only applies to the total.
+    pub score: i32,
+}
+
+/// Evaluates `board` like `evaluate`, but reports every term for both players.
+pub fn evaluate_trace(board: &Board, config: &Config) -> EvalTrace {
+    let phase = phase(board);
+    let red_pst = pst_scores(board, Player::Red);
+    let black_pst = pst_scores(board, Player::Black);
+
+    let mut terms = vec![
+        EvalTerm {
+            name: "material",
+            red: material_score(board, Player::Red),
+            black: material_score(board, Player::Black),
+        },
+        EvalTerm { name: "pst mg", red: red_pst.mg(), black: black_pst.mg() },
+        EvalTerm { name: "pst eg", red: red_pst.eg(), black: black_pst.eg() },
+        EvalTerm { name: "pst", red: taper(red_pst, phase), black: taper(black_pst, phase) },
+    ];
+    terms.extend(DYNAMIC_TERMS.iter().map(|&(name, term)| EvalTerm {
+        name,
+        red: taper(term(board, config, Player::Red), phase),
+        black: taper(term(board, config, Player::Black), phase),
+    }));
+    let red_to_move = board.player_to_move == Player::Red;
+    let tempo = taper(config.tempo_bonus, phase);
+    terms.push(EvalTerm {
+        name: "tempo",
+        red: if red_to_move { tempo } else { 0 },
+        black: if red_to_move { 0 } else { tempo },
+    });
+
+    let score = evaluate(board, config);
+    EvalTrace {
+        terms,
+        phase_weight: phase as f64 / OPENING_PHASE as f64,
+        score: if board.player_to_move == Player::Red { score } else { -score },
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<20}{:>8}{:>8}{:>8}", "term", "red", "black", "net")?;
+        for term in &self.terms {
+            writeln!(f, "{:<20}{:>8}{:>8}{:>8}", term.name, term.red, term.black, term.net())?;
+        }
+        writeln!(f, "phase: {:.2}", self.phase_weight)?;
+        write!(f, "total: {} (red's point of view)", self.score)
+    }
+}
+
+/// Calculates a score for the pawn structure of `player`, cached in the pawn hash table.
+fn calculate_pawn_structure_score(board: &Board, config: &Config, player: Player) -> Score {
+    pawns::pawn_structure_scores(board, config)[player.get_bb_idx()]
+}
+
+/// Calculates a score bonus for `player`'s rooks on open or semi-open files.
+fn calculate_rook_placement_score(board: &Board, config: &Config, player: Player) -> Score {
+    let mut score = Score::ZERO;
+    let own_pawns_bb = pieces(board, Piece::RPawn, player);
+    let enemy_pawns_bb = pieces(board, Piece::RPawn, player.opponent());
+    let rooks_bb = pieces(board, Piece::RRook, player);
+
+    for file_mask in bitboard::FILE_MASKS {
+        if (own_pawns_bb & file_mask).is_empty() && !(rooks_bb & file_mask).is_empty() {
+            if (enemy_pawns_bb & file_mask).is_empty() { // Open file
+                score += config.bonus_rook_on_open_file;
+            } else { // Semi-open file
+                score += config.bonus_rook_on_semi_open_file;
             }
         }
     }
     score
 }
 
-/// Calculates a score bonus for specific piece patterns.
-fn calculate_pattern_score(board: &Board, config: &Config) -> i32 {
-    let mut pattern_score = 0;
+/// Calculates a score bonus for `player`'s control of files: both rooks on one file,
+/// and rooks and cannons bearing down on the file of the enemy king.
+fn calculate_file_pressure_score(board: &Board, config: &Config, player: Player) -> Score {
+    let mut score = Score::ZERO;
+    let rooks_bb = pieces(board, Piece::RRook, player);
+    let cannons_bb = pieces(board, Piece::RCannon, player);
+
+    if bitboard::FILE_MASKS.iter().any(|&file_mask| (rooks_bb & file_mask).count_ones() > 1) {
+        score += config.bonus_doubled_rooks;
+    }
+
+    let enemy_king_bb = pieces(board, Piece::RKing, player.opponent());
+    if !enemy_king_bb.is_empty() {
+        let king_file = bitboard::FILE_MASKS[enemy_king_bb.trailing_zeros() as usize % 9];
+        score += config.bonus_rook_on_king_file * (rooks_bb & king_file).count_ones() as i32;
+        score += config.bonus_cannon_on_king_file * (cannons_bb & king_file).count_ones() as i32;
+    }
+
+    score
+}
+
+/// Calculates a score bonus for specific piece patterns of `player`.
+fn calculate_pattern_score(board: &Board, config: &Config, player: Player) -> Score {
+    let mut pattern_score = Score::ZERO;
+    // The enemy's back rank and palace centre.
+    let (back_rank, palace_heart) = if player == Player::Red { (0, 4) } else { (9, 85) };
 
-    // --- Red Player Patterns ---
     // Bottom Cannon: A cannon on the bottom rank is a strong attacking piece.
-    let red_cannons = board.piece_bitboards[Piece::RCannon.get_bb_index().unwrap()];
-    if (red_cannons & bitboard::RANK_MASKS[0]) != 0 {
+    let cannons = pieces(board, Piece::RCannon, player);
+    if !(cannons & bitboard::RANK_MASKS[back_rank]).is_empty() {
         pattern_score += config.bonus_bottom_cannon;
     }
     // Palace Heart Horse: A horse in the center of the palace is a strong defensive and offensive piece.
-    let red_horses = board.piece_bitboards[Piece::RHorse.get_bb_index().unwrap()];
-    if (red_horses & bitboard::SQUARE_MASKS[4]) != 0 {
+    let horses = pieces(board, Piece::RHorse, player);
+    if !(horses & bitboard::SQUARE_MASKS[palace_heart]).is_empty() {
         pattern_score += config.bonus_palace_heart_horse;
     }
 
-    // --- Black Player Patterns ---
-    // Bottom Cannon
-    let black_cannons = board.piece_bitboards[Piece::BCannon.get_bb_index().unwrap()];
-    if (black_cannons & bitboard::RANK_MASKS[9]) != 0 {
-        pattern_score -= config.bonus_bottom_cannon;
-    }
-    // Palace Heart Horse
-    let black_horses = board.piece_bitboards[Piece::BHorse.get_bb_index().unwrap()];
-    if (black_horses & bitboard::SQUARE_MASKS[85]) != 0 {
-        pattern_score -= config.bonus_palace_heart_horse;
-    }
-
     pattern_score
+        + cannon_pattern_score(board, config, player, cannons)
+        + horse_cannon_score(board, config, player, horses, cannons)
 }
 
-/// Calculates a score based on the safety of each player's king.
-fn calculate_king_safety_score(board: &Board, config: &Config) -> i32 {
-    let mut king_safety_score = 0;
-
-    // Red player's king safety: Penalize for each missing guard.
-    let red_guard_count =
-        bitboard::popcount(board.piece_bitboards[Piece::RGuard.get_bb_index().unwrap()]);
-    if red_guard_count < 2 {
-        king_safety_score -= (2 - red_guard_count as i32) * config.king_safety_penalty_per_guard;
+/// Scores horses and cannons of `player` working together against the enemy palace.
+fn horse_cannon_score(
+    board: &Board,
+    config: &Config,
+    player: Player,
+    horses: bitboard::Bitboard,
+    cannons: bitboard::Bitboard,
+) -> Score {
+    let enemy_king = pieces(board, Piece::RKing, player.opponent());
+    if horses.is_empty() || cannons.is_empty() || enemy_king.is_empty() {
+        return Score::ZERO;
+    }
+    let mut score = Score::ZERO;
+    let occupied = board.occupied_bitboard();
+    let king_sq = enemy_king.trailing_zeros() as usize;
+    let palace_rows = if player == Player::Red { 0..=2 } else { 7..=9 };
+    let palace = palace_rows.fold(Bitboard::EMPTY, |acc, r| acc | bitboard::RANK_MASKS[r])
+        & (bitboard::FILE_MASKS[3] | bitboard::FILE_MASKS[4] | bitboard::FILE_MASKS[5]);
+
+    let attacks_palace = |piece: Piece, bb: bitboard::Bitboard| {
+        for sq in bb {
+            if !(attacks_from(player_piece(piece, player), sq, occupied) & palace).is_empty() {
+                return true;
+            }
+        }
+        false
+    };
+    if attacks_palace(Piece::RHorse, horses) && attacks_palace(Piece::RCannon, cannons) {
+        score += config.bonus_horse_cannon_palace_attack;
     }
 
-    // Black player's king safety: Penalize for each missing guard.
-    let black_guard_count =
-        bitboard::popcount(board.piece_bitboards[Piece::BGuard.get_bb_index().unwrap()]);
-    if black_guard_count < 2 {
-        king_safety_score += (2 - black_guard_count as i32) * config.king_safety_penalty_per_guard;
+    // Horse Behind Cannon (马后炮): the cannon checks through its own horse, which
+    // also covers the king's escape squares.
+    for sq in cannons {
+        if sq / 9 == king_sq / 9 || sq % 9 == king_sq % 9 {
+            let screens = bitboard::utils::between(sq, king_sq) & occupied;
+            if screens.count_ones() == 1 && !(screens & horses).is_empty() {
+                score += config.bonus_horse_cannon_check;
+            }
+        }
     }
 
-    king_safety_score
+    score
 }
 
-/// Calculates a dynamic score bonus for attacking a weakened palace.
-fn calculate_dynamic_bonus_score(board: &Board, config: &Config) -> i32 {
-    let mut dynamic_score = 0;
+/// Scores the classic cannon configurations of `player`.
+fn cannon_pattern_score(board: &Board, config: &Config, player: Player, cannons: bitboard::Bitboard) -> Score {
+    let mut score = Score::ZERO;
+    let occupied = board.occupied_bitboard();
+
+    // Doubled Cannons (重炮): the rear cannon uses the front one as its screen.
+    if bitboard::FILE_MASKS.iter().any(|&file_mask| (cannons & file_mask).count_ones() > 1) {
+        score += config.bonus_doubled_cannons;
+    }
 
-    // --- Red attacking Black's Palace ---
-    let black_defenders =
-        bitboard::popcount(board.piece_bitboards[Piece::BGuard.get_bb_index().unwrap()]);
-    let missing_black_defenders = 2 - black_defenders as i32;
-    if missing_black_defenders > 0 {
-        let mut red_attackers = 0;
-        // Define black palace zone
-        for r in 0..=2 {
-            for c in 3..=5 {
-                if move_generator::is_square_attacked_by(board, sq_to_idx(r, c), Player::Red) {
-                    red_attackers += 1;
+    let enemy_king = pieces(board, Piece::RKing, player.opponent());
+    let enemy_rooks = pieces(board, Piece::RRook, player.opponent());
+    let enemy_palace_rows = if player == Player::Red { 0..=2 } else { 7..=9 };
+    let enemy_palace = enemy_palace_rows.fold(Bitboard::EMPTY, |acc, r| acc | bitboard::RANK_MASKS[r]);
+
+    for sq in cannons {
+
+        // Palace Cannon (空头炮): facing the king with an empty file between them, or
+        // through a single screen inside the palace, which pins the palace down.
+        if !enemy_king.is_empty() {
+            let king_sq = enemy_king.trailing_zeros() as usize;
+            if king_sq % 9 == sq % 9 {
+                let between = bitboard::utils::between(sq, king_sq) & occupied;
+                if between.is_empty() || (between.count_ones() == 1 && !(between & enemy_palace).is_empty()) {
+                    score += config.bonus_palace_cannon;
                 }
             }
         }
-        dynamic_score +=
-            red_attackers * missing_black_defenders * config.dynamic_bonus_attack_per_missing_defender;
-    }
-
-    // --- Black attacking Red's Palace ---
-    let red_defenders =
-        bitboard::popcount(board.piece_bitboards[Piece::RGuard.get_bb_index().unwrap()]);
-    let missing_red_defenders = 2 - red_defenders as i32;
-    if missing_red_defenders > 0 {
-        let mut black_attackers = 0;
-        // Define red palace zone
-        for r in 7..=9 {
-            for c in 3..=5 {
-                if move_generator::is_square_attacked_by(board, sq_to_idx(r, c), Player::Black) {
-                    black_attackers += 1;
+
+        // A cannon attacking a rook through a screen.
+        if !(move_generator::get_cannon_attacks_bb(sq, occupied) & enemy_rooks).is_empty() {
+            score += config.bonus_cannon_attacks_rook;
+        }
+    }
+
+    score
+}
+
+// --- King Safety ---
+// Attack units added by each enemy piece that attacks the palace, indexed by piece type.
+const ATTACK_UNITS: [i32; 8] = [0, 0, 0, 0, 3, 4, 3, 2];
+// Attack units added by each palace file without a friendly piece in the own half.
+const OPEN_PALACE_FILE_UNITS: i32 = 1;
+// Attack units taken away by each guard and bishop still defending.
+const DEFENDER_UNITS: i32 = 1;
+
+/// The king-safety penalty by attack units. It grows quadratically, as attackers
+/// working together are far more dangerous than the same attackers on their own.
+const KING_SAFETY_TABLE: [i32; 32] = {
+    let mut table = [0; 32];
+    let mut i = 0;
+    while i < 32 {
+        let penalty = (i * (i + 1)) as i32;
+        table[i] = if penalty < 600 { penalty } else { 600 };
+        i += 1;
+    }
+    table
+};
+
+/// The squares attacked by the piece on `sq`, for the pieces that can attack a palace.
+fn attacks_from(piece: Piece, sq: usize, occupied: bitboard::Bitboard) -> bitboard::Bitboard {
+    match piece {
+        Piece::RRook | Piece::BRook => move_generator::get_rook_moves_bb(sq, occupied),
+        Piece::RCannon | Piece::BCannon => move_generator::get_cannon_attacks_bb(sq, occupied),
+        Piece::RHorse | Piece::BHorse => {
+            let mut attacks = Bitboard::EMPTY;
+            for to_sq in move_generator::ATTACK_TABLES.horse[sq] {
+                let leg_sq = move_generator::ATTACK_TABLES.horse_legs[sq][to_sq];
+                if (occupied & bitboard::SQUARE_MASKS[leg_sq]).is_empty() {
+                    attacks |= bitboard::SQUARE_MASKS[to_sq];
                 }
             }
+            attacks
+        }
+        Piece::RPawn | Piece::BPawn => {
+            let player = piece.player().unwrap();
+            move_generator::ATTACK_TABLES.pawn[player.get_bb_idx()][sq]
         }
-        dynamic_score -=
-            black_attackers * missing_red_defenders * config.dynamic_bonus_attack_per_missing_defender;
+        _ => Bitboard::EMPTY,
     }
+}
 
-    dynamic_score
+/// The squares attacked by `player`'s rooks, horses, cannons and pawns.
+fn attacked_squares(board: &Board, player: Player, occupied: bitboard::Bitboard) -> bitboard::Bitboard {
+    let mut attacks = Bitboard::EMPTY;
+    for piece in [Piece::RRook, Piece::RHorse, Piece::RCannon, Piece::RPawn] {
+        let player_piece = player_piece(piece, player);
+        for sq in pieces(board, piece, player) {
+            attacks |= attacks_from(player_piece, sq, occupied);
+        }
+    }
+    attacks
 }
 
-/// Calculates a score based on the mobility of each player's pieces.
-fn calculate_mobility_score(board: &Board, config: &Config) -> i32 {
-    let mut mobility_score = 0;
+/// Calculates a score based on the safety of `player`'s king, with an attack-units model:
+/// enemy pieces attacking the palace add units by type, open palace files add more,
+/// guards and bishops take some away, and the total is mapped through `KING_SAFETY_TABLE`.
+fn calculate_king_safety_score(board: &Board, config: &Config, player: Player) -> Score {
+    let enemy = player.opponent();
     let occupied = board.occupied_bitboard();
+    let (palace_rows, own_half) = if player == Player::Red { (7..=9, 5..=9) } else { (0..=2, 0..=4) };
+    let palace_ranks = palace_rows.fold(Bitboard::EMPTY, |acc, r| acc | bitboard::RANK_MASKS[r]);
+    let own_half = own_half.fold(Bitboard::EMPTY, |acc, r| acc | bitboard::RANK_MASKS[r]);
+    let palace_files = bitboard::FILE_MASKS[3] | bitboard::FILE_MASKS[4] | bitboard::FILE_MASKS[5];
+    let palace = palace_ranks & palace_files;
+
+    let mut units = 0;
+    for piece in [Piece::RRook, Piece::RHorse, Piece::RCannon, Piece::RPawn] {
+        let enemy_piece = player_piece(piece, enemy);
+        for sq in pieces(board, piece, enemy) {
+            if !(attacks_from(enemy_piece, sq, occupied) & palace).is_empty() {
+                units += ATTACK_UNITS[piece.abs_val() as usize];
+            }
+        }
+    }
 
-    for player in [Player::Red, Player::Black] {
-        let player_sign = if player == Player::Red { 1 } else { -1 };
-        let own_pieces_bb = board.color_bitboards[player.get_bb_idx()];
-
-        // Rook mobility
-        let rook_type = if player == Player::Red {
-            Piece::RRook
-        } else {
-            Piece::BRook
-        };
-        let mut rooks_bb = board.piece_bitboards[rook_type.get_bb_index().unwrap()];
-        while rooks_bb != 0 {
-            let sq = rooks_bb.trailing_zeros() as usize;
-            let moves_bb = move_generator::get_rook_moves_bb(sq, occupied) & !own_pieces_bb;
-            mobility_score +=
-                bitboard::popcount(moves_bb) as i32 * config.mobility_bonus_rook * player_sign;
-            rooks_bb &= !bitboard::SQUARE_MASKS[sq];
+    let own_pieces = board.color_bitboards[player.get_bb_idx()] & !pieces(board, Piece::RKing, player);
+    for file in 3..=5 {
+        if (own_pieces & own_half & bitboard::FILE_MASKS[file]).is_empty() {
+            units += OPEN_PALACE_FILE_UNITS;
         }
+    }
 
-        // Horse mobility
-        let horse_type = if player == Player::Red {
-            Piece::RHorse
-        } else {
-            Piece::BHorse
-        };
-        let mut horses_bb = board.piece_bitboards[horse_type.get_bb_index().unwrap()];
-        while horses_bb != 0 {
-            let sq = horses_bb.trailing_zeros() as usize;
-            let mut potential_moves = move_generator::ATTACK_TABLES.horse[sq] & !own_pieces_bb;
-            let mut count = 0;
-            while potential_moves != 0 {
-                let to_sq = potential_moves.trailing_zeros() as usize;
-                let leg_sq = move_generator::ATTACK_TABLES.horse_legs[sq][to_sq];
-                if (occupied & bitboard::SQUARE_MASKS[leg_sq]) == 0 {
-                    count += 1;
-                }
-                potential_moves &= !bitboard::SQUARE_MASKS[to_sq];
+    let defenders = pieces(board, Piece::RGuard, player) | pieces(board, Piece::RBishop, player);
+    units -= defenders.count_ones() as i32 * DEFENDER_UNITS;
+
+    let units = units.clamp(0, KING_SAFETY_TABLE.len() as i32 - 1) as usize;
+    Score::flat(-KING_SAFETY_TABLE[units] * config.king_safety_weight / 100)
+}
+
+/// Scores the guards and bishops of `player` as a defensive structure: bishops protecting
+/// each other and the central point of their half, guards on their home squares or the
+/// palace heart, and bishop moves cut off at the eye.
+fn calculate_defensive_structure_score(board: &Board, config: &Config, player: Player) -> Score {
+    let enemy = player.opponent();
+    let occupied = board.occupied_bitboard();
+    // The palace heart, the guards' home squares and the bishops' central point.
+    let (palace_heart, guard_homes, central_point, own_half) = if player == Player::Red {
+        (76, [84, 86], 67, move_generator::ATTACK_TABLES.red_half_mask)
+    } else {
+        (13, [3, 5], 22, move_generator::ATTACK_TABLES.black_half_mask)
+    };
+    let mut score = Score::ZERO;
+
+    let ideal_guard_squares = bitboard::SQUARE_MASKS[palace_heart]
+        | bitboard::SQUARE_MASKS[guard_homes[0]]
+        | bitboard::SQUARE_MASKS[guard_homes[1]];
+    let guards = pieces(board, Piece::RGuard, player);
+    score += config.bonus_guard_on_ideal_square * (guards & ideal_guard_squares).count_ones() as i32;
+
+    let bishops = pieces(board, Piece::RBishop, player);
+    if bishops.is_empty() {
+        return score;
+    }
+    let enemy_attacks = attacked_squares(board, enemy, occupied);
+    let mut covered = Bitboard::EMPTY;
+    for sq in bishops {
+        for to_sq in move_generator::ATTACK_TABLES.bishop[sq] & own_half {
+            let eye_sq = move_generator::ATTACK_TABLES.bishop_legs[sq][to_sq];
+            if !((occupied | enemy_attacks) & bitboard::SQUARE_MASKS[eye_sq]).is_empty() {
+                score -= config.penalty_bishop_eye;
+            } else {
+                covered |= bitboard::SQUARE_MASKS[to_sq];
             }
-            mobility_score += count * config.mobility_bonus_horse * player_sign;
-            horses_bb &= !bitboard::SQUARE_MASKS[sq];
         }
+    }
+    if bishops.count_ones() == 2
+        && covered & bishops == bishops
+        && !((covered | bishops) & bitboard::SQUARE_MASKS[central_point]).is_empty()
+    {
+        score += config.bonus_connected_bishops;
+    }
 
-        // Cannon mobility
-        let cannon_type = if player == Player::Red {
-            Piece::RCannon
-        } else {
-            Piece::BCannon
-        };
-        let mut cannons_bb = board.piece_bitboards[cannon_type.get_bb_index().unwrap()];
-        while cannons_bb != 0 {
-            let sq = cannons_bb.trailing_zeros() as usize;
-            let moves_bb = move_generator::get_cannon_moves_bb(sq, occupied) & !own_pieces_bb;
-            mobility_score +=
-                bitboard::popcount(moves_bb) as i32 * config.mobility_bonus_cannon * player_sign;
-            cannons_bb &= !bitboard::SQUARE_MASKS[sq];
+    score
+}
+
+/// Penalizes `player`'s king when no friendly piece stands in front of it on its file,
+/// leaving it open to enemy rooks and cannons. Cheaper than the palace attack scan of
+/// the king-safety term, it flags the weakness before any attacker arrives.
+fn calculate_exposed_king_score(board: &Board, config: &Config, player: Player) -> Score {
+    let king = pieces(board, Piece::RKing, player);
+    if king.is_empty() {
+        return Score::ZERO;
+    }
+    let king_sq = king.trailing_zeros() as usize;
+    let (row, file) = (king_sq / 9, king_sq % 9);
+    let front_rows = if player == Player::Red { 0..row } else { row + 1..10 };
+    let front = front_rows.fold(Bitboard::EMPTY, |acc, r| acc | bitboard::RANK_MASKS[r]) & bitboard::FILE_MASKS[file];
+    if !(board.color_bitboards[player.get_bb_idx()] & front).is_empty() {
+        return Score::ZERO;
+    }
+
+    let enemy = player.opponent();
+    let heavy_pieces = pieces(board, Piece::RRook, enemy) | pieces(board, Piece::RCannon, enemy);
+    -(config.penalty_exposed_king * heavy_pieces.count_ones() as i32)
+}
+
+/// Scores `player`'s king controlling its file up to the enemy back rank. Kings may
+/// not face each other, so the enemy king cannot step onto that file, which in endings
+/// often decides whether a mate works.
+fn calculate_king_opposition_score(board: &Board, config: &Config, player: Player) -> Score {
+    let king = pieces(board, Piece::RKing, player);
+    if king.is_empty() {
+        return Score::ZERO;
+    }
+    let king_sq = king.trailing_zeros() as usize;
+    let enemy_back_rank_sq = if player == Player::Red { king_sq % 9 } else { 81 + king_sq % 9 };
+    let file_ahead = bitboard::utils::between(king_sq, enemy_back_rank_sq) | bitboard::SQUARE_MASKS[enemy_back_rank_sq];
+    if !(board.occupied_bitboard() & file_ahead).is_empty() {
+        return Score::ZERO;
+    }
+    config.bonus_king_file_control
+}
+
+/// Calculates a dynamic score bonus for `player` attacking a weakened enemy palace.
+fn calculate_dynamic_bonus_score(board: &Board, config: &Config, player: Player) -> Score {
+    let defenders = pieces(board, Piece::RGuard, player.opponent()).count_ones();
+    let missing_defenders = 2 - defenders as i32;
+    if missing_defenders <= 0 {
+        return Score::ZERO;
+    }
+
+    let mut attackers = 0;
+    // Define the enemy palace zone
+    let palace_rows = if player == Player::Red { 0..=2 } else { 7..=9 };
+    for r in palace_rows {
+        for c in 3..=5 {
+            if move_generator::is_square_attacked_by(board, sq_to_idx(r, c), player) {
+                attackers += 1;
+            }
         }
     }
+    config.dynamic_bonus_attack_per_missing_defender * (attackers * missing_defenders)
+}
+
+// A rook with at most this many moves is trapped.
+const TRAPPED_ROOK_MOBILITY: u32 = 1;
+
+/// Penalizes `player`'s rooks and horses that can hardly move. Mobility alone undervalues
+/// them, as a shut-in rook or a horse stuck on the rim is often lost or useless.
+fn calculate_trapped_piece_score(board: &Board, config: &Config, player: Player) -> Score {
+    let mut score = Score::ZERO;
+    let occupied = board.occupied_bitboard();
+    let own_pieces_bb = board.color_bitboards[player.get_bb_idx()];
+
+    for sq in pieces(board, Piece::RRook, player) {
+        let moves_bb = move_generator::get_rook_moves_bb(sq, occupied) & !own_pieces_bb;
+        if moves_bb.count_ones() <= TRAPPED_ROOK_MOBILITY {
+            score -= config.penalty_trapped_rook;
+        }
+    }
+
+    let rim = bitboard::FILE_MASKS[0] | bitboard::FILE_MASKS[8];
+    let horse = player_piece(Piece::RHorse, player);
+    for sq in pieces(board, Piece::RHorse, player) & rim {
+        if (attacks_from(horse, sq, occupied) & !own_pieces_bb).is_empty() {
+            score -= config.penalty_trapped_horse;
+        }
+    }
+
+    score
+}
+
+/// Calculates a score based on the mobility of `player`'s pieces.
+fn calculate_mobility_score(board: &Board, config: &Config, player: Player) -> Score {
+    let mut mobility_score = Score::ZERO;
+    let occupied = board.occupied_bitboard();
+    let own_pieces_bb = board.color_bitboards[player.get_bb_idx()];
+
+    // Rook mobility
+    for sq in pieces(board, Piece::RRook, player) {
+        let moves_bb = move_generator::get_rook_moves_bb(sq, occupied) & !own_pieces_bb;
+        mobility_score += config.mobility_bonus_rook * moves_bb.count_ones() as i32;
+    }
+
+    // Horse mobility
+    for sq in pieces(board, Piece::RHorse, player) {
+        let potential_moves = move_generator::ATTACK_TABLES.horse[sq] & !own_pieces_bb;
+        let mut count = 0;
+        for to_sq in potential_moves {
+            let leg_sq = move_generator::ATTACK_TABLES.horse_legs[sq][to_sq];
+            if (occupied & bitboard::SQUARE_MASKS[leg_sq]).is_empty() {
+                count += 1;
+            }
+        }
+        mobility_score += config.mobility_bonus_horse * count;
+    }
+
+    // Cannon mobility
+    for sq in pieces(board, Piece::RCannon, player) {
+        let moves_bb = move_generator::get_cannon_moves_bb(sq, occupied) & !own_pieces_bb;
+        mobility_score += config.mobility_bonus_cannon * moves_bb.count_ones() as i32;
+    }
+
     mobility_score
 }
@@ -0,0 +1,177 @@
+//! Knowledge of common endings, which the general evaluation scores wrongly.
+//!
+//! Recognised endings either get a bonus, when the material count undersells a
+//! known win, or have their score pulled toward a draw. Other drawish material
+//! balances are scaled toward zero afterwards, so the engine does not press for a
+//! win that theory says is not there.
+
+use super::pieces;
+use crate::bitboard::{Bitboard, Board, RANK_MASKS};
+use crate::constants::{Piece, Player};
+
+/// Added for the winning side in an ending recognised as a win.
+const KNOWN_WIN_BONUS: i32 = 200;
+/// Scores are divided by these in endings that are hard to win, or drawn.
+const DRAWISH_DIVISOR: i32 = 4;
+const DRAWN_DIVISOR: i32 = 16;
+
+/// Scale factors for drawish material balances, out of `SCALE_NORMAL`.
+const SCALE_NORMAL: i32 = 64;
+/// An extra horse or cannon, without pawns, against full guards and bishops.
+const SCALE_EXTRA_MINOR: i32 = 32;
+/// The same single piece on both sides, without pawns for the stronger side.
+const SCALE_SAME_PIECES: i32 = 16;
+
+/// The pieces of one player, besides the king.
+#[derive(Debug, Clone, Copy)]
+struct Material {
+    rooks: u32,
+    horses: u32,
+    cannons: u32,
+    pawns: u32,
+    guards: u32,
+    bishops: u32,
+}
+
+impl Material {
+    fn of(board: &Board, player: Player) -> Self {
+        let count = |piece: Piece| board.piece_count(Piece::from_abs(piece as i8 * player as i8));
+        Self {
+            rooks: count(Piece::RRook),
+            horses: count(Piece::RHorse),
+            cannons: count(Piece::RCannon),
+            pawns: count(Piece::RPawn),
+            guards: count(Piece::RGuard),
+            bishops: count(Piece::RBishop),
+        }
+    }
+
+    fn majors(&self) -> u32 {
+        self.rooks + self.horses + self.cannons
+    }
+
+    fn minors(&self) -> u32 {
+        self.horses + self.cannons
+    }
+
+    /// Whether nothing is left but guards and bishops.
+    fn only_defenders(&self) -> bool {
+        self.majors() + self.pawns == 0
+    }
+
+    /// Both guards and both bishops (士象全).
+    fn full_defense(&self) -> bool {
+        self.guards == 2 && self.bishops == 2
+    }
+}
+
+/// Pawns of `player` that can still attack: across the river but not yet on the enemy's
+/// back rank, where a pawn can only move sideways.
+fn active_pawns(board: &Board, player: Player) -> u32 {
+    let (crossed, back_rank) = if player == Player::Red {
+        (0..=4, 0)
+    } else {
+        (5..=9, 9)
+    };
+    let crossed = crossed.fold(Bitboard::EMPTY, |acc, r| acc | RANK_MASKS[r]) & !RANK_MASKS[back_rank];
+    (pieces(board, Piece::RPawn, player) & crossed).count_ones()
+}
+
+/// Adjusts `score`, from Red's point of view, for the ending on `board`.
+pub fn evaluate_endgame(board: &Board, score: i32) -> i32 {
+    let red = Material::of(board, Player::Red);
+    let black = Material::of(board, Player::Black);
+    if red.majors() + black.majors() <= 1
+        && let Some(score) = adjust_for(board, Player::Red, &red, &black, score)
+            .or_else(|| adjust_for(board, Player::Black, &black, &red, score))
+    {
+        return score;
+    }
+
+    let (strong, weak) = if score > 0 {
+        (&red, &black)
+    } else {
+        (&black, &red)
+    };
+    score * scale_factor(strong, weak) / SCALE_NORMAL
+}
+
+/// The scale factor, out of `SCALE_NORMAL`, for the side with `strong` material
+/// against `weak` when the score favours it.
+fn scale_factor(strong: &Material, weak: &Material) -> i32 {
+    if strong.pawns > 0 {
+        return SCALE_NORMAL;
+    }
+    // The defence holds against one extra horse or cannon, e.g. rook and horse
+    // against rook, guards and bishops.
+    if weak.majors() <= 1
+        && strong.rooks == weak.rooks
+        && strong.minors() == weak.minors() + 1
+        && weak.full_defense()
+    {
+        return SCALE_EXTRA_MINOR;
+    }
+    // A single piece against the same piece is rarely enough to win, whatever the
+    // guards and bishops.
+    if strong.majors() == 1
+        && strong.rooks == weak.rooks
+        && strong.horses == weak.horses
+        && strong.cannons == weak.cannons
+    {
+        return SCALE_SAME_PIECES;
+    }
+    SCALE_NORMAL
+}
+
+/// Adjusts `score` for an ending recognised from the point of view of `player`,
+/// who has `own` material against `enemy`.
+fn adjust_for(
+    board: &Board,
+    player: Player,
+    own: &Material,
+    enemy: &Material,
+    score: i32,
+) -> Option<i32> {
+    let sign = if player == Player::Red { 1 } else { -1 };
+    // The drawing rules only pull the score in when it favours `player`.
+    let favoured = sign * score > 0;
+
+    // Guards and bishops never cross the river, so they cannot win on their own.
+    if own.only_defenders() {
+        return favoured.then_some(score / DRAWN_DIVISOR);
+    }
+    if enemy.majors() > 0 {
+        return None;
+    }
+
+    // Lone rook: wins against anything short of full guards and bishops, which hold.
+    if own.rooks == 1 && own.majors() == 1 && own.pawns == 0 && enemy.only_defenders() {
+        return Some(if enemy.full_defense() {
+            score / DRAWISH_DIVISOR
+        } else {
+            score + sign * KNOWN_WIN_BONUS
+        });
+    }
+
+    // Lone horse against full guards and bishops is a draw.
+    if own.horses == 1
+        && own.majors() == 1
+        && own.pawns == 0
+        && enemy.only_defenders()
+        && enemy.full_defense()
+    {
+        return favoured.then_some(score / DRAWN_DIVISOR);
+    }
+
+    // Pawns alone: only pawns across the river and off the back rank can make progress,
+    // and a single one cannot break full guards and bishops.
+    if own.majors() == 0 && favoured {
+        return match active_pawns(board, player) {
+            0 => Some(score / DRAWN_DIVISOR),
+            1 if enemy.full_defense() => Some(score / DRAWISH_DIVISOR),
+            _ => None,
+        };
+    }
+
+    None
+}
@@ -0,0 +1,183 @@
+//! Material values and piece-square tables, loadable from a parameter file.
+//!
+//! The compiled-in values are the defaults. A file only needs to list what it changes:
+//!
+//! ```text
+//! # Comments start with '#'.
+//! material 0 10000 200 200 450 900 500 100
+//! pst_mg rook
+//! 14 14 12 18 16 18 12 14 14
+//! ...
+//! ```
+//!
+//! `material` is followed by the 8 values indexed by piece type, `pst_mg` and `pst_eg`
+//! by a piece name (king, guard, bishop, horse, rook, cannon or pawn) and 90 values,
+//! rank by rank from Red's point of view. A piece without an endgame table uses its
+//! midgame table for both.
+
+use super::{MATERIAL_VALUES, psts};
+use crate::constants::Piece;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The file read at engine startup, if it exists.
+pub const PARAMS_FILE: &str = "eval_params.txt";
+
+pub type Pst = [[i32; 9]; 10];
+
+const PIECE_NAMES: [&str; 7] = ["king", "guard", "bishop", "horse", "rook", "cannon", "pawn"];
+
+/// The evaluation parameters, with the tables indexed by piece type minus one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalParams {
+    pub material: [i32; 8],
+    pub pst_mg: [Pst; 7],
+    pub pst_eg: [Pst; 7],
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        let pst_mg = [
+            psts::KING_PST_MG,
+            psts::GUARD_PST_MG,
+            psts::BISHOP_PST_MG,
+            psts::HORSE_PST_MG,
+            psts::ROOK_PST_MG,
+            psts::CANNON_PST_MG,
+            psts::PAWN_PST_MG,
+        ];
+        let mut pst_eg = pst_mg;
+        pst_eg[6] = psts::PAWN_PST_EG;
+        Self {
+            material: MATERIAL_VALUES,
+            pst_mg,
+            pst_eg,
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads `count` values for `what` from `tokens`. Each must fit in an `i16`, as the
+/// board keeps its material and PST score as a packed `Score`.
+fn read_values<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    count: usize,
+    what: &str,
+) -> io::Result<Vec<i32>> {
+    (0..count)
+        .map(|_| {
+            let token = tokens
+                .next()
+                .ok_or_else(|| invalid(format!("Missing values for {}", what)))?;
+            let value: i32 = token
+                .parse()
+                .map_err(|_| invalid(format!("Invalid value '{}' for {}", token, what)))?;
+            if i16::try_from(value).is_err() {
+                return Err(invalid(format!("Value {} for {} is out of range", value, what)));
+            }
+            Ok(value)
+        })
+        .collect()
+}
+
+impl EvalParams {
+    /// Parses a parameter file, starting from the defaults.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut params = Self::default();
+        let mut eg_given = [false; 7];
+        let mut tokens = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap())
+            .flat_map(str::split_whitespace);
+
+        while let Some(keyword) = tokens.next() {
+            match keyword {
+                "material" => {
+                    let values = read_values(&mut tokens, 8, keyword)?;
+                    params.material.copy_from_slice(&values);
+                }
+                "pst_mg" | "pst_eg" => {
+                    let name = tokens
+                        .next()
+                        .ok_or_else(|| invalid(format!("Missing piece name for {}", keyword)))?;
+                    let piece = PIECE_NAMES
+                        .iter()
+                        .position(|&piece_name| piece_name == name)
+                        .ok_or_else(|| invalid(format!("Unknown piece '{}'", name)))?;
+                    let values = read_values(&mut tokens, 90, &format!("{} {}", keyword, name))?;
+                    let table = if keyword == "pst_mg" {
+                        &mut params.pst_mg[piece]
+                    } else {
+                        eg_given[piece] = true;
+                        &mut params.pst_eg[piece]
+                    };
+                    for (row, chunk) in table.iter_mut().zip(values.chunks(9)) {
+                        row.copy_from_slice(chunk);
+                    }
+                }
+                _ => return Err(invalid(format!("Unknown keyword '{}'", keyword))),
+            }
+        }
+
+        // Pieces without an endgame table of their own follow their midgame table,
+        // except the pawn, whose compiled-in endgame table differs.
+        for (piece, &given) in eg_given.iter().enumerate().take(6) {
+            if !given {
+                params.pst_eg[piece] = params.pst_mg[piece];
+            }
+        }
+        Ok(params)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Writes the parameters in the file format, so they can be edited and loaded back.
+impl fmt::Display for EvalParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values = |row: &[i32]| {
+            row.iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        writeln!(f, "material {}", values(&self.material))?;
+        for (name, tables) in [("pst_mg", &self.pst_mg), ("pst_eg", &self.pst_eg)] {
+            for (piece_name, table) in PIECE_NAMES.iter().zip(tables) {
+                writeln!(f, "{} {}", name, piece_name)?;
+                for row in table {
+                    writeln!(f, "{}", values(row))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+static PARAMS: OnceLock<EvalParams> = OnceLock::new();
+
+/// The parameters in use. The defaults, unless `load` was called first.
+pub fn params() -> &'static EvalParams {
+    PARAMS.get_or_init(EvalParams::default)
+}
+
+/// Loads the parameters from `path`. Boards built before would have scores from
+/// other parameters, so this fails once the parameters have been used.
+pub fn load(path: impl AsRef<Path>) -> io::Result<()> {
+    let params = EvalParams::from_file(path)?;
+    PARAMS
+        .set(params)
+        .map_err(|_| io::Error::other("Evaluation parameters are already in use"))
+}
+
+/// Returns the index of `piece` in the tables of `EvalParams`.
+pub(super) fn table_index(piece: Piece) -> usize {
+    (piece.abs_val() as usize).max(1) - 1
+}
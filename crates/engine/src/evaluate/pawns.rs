@@ -0,0 +1,117 @@
+//! Pawn-structure evaluation, cached in a pawn hash table.
+//!
+//! The structure only changes when a pawn moves or is captured, so most positions of a
+//! search share their pawns with many others and the analysis is looked up instead.
+
+use super::Score;
+use crate::bitboard::{Bitboard, Board, FILE_MASKS, RANK_MASKS};
+use crate::config::Config;
+use crate::constants::{Piece, Player};
+use std::cell::RefCell;
+
+/// The number of entries in each thread's pawn hash table.
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    /// The midgame and endgame structure scores of each player, indexed by
+    /// `Player::get_bb_idx`.
+    scores: [Score; 2],
+}
+
+thread_local! {
+    static PAWN_TABLE: RefCell<Vec<PawnEntry>> =
+        RefCell::new(vec![PawnEntry::default(); PAWN_TABLE_SIZE]);
+}
+
+/// The SplitMix64 finalizer.
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Hashes the pawns of both players together with the weights they are scored with,
+/// so changing the weights never returns stale scores.
+fn pawn_key(red_pawns: Bitboard, black_pawns: Bitboard, config: &Config) -> u64 {
+    let ([red_low, red_high], [black_low, black_high]) = (red_pawns.words(), black_pawns.words());
+    let weights = [
+        config.penalty_doubled_pawn,
+        config.bonus_connected_pawns,
+        config.penalty_isolated_pawn,
+        config.bonus_crossed_pawn,
+        config.bonus_pawn_near_palace,
+    ]
+    .into_iter()
+    .flat_map(|weight| [weight.mg() as u64, weight.eg() as u64]);
+    [red_low, red_high, black_low, black_high]
+        .into_iter()
+        .chain(weights)
+        .fold(0, |hash, value| mix(hash ^ value))
+}
+
+/// The midgame and endgame pawn-structure score of each player, indexed by
+/// `Player::get_bb_idx`.
+pub fn pawn_structure_scores(board: &Board, config: &Config) -> [Score; 2] {
+    let red_pawns = board.piece_bitboards[Piece::RPawn.get_bb_index().unwrap()];
+    let black_pawns = board.piece_bitboards[Piece::BPawn.get_bb_index().unwrap()];
+    let key = pawn_key(red_pawns, black_pawns, config);
+
+    PAWN_TABLE.with_borrow_mut(|table| {
+        let entry = &mut table[key as usize % PAWN_TABLE_SIZE];
+        if entry.key != key {
+            *entry = PawnEntry {
+                key,
+                scores: [
+                    structure_score(red_pawns, Player::Red, config),
+                    structure_score(black_pawns, Player::Black, config),
+                ],
+            };
+        }
+        entry.scores
+    })
+}
+
+/// Scores the pawns of `player` in the midgame and the endgame: doubled pawns, pawns
+/// across the river, side by side or close to the enemy palace, and lone pawns short
+/// of the river.
+fn structure_score(pawns: Bitboard, player: Player, config: &Config) -> Score {
+    let mut score = Score::ZERO;
+
+    let (enemy_half, enemy_palace_ranks) = if player == Player::Red {
+        (0..=4, 0..=2)
+    } else {
+        (5..=9, 7..=9)
+    };
+    let enemy_half = enemy_half.fold(Bitboard::EMPTY, |acc, r| acc | RANK_MASKS[r]);
+    let enemy_palace = enemy_palace_ranks.fold(Bitboard::EMPTY, |acc, r| acc | RANK_MASKS[r])
+        & (FILE_MASKS[3] | FILE_MASKS[4] | FILE_MASKS[5]);
+
+    // Doubled pawns block each other.
+    for file_mask in FILE_MASKS {
+        let count = (pawns & file_mask).count_ones() as i32;
+        if count > 1 {
+            score -= config.penalty_doubled_pawn * (count - 1);
+        }
+    }
+
+    // Pawns across the river move sideways, so neighbours on a rank protect each other.
+    let crossed = pawns & enemy_half;
+    let pairs = crossed & (crossed >> 1) & !FILE_MASKS[8];
+    score += config.bonus_connected_pawns * pairs.count_ones() as i32;
+    score += config.bonus_pawn_near_palace * (pawns & enemy_palace).count_ones() as i32;
+
+    // A pawn still on its own side with no friendly pawn nearby crosses without support.
+    for sq in pawns & !enemy_half {
+        let file = sq % 9;
+        let neighbours = (file.saturating_sub(2)..=(file + 2).min(8))
+            .filter(|&f| f != file)
+            .fold(Bitboard::EMPTY, |acc, f| acc | FILE_MASKS[f]);
+        if (pawns & neighbours).is_empty() {
+            score -= config.penalty_isolated_pawn;
+        }
+    }
+
+    score + config.bonus_crossed_pawn * crossed.count_ones() as i32
+}
@@ -0,0 +1,117 @@
+//! A midgame and an endgame score packed into one integer.
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+/// A midgame and an endgame score in one `i32`: the endgame half in the upper 16 bits
+/// and the midgame half in the lower 16, borrowing from the upper half when negative.
+/// Adding, subtracting and scaling update both halves with a single operation, as long
+/// as each half stays within the range of an `i16`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Score(i32);
+
+impl Score {
+    pub const ZERO: Score = Score(0);
+
+    pub const fn new(mg: i32, eg: i32) -> Self {
+        Score((eg << 16).wrapping_add(mg))
+    }
+
+    /// The same score in the midgame and the endgame.
+    pub const fn flat(value: i32) -> Self {
+        Self::new(value, value)
+    }
+
+    pub const fn mg(self) -> i32 {
+        self.0 as i16 as i32
+    }
+
+    pub const fn eg(self) -> i32 {
+        (self.0.wrapping_add(0x8000) >> 16) as i16 as i32
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, other: Score) -> Score {
+        Score(self.0.wrapping_add(other.0))
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+
+    fn sub(self, other: Score) -> Score {
+        Score(self.0.wrapping_sub(other.0))
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        Score(self.0.wrapping_neg())
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+
+    fn mul(self, factor: i32) -> Score {
+        Score(self.0.wrapping_mul(factor))
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Score {
+    fn sum<I: Iterator<Item = Score>>(iter: I) -> Score {
+        iter.fold(Score::ZERO, Add::add)
+    }
+}
+
+/// A score as written in a config file: one number for both halves or `[mg, eg]`.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum ScoreRepr {
+    Flat(i32),
+    Tapered([i32; 2]),
+}
+
+impl Serialize for Score {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.mg() == self.eg() {
+            ScoreRepr::Flat(self.mg())
+        } else {
+            ScoreRepr::Tapered([self.mg(), self.eg()])
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Score {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Score, D::Error> {
+        let (mg, eg) = match ScoreRepr::deserialize(deserializer)? {
+            ScoreRepr::Flat(value) => (value, value),
+            ScoreRepr::Tapered([mg, eg]) => (mg, eg),
+        };
+        let range = i16::MIN as i32..=i16::MAX as i32;
+        if !range.contains(&mg) || !range.contains(&eg) {
+            return Err(de::Error::custom(format!("score [{}, {}] is out of range", mg, eg)));
+        }
+        Ok(Score::new(mg, eg))
+    }
+}
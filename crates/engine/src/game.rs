@@ -0,0 +1,368 @@
+//! A whole game: the start position, the moves played with their notation, the
+//! clocks and the result.
+//!
+//! Unlike a `Board`, which only knows the positions since it was set up and is shared
+//! with the search, a `Game` keeps everything a user interface or a match runner
+//! needs: undo and redo, the repetition history of the whole game, and a record that
+//! can be exported.
+
+use crate::bitboard::{Board, RepetitionOutcome};
+use crate::constants::Player;
+use crate::r#move::Move;
+use crate::movelist::MoveList;
+use crate::notation::{chinese, iccs};
+use crate::rules::Rules;
+
+/// The standard start position.
+pub const START_FEN: &str = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1";
+
+/// How a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    RedWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    /// The win of `player`.
+    pub fn win_for(player: Player) -> Self {
+        match player {
+            Player::Red => GameResult::RedWins,
+            Player::Black => GameResult::BlackWins,
+        }
+    }
+
+    /// The result as written in game records: `1-0`, `0-1` or `1/2-1/2`.
+    pub fn to_pgn_string(self) -> &'static str {
+        match self {
+            GameResult::RedWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+}
+
+/// Why a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    Checkmate,
+    /// The side to move has no legal move without being in check, which loses too.
+    Stalemate,
+    /// The same position for the third time.
+    Repetition,
+    /// The same position for the third time, forced by one side checking with every
+    /// move, which loses for it.
+    PerpetualCheck,
+    /// The same position for the third time, forced by one side attacking the same
+    /// piece with every move, which loses for it.
+    PerpetualChase,
+    /// Too many plies without a capture, see `Rules::move_rule_plies`.
+    MoveRule,
+    /// A side ran out of time.
+    Time,
+}
+
+/// The time each side has left, and what it gains with every move, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    pub red_ms: u128,
+    pub black_ms: u128,
+    pub increment_ms: u128,
+}
+
+impl Clock {
+    pub fn new(time_ms: u128, increment_ms: u128) -> Self {
+        Self {
+            red_ms: time_ms,
+            black_ms: time_ms,
+            increment_ms,
+        }
+    }
+
+    pub fn remaining_ms(&self, player: Player) -> u128 {
+        match player {
+            Player::Red => self.red_ms,
+            Player::Black => self.black_ms,
+        }
+    }
+
+    /// Charges `player` for a move that took `elapsed_ms` and adds the increment.
+    /// Returns false if the time ran out before the move was made.
+    fn charge(&mut self, player: Player, elapsed_ms: u128) -> bool {
+        let remaining = match player {
+            Player::Red => &mut self.red_ms,
+            Player::Black => &mut self.black_ms,
+        };
+        if elapsed_ms > *remaining {
+            *remaining = 0;
+            return false;
+        }
+        *remaining = *remaining - elapsed_ms + self.increment_ms;
+        true
+    }
+}
+
+/// The notation a game record is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    Iccs,
+    Chinese,
+}
+
+/// A move of a game, with its notation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameMove {
+    pub mv: Move,
+    pub iccs: String,
+    pub chinese: String,
+    /// The clock before the move, to restore when it is taken back.
+    clock_before: Option<Clock>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    start_fen: String,
+    board: Board,
+    moves: Vec<GameMove>,
+    /// The moves taken back, the last one taken back at the end.
+    undone: Vec<Move>,
+    /// The hash key of the position before each move.
+    hash_keys: Vec<u64>,
+    clock: Option<Clock>,
+    rules: Rules,
+    result: Option<(GameResult, Termination)>,
+}
+
+impl Game {
+    /// A game from the standard start position.
+    pub fn new() -> Self {
+        Self::from_fen(START_FEN)
+    }
+
+    /// A game from the position `fen`. Panics on an invalid position, like
+    /// `Board::from_fen`.
+    pub fn from_fen(fen: &str) -> Self {
+        let board = Board::from_fen(fen);
+        let mut game = Self {
+            start_fen: board.to_fen(),
+            board,
+            moves: Vec::new(),
+            undone: Vec::new(),
+            hash_keys: Vec::new(),
+            clock: None,
+            rules: Rules::default(),
+            result: None,
+        };
+        game.update_result();
+        game
+    }
+
+    /// The current position.
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn start_fen(&self) -> &str {
+        &self.start_fen
+    }
+
+    /// The moves played so far, not counting those taken back.
+    pub fn moves(&self) -> &[GameMove] {
+        &self.moves
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    /// Starts timing the game with `clock`.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = Some(clock);
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// Adjudicates the game by `rules` from now on.
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+        self.update_result();
+    }
+
+    /// The result and why, once the game is over.
+    pub fn result(&self) -> Option<(GameResult, Termination)> {
+        self.result
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// How many times the current position occurred before in this game.
+    pub fn repetition_count(&self) -> usize {
+        self.board.repetition_count(&self.hash_keys)
+    }
+
+    /// Plies since the last capture.
+    pub fn halfmove_clock(&self) -> u32 {
+        self.board.halfmove_clock
+    }
+
+    /// Plays `mv`, which must be legal, and forgets the moves taken back.
+    pub fn make_move(&mut self, mv: Move) -> Result<(), String> {
+        self.play(mv, None)
+    }
+
+    /// Like `make_move`, charging the side to move `elapsed_ms` on the clock. If its
+    /// time has run out, the game is lost on time and the move is not played.
+    pub fn make_timed_move(&mut self, mv: Move, elapsed_ms: u128) -> Result<(), String> {
+        self.play(mv, Some(elapsed_ms))
+    }
+
+    fn play(&mut self, mv: Move, elapsed_ms: Option<u128>) -> Result<(), String> {
+        if self.is_over() {
+            return Err("the game is over".to_string());
+        }
+        if !self.board.is_legal(mv) {
+            return Err(format!("illegal move `{}`", iccs::format(mv)));
+        }
+        let player = self.board.player_to_move;
+        let clock_before = self.clock;
+        if let (Some(clock), Some(elapsed_ms)) = (&mut self.clock, elapsed_ms)
+            && !clock.charge(player, elapsed_ms)
+        {
+            self.result = Some((GameResult::win_for(player.opponent()), Termination::Time));
+            return Ok(());
+        }
+
+        self.moves.push(GameMove {
+            mv,
+            iccs: iccs::format(mv),
+            chinese: chinese::format(&self.board, mv),
+            clock_before,
+        });
+        self.hash_keys.push(self.board.hash_key);
+        self.board.make_move(mv);
+        self.undone.clear();
+        self.update_result();
+        Ok(())
+    }
+
+    /// Takes back the last move, which `redo` plays again. Returns the move, or `None`
+    /// at the start of the game.
+    pub fn undo(&mut self) -> Option<Move> {
+        let game_move = self.moves.pop()?;
+        self.hash_keys.pop();
+        self.board.undo_move();
+        if game_move.clock_before.is_some() {
+            self.clock = game_move.clock_before;
+        }
+        self.undone.push(game_move.mv);
+        self.result = None;
+        self.update_result();
+        Some(game_move.mv)
+    }
+
+    /// Plays the last move taken back again. Returns the move, or `None` if there is
+    /// none.
+    pub fn redo(&mut self) -> Option<Move> {
+        let mv = self.undone.pop()?;
+        let undone = std::mem::take(&mut self.undone);
+        self.play(mv, None).ok()?;
+        self.undone = undone;
+        Some(mv)
+    }
+
+    /// Ends the game if the side to move has no legal moves, if the position occurred
+    /// for the third time, which the rules may make a loss for the side that forced it,
+    /// or if there was no capture for as long as the rules allow.
+    fn update_result(&mut self) {
+        let mut legal_moves = MoveList::new();
+        self.board.generate_legal_moves(&mut legal_moves);
+        let player = self.board.player_to_move;
+        self.result = if legal_moves.is_empty() {
+            let termination = if self.board.checkers().is_empty() {
+                Termination::Stalemate
+            } else {
+                Termination::Checkmate
+            };
+            Some((GameResult::win_for(player.opponent()), termination))
+        } else if self.repetition_count() >= 2 {
+            let hash_key = self.board.hash_key;
+            let first = self.hash_keys.iter().position(|&hash| hash == hash_key);
+            let outcome = first.map_or(RepetitionOutcome::Draw, |ply| {
+                self.rules.repetition_outcome(&mut self.board, ply)
+            });
+            Some(match outcome {
+                RepetitionOutcome::Draw => (GameResult::Draw, Termination::Repetition),
+                RepetitionOutcome::PerpetualCheck(loser) => (
+                    GameResult::win_for(loser.opponent()),
+                    Termination::PerpetualCheck,
+                ),
+                RepetitionOutcome::PerpetualChase(loser) => (
+                    GameResult::win_for(loser.opponent()),
+                    Termination::PerpetualChase,
+                ),
+            })
+        } else if self
+            .rules
+            .move_rule_plies()
+            .is_some_and(|plies| self.board.halfmove_clock >= plies)
+        {
+            Some((GameResult::Draw, Termination::MoveRule))
+        } else {
+            None
+        };
+    }
+
+    /// The game record in PGN form, with the moves in `notation`.
+    pub fn to_pgn(&self, notation: Notation) -> String {
+        let result = self
+            .result
+            .map_or("*", |(result, _)| result.to_pgn_string());
+        let mut pgn = String::from("[Game \"Chinese Chess\"]\n");
+        if self.start_fen != START_FEN {
+            pgn.push_str(&format!("[FEN \"{}\"]\n", self.start_fen));
+        }
+        let format = match notation {
+            Notation::Iccs => "ICCS",
+            Notation::Chinese => "Chinese",
+        };
+        pgn.push_str(&format!("[Format \"{}\"]\n", format));
+        pgn.push_str(&format!("[Result \"{}\"]\n\n", result));
+
+        // Number the moves from the start position's fullmove number, which may start
+        // with a Black move.
+        let start = Board::from_fen(&self.start_fen);
+        let mut move_number = start.fullmove_number;
+        let mut red_to_move = start.player_to_move == Player::Red;
+        let mut tokens = Vec::new();
+        for (i, game_move) in self.moves.iter().enumerate() {
+            if red_to_move {
+                tokens.push(format!("{}.", move_number));
+            } else if i == 0 {
+                tokens.push(format!("{}...", move_number));
+            }
+            tokens.push(match notation {
+                Notation::Iccs => game_move.iccs.clone(),
+                Notation::Chinese => game_move.chinese.clone(),
+            });
+            if !red_to_move {
+                move_number += 1;
+            }
+            red_to_move = !red_to_move;
+        }
+        tokens.push(result.to_string());
+        pgn.push_str(&tokens.join(" "));
+        pgn.push('\n');
+        pgn
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,12 +1,25 @@
+pub mod bench;
 pub mod bitboard;
 pub mod config;
 pub mod constants;
 pub mod engine;
 pub mod evaluate;
+pub mod game;
+pub mod mcts;
 pub mod move_generator;
 pub mod movelist;
 pub mod r#move;
+pub mod notation;
 pub mod opening_book;
+pub mod output;
+pub mod perft;
+pub mod rules;
+pub mod search_limits;
+pub mod searcher;
+pub mod skill;
+pub mod square;
+pub mod stats;
+pub mod stop_token;
 pub mod tt;
 pub mod zobrist;
 
@@ -29,7 +42,7 @@ mod tests {
         let to_sq = first_move.to_sq();
         let moving_piece = board.board[from_sq];
 
-        board.move_piece(first_move);
+        board.make_move(first_move);
 
         assert_eq!(board.board[to_sq], moving_piece);
         assert_eq!(board.board[from_sq], Piece::Empty);
@@ -44,9 +57,770 @@ mod tests {
         board.generate_legal_moves(&mut moves);
         let first_move = moves[0];
 
-        let captured_piece = board.move_piece(first_move);
-        board.unmove_piece(first_move, captured_piece);
+        board.make_move(first_move);
+        assert_eq!(board.undo_move(), Some(first_move));
 
         assert_eq!(board.to_fen(), original_fen);
+        assert_eq!(board.undo_move(), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_mate_search() {
+        let mut board = Board::from_fen("3k5/9/9/9/9/9/9/9/R8/R3K4 w - - 0 1");
+        let mut engine = crate::engine::Engine::new(16);
+        let limits = crate::search_limits::SearchLimits {
+            mate: Some(1),
+            ..Default::default()
+        };
+
+        let result = engine.search(&mut board, &limits);
+
+        assert_eq!(result.best_move.to_uci_string(), "a0d0");
+        assert_eq!(result.score, crate::constants::MATE_VALUE - 1);
+    }
+
+    #[test]
+    fn test_stalemate() {
+        // Red can leave Black's king, not in check, without a move, which loses in
+        // xiangqi.
+        let fen = "4k4/8N/9/9/4P4/9/9/9/3R1R3/4K4 w - - 0 1";
+        let limits = crate::search_limits::SearchLimits {
+            depth: Some(3),
+            ..Default::default()
+        };
+
+        let mut board = Board::from_fen(fen);
+        let result = crate::engine::Engine::new(16).search(&mut board, &limits);
+        assert_eq!(result.score, crate::constants::MATE_VALUE - 1);
+
+        board.make_move(result.best_move);
+        let mut moves = MoveList::new();
+        board.generate_legal_moves(&mut moves);
+        assert!(moves.is_empty());
+        assert_eq!(board.checkers(), crate::bitboard::Bitboard::EMPTY);
+    }
+
+    /// A move generator written from the rules alone, on a plain 10x9 array and sharing
+    /// no code with `Board`, to count the perft suite independently. Red's pieces are
+    /// positive, Black's negative, and row 0 is Black's back rank.
+    mod reference {
+        type Grid = [[i8; 9]; 10];
+        const KING: i8 = 1;
+        const GUARD: i8 = 2;
+        const BISHOP: i8 = 3;
+        const HORSE: i8 = 4;
+        const ROOK: i8 = 5;
+        const CANNON: i8 = 6;
+        const PAWN: i8 = 7;
+
+        /// The grid and whether Red is to move.
+        fn parse(fen: &str) -> (Grid, bool) {
+            let mut grid = [[0; 9]; 10];
+            let mut fields = fen.split(' ');
+            for (row, rank) in fields.next().unwrap().split('/').enumerate() {
+                let mut col = 0;
+                for ch in rank.chars() {
+                    if let Some(empty) = ch.to_digit(10) {
+                        col += empty as usize;
+                        continue;
+                    }
+                    let kind = match ch.to_ascii_lowercase() {
+                        'k' => KING,
+                        'a' => GUARD,
+                        'b' | 'e' => BISHOP,
+                        'n' | 'h' => HORSE,
+                        'r' => ROOK,
+                        'c' => CANNON,
+                        _ => PAWN,
+                    };
+                    grid[row][col] = if ch.is_ascii_uppercase() { kind } else { -kind };
+                    col += 1;
+                }
+            }
+            (grid, fields.next() != Some("b"))
+        }
+
+        fn on_board(row: i32, col: i32) -> bool {
+            (0..10).contains(&row) && (0..9).contains(&col)
+        }
+
+        fn in_palace(row: i32, col: i32, red: bool) -> bool {
+            (3..=5).contains(&col) && if red { row >= 7 } else { row <= 2 }
+        }
+
+        fn on_own_side(row: i32, red: bool) -> bool {
+            if red { row >= 5 } else { row <= 4 }
+        }
+
+        /// The moves of `red`'s pieces that don't land on their own pieces, as
+        /// (from row, from col, to row, to col), the king's safety aside.
+        fn pseudo_legal_moves(grid: &Grid, red: bool) -> Vec<(i32, i32, i32, i32)> {
+            let own = |piece: i8| piece != 0 && (piece > 0) == red;
+            let mut moves = Vec::new();
+            for row in 0..10 {
+                for col in 0..9 {
+                    let piece = grid[row as usize][col as usize];
+                    if !own(piece) {
+                        continue;
+                    }
+                    let at = |r: i32, c: i32| grid[r as usize][c as usize];
+                    let mut add = |r: i32, c: i32| {
+                        if on_board(r, c) && !own(at(r, c)) {
+                            moves.push((row, col, r, c));
+                        }
+                    };
+                    match piece.abs() {
+                        KING => {
+                            for (dr, dc) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                                if in_palace(row + dr, col + dc, red) {
+                                    add(row + dr, col + dc);
+                                }
+                            }
+                        }
+                        GUARD => {
+                            for (dr, dc) in [(1, 1), (1, -1), (-1, 1), (-1, -1)] {
+                                if in_palace(row + dr, col + dc, red) {
+                                    add(row + dr, col + dc);
+                                }
+                            }
+                        }
+                        BISHOP => {
+                            for (dr, dc) in [(2, 2), (2, -2), (-2, 2), (-2, -2)] {
+                                let (r, c) = (row + dr, col + dc);
+                                if on_board(r, c) && on_own_side(r, red) && at(row + dr / 2, col + dc / 2) == 0 {
+                                    add(r, c);
+                                }
+                            }
+                        }
+                        HORSE => {
+                            for (dr, dc) in [(2, 1), (2, -1), (-2, 1), (-2, -1), (1, 2), (-1, 2), (1, -2), (-1, -2)] as [(i32, i32); 8] {
+                                // The leg is next to the horse, in the direction of the long step.
+                                let (leg_r, leg_c) = if dr.abs() == 2 { (row + dr / 2, col) } else { (row, col + dc / 2) };
+                                if on_board(row + dr, col + dc) && at(leg_r, leg_c) == 0 {
+                                    add(row + dr, col + dc);
+                                }
+                            }
+                        }
+                        ROOK | CANNON => {
+                            for (dr, dc) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                                let (mut r, mut c) = (row + dr, col + dc);
+                                let mut screened = false;
+                                while on_board(r, c) {
+                                    let target = at(r, c);
+                                    if piece.abs() == ROOK {
+                                        add(r, c);
+                                        if target != 0 {
+                                            break;
+                                        }
+                                    } else if !screened {
+                                        if target == 0 {
+                                            add(r, c);
+                                        } else {
+                                            screened = true;
+                                        }
+                                    } else if target != 0 {
+                                        add(r, c);
+                                        break;
+                                    }
+                                    r += dr;
+                                    c += dc;
+                                }
+                            }
+                        }
+                        _ => {
+                            let forward = if red { -1 } else { 1 };
+                            add(row + forward, col);
+                            if !on_own_side(row, red) {
+                                add(row, col + 1);
+                                add(row, col - 1);
+                            }
+                        }
+                    }
+                }
+            }
+            moves
+        }
+
+        /// Whether `red`'s king can be captured, or sees the other king on an open file.
+        fn king_exposed(grid: &Grid, red: bool) -> bool {
+            let find = |king: i8| (0..10).flat_map(|r| (0..9).map(move |c| (r, c))).find(|&(r, c)| grid[r as usize][c as usize] == king);
+            let (Some(own), Some(other)) = (find(if red { KING } else { -KING }), find(if red { -KING } else { KING })) else {
+                return true;
+            };
+            if own.1 == other.1 && (own.0.min(other.0) + 1..own.0.max(other.0)).all(|r| grid[r as usize][own.1 as usize] == 0) {
+                return true;
+            }
+            pseudo_legal_moves(grid, !red).iter().any(|&(_, _, r, c)| (r, c) == own)
+        }
+
+        fn perft_grid(grid: &mut Grid, red: bool, depth: u32) -> u64 {
+            if depth == 0 {
+                return 1;
+            }
+            let mut nodes = 0;
+            for (fr, fc, tr, tc) in pseudo_legal_moves(grid, red) {
+                let (from, to) = ((fr as usize, fc as usize), (tr as usize, tc as usize));
+                let captured = grid[to.0][to.1];
+                grid[to.0][to.1] = grid[from.0][from.1];
+                grid[from.0][from.1] = 0;
+                if !king_exposed(grid, red) {
+                    nodes += perft_grid(grid, !red, depth - 1);
+                }
+                grid[from.0][from.1] = grid[to.0][to.1];
+                grid[to.0][to.1] = captured;
+            }
+            nodes
+        }
+
+        pub fn perft(fen: &str, depth: u32) -> u64 {
+            let (mut grid, red) = parse(fen);
+            perft_grid(&mut grid, red, depth)
+        }
+    }
+
+    #[test]
+    fn test_reference_perft() {
+        for (fen, counts) in crate::perft::PERFT_SUITE {
+            for (depth, &expected) in counts.iter().enumerate().take(3) {
+                assert_eq!(reference::perft(fen, depth as u32 + 1), expected, "{}", fen);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "slow; run with --ignored --release"]
+    fn test_reference_perft_full() {
+        for (fen, counts) in crate::perft::PERFT_SUITE {
+            for (depth, &expected) in counts.iter().enumerate().take(4) {
+                assert_eq!(reference::perft(fen, depth as u32 + 1), expected, "{}", fen);
+            }
+        }
+    }
+
+    #[test]
+    fn test_perft() {
+        for (fen, counts) in crate::perft::PERFT_SUITE {
+            let mut board = Board::from_fen(fen);
+            for (depth, &expected) in counts.iter().enumerate().take(3) {
+                assert_eq!(crate::perft::perft(&mut board, depth as u32 + 1), expected, "{}", fen);
+            }
+            let divided: u64 = crate::perft::perft_divide(&mut board, 2).iter().map(|&(_, nodes)| nodes).sum();
+            assert_eq!(divided, counts[1], "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_is_legal() {
+        for (fen, _) in crate::perft::PERFT_SUITE {
+            let mut board = Board::from_fen(fen);
+            let mut legal_moves = MoveList::new();
+            board.generate_legal_moves(&mut legal_moves);
+
+            let mut legal_count = 0;
+            for from_sq in 0..90 {
+                for to_sq in 0..90 {
+                    let captured = board.board[to_sq];
+                    let mv = crate::r#move::Move::new(from_sq, to_sq, (captured != Piece::Empty).then_some(captured));
+                    if board.is_legal(mv) {
+                        assert!(legal_moves.as_slice().contains(&mv), "{} {}", fen, mv.to_uci_string());
+                        legal_count += 1;
+                    }
+                }
+            }
+            assert_eq!(legal_count, legal_moves.len(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_eval_symmetry() {
+        let config = crate::config::Config::default();
+        for (fen, _) in crate::perft::PERFT_SUITE {
+            let board = Board::from_fen(fen);
+            let score = crate::evaluate::evaluate(&board, &config);
+
+            let mirrored = board.mirror_horizontal();
+            assert_eq!(mirrored.hash_key, board.mirrored_hash_key, "{}", fen);
+            assert_eq!(crate::evaluate::evaluate(&mirrored, &config), score, "{}", fen);
+
+            let flipped = board.flip_colors();
+            assert_eq!(crate::evaluate::evaluate(&flipped, &config), score, "{}", fen);
+            assert_eq!(flipped.flip_colors().to_fen(), board.to_fen(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_chinese_notation() {
+        use crate::notation::chinese;
+
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let mv = chinese::parse(&mut board, "炮二平五").unwrap();
+        assert_eq!(mv.to_uci_string(), "h2e2");
+        assert_eq!(chinese::format(&board, mv), "炮二平五");
+        board.make_move(mv);
+        let mv = chinese::parse(&mut board, "馬8進7").unwrap();
+        assert_eq!(chinese::format(&board, mv), "马8进7");
+
+        let mut board = Board::from_fen("4k4/9/9/9/4R4/9/4R4/9/9/3K5 w - - 0 1");
+        let mv = chinese::parse(&mut board, "后车进一").unwrap();
+        assert_eq!(mv.to_uci_string(), "e3e4");
+    }
+
+    #[test]
+    fn test_checkers_and_pinned() {
+        use crate::bitboard::{Bitboard, SQUARE_MASKS};
+        use crate::constants::Player;
+
+        // The horse on e7 shields its king from the rook.
+        let board = Board::from_fen("4k4/9/4n4/9/4R4/9/9/9/9/3K5 b - - 0 1");
+        assert_eq!(board.checkers(), Bitboard::EMPTY);
+        assert_eq!(board.pinned(Player::Black), SQUARE_MASKS[22]);
+        assert_eq!(board.pinned(Player::Red), Bitboard::EMPTY);
+
+        // The guard on e8 is the cannon's screen.
+        let board = Board::from_fen("4k4/4a4/9/9/4C4/9/9/9/9/3K5 b - - 0 1");
+        assert_eq!(board.checkers(), SQUARE_MASKS[40]);
+        assert_eq!(board.pinned(Player::Black), Bitboard::EMPTY);
+
+        // A pawn across the river attacks sideways.
+        let board = Board::from_fen("3k5/9/9/9/9/9/9/9/3pK4/9 w - - 0 1");
+        assert_eq!(board.checkers(), SQUARE_MASKS[75]);
+        assert!(crate::move_generator::is_king_in_check(&board, Player::Red));
+        assert_eq!(board.attackers_to("d1".parse().unwrap(), Player::Red), SQUARE_MASKS[76]);
+    }
+
+    #[test]
+    fn test_legal_moves() {
+        use rand::{Rng, SeedableRng};
+
+        // The moves that don't leave the king in check when tried on the board.
+        fn tried_moves(board: &mut Board) -> Vec<String> {
+            let mut pseudo_legal_moves = MoveList::new();
+            board.generate_capture_moves(&mut pseudo_legal_moves);
+            board.generate_quiet_moves(&mut pseudo_legal_moves);
+            let mut legal_moves = Vec::new();
+            for &mv in pseudo_legal_moves.as_slice() {
+                board.make_move(mv);
+                if !crate::move_generator::is_king_in_check(board, board.player_to_move.opponent()) {
+                    legal_moves.push(mv.to_uci_string());
+                }
+                board.undo_move();
+            }
+            legal_moves
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let fens = [
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1",
+            "4k4/4a4/9/9/4C4/9/9/9/9/3K5 b - - 0 1",     // Cannon check with a screen
+            "3k5/9/9/9/9/9/9/9/3pK4/9 w - - 0 1",        // Pawn check
+            "2bak4/4a4/4c4/4C4/2n6/9/9/4R4/3p5/4K4 w - - 0 1",
+        ];
+        for fen in fens {
+            for _ in 0..20 {
+                let mut board = Board::from_fen(fen);
+                for _ in 0..100 {
+                    let mut moves = MoveList::new();
+                    board.generate_legal_moves(&mut moves);
+                    let generated: Vec<String> = moves.as_slice().iter().map(|mv| mv.to_uci_string()).collect();
+                    assert_eq!(generated, tried_moves(&mut board), "{}", board.to_fen());
+                    if moves.is_empty() {
+                        break;
+                    }
+                    board.make_move(moves[rng.gen_range(0..moves.len())]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        assert_eq!(board.validate(), Ok(()));
+
+        let invalid = [
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBA1ABNR w - - 0 1", // No red king
+            "4k4/9/9/9/9/9/9/9/9/4K4 w - - 0 1",                                   // Kings facing
+            "3k5/9/9/9/9/9/9/9/9/K8 w - - 0 1",                                    // King outside the palace
+            "3k5/9/9/9/9/9/9/9/4B4/4K4 w - - 0 1",                                 // Bishop off its points
+            "3k5/9/9/9/9/9/9/P8/9/4K4 w - - 0 1",                                  // Pawn behind its start
+            "3k5/9/9/9/RRR6/9/9/9/9/4K4 w - - 0 1",                                // Three rooks
+        ];
+        for fen in invalid {
+            assert!(Board::try_from_fen(fen).is_err(), "{}", fen);
+        }
+
+        let malformed = [
+            "",                                                                   // Nothing at all
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9 w - - 0 1",         // Nine ranks
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNRR w",      // Ten files
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABN w",        // Eight files
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNX w",       // Unknown piece
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR",         // No side to move
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR x - - 0 1", // Unknown side
+        ];
+        for fen in malformed {
+            assert!(Board::try_from_fen(fen).is_err(), "{}", fen);
+        }
+        assert_eq!(Board::try_from_fen("3k5/9/9/9/RRR6/9/9/9/9/4K4 w - - 0 1").err(), Some("too many RRook pieces: 3".to_string()));
+        assert_eq!(Board::try_from_fen("3k5/9/9/9/9/9/9/9/9/4K4 x").err(), Some("unknown side to move 'x'".to_string()));
+    }
+
+    #[test]
+    fn test_long_history() {
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let start_fen = board.to_fen();
+        let shuffle = ["h0g2", "h9g7", "g2h0", "g7h9"];
+        for text in shuffle.iter().cycle().take(400) {
+            let mv = board.parse_uci_move(text).unwrap();
+            board.make_move(mv);
+        }
+        assert_eq!(board.history_ply, 400);
+        let before = board.clone();
+        let mv = board.parse_uci_move("b2e2").unwrap();
+        board.make_move(mv);
+        assert_eq!(board.diff(&before).into_iter().collect::<Vec<_>>(), [64, 67]);
+        board.undo_move();
+        assert!(board.diff(&before).is_empty());
+        while board.undo_move().is_some() {}
+        assert_eq!(board.to_fen(), start_fen);
+    }
+
+    #[test]
+    fn test_square() {
+        use crate::constants::Player;
+        use crate::square::{File, Rank, Square};
+
+        let e0: Square = "e0".parse().unwrap();
+        assert_eq!(e0, Square::new(File::new(4).unwrap(), Rank::new(0).unwrap()));
+        assert_eq!(e0.index(), 85);
+        assert_eq!(e0.to_string(), "e0");
+        assert!(e0.in_palace(Player::Red) && !e0.in_palace(Player::Black));
+        assert!(!e0.across_river(Player::Red) && e0.across_river(Player::Black));
+        assert_eq!(e0.file().number_for(Player::Red), 5);
+        assert_eq!("a9".parse::<Square>().unwrap().mirrored().to_string(), "i9");
+        assert!("j0".parse::<Square>().is_err());
+    }
+
+    #[test]
+    fn test_game() {
+        use crate::game::{Game, GameResult, Notation, Termination};
+
+        let mut game = Game::new();
+        let shuffle = ["h0g2", "h9g7", "g2h0", "g7h9"];
+        for text in shuffle.iter().cycle().take(8) {
+            let mv = game.board().clone().parse_uci_move(text).unwrap();
+            game.make_move(mv).unwrap();
+        }
+        assert_eq!(game.result(), Some((GameResult::Draw, Termination::Repetition)));
+        assert!(game.make_move(game.moves()[0].mv).is_err());
+
+        game.undo();
+        assert_eq!(game.result(), None);
+        assert_eq!(game.repetition_count(), 1);
+        game.redo();
+        assert_eq!(game.moves().len(), 8);
+        assert!(game.to_pgn(Notation::Iccs).ends_with("1. h0g2 h9g7 2. g2h0 g7h9 3. h0g2 h9g7 4. g2h0 g7h9 1/2-1/2\n"));
+        assert!(game.to_pgn(Notation::Chinese).contains("1. 马二进三"));
+
+        let mut game = Game::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 119 60");
+        let mv = game.board().clone().parse_uci_move("h0g2").unwrap();
+        game.make_move(mv).unwrap();
+        assert_eq!(game.result(), Some((GameResult::Draw, Termination::MoveRule)));
+    }
+
+    #[test]
+    fn test_perpetual_check() {
+        use crate::game::{Game, GameResult, Termination};
+        use crate::rules::Rules;
+
+        let mut game = Game::from_fen("3k5/9/9/9/9/9/9/9/9/R3K4 w - - 0 1");
+        let cycle = ["a8a9", "d9d8", "a9a8", "d8d9"];
+        for text in ["a0a9", "d9d8", "a9a8", "d8d9"].iter().chain(cycle.iter().cycle().take(5)) {
+            let mv = game.board().clone().parse_uci_move(text).unwrap();
+            game.make_move(mv).unwrap();
+        }
+        assert_eq!(game.result(), Some((GameResult::BlackWins, Termination::PerpetualCheck)));
+        game.set_rules(Rules::SimpleRepetitionDraw);
+        assert_eq!(game.result(), Some((GameResult::Draw, Termination::Repetition)));
+    }
+
+    #[test]
+    fn test_perpetual_chase() {
+        use crate::game::{Game, GameResult, Termination};
+
+        // The rook follows the unprotected cannon from file to file.
+        let mut game = Game::from_fen("4k4/9/c8/9/9/9/9/9/9/R2K5 b - - 0 1");
+        let cycle = ["a7b7", "a0b0", "b7a7", "b0a0"];
+        for text in cycle.iter().cycle().take(8) {
+            let mv = game.board().clone().parse_uci_move(text).unwrap();
+            game.make_move(mv).unwrap();
+        }
+        assert_eq!(game.result(), Some((GameResult::BlackWins, Termination::PerpetualChase)));
+    }
+
+    #[test]
+    fn test_fen_dialects() {
+        use crate::bitboard::FenDialect;
+
+        let fen = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1";
+        let xiangqi_fen = "rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR r - - 0 1";
+        assert_eq!(Board::from_fen(fen).to_fen_in(FenDialect::Xiangqi), xiangqi_fen);
+        assert_eq!(Board::from_fen(xiangqi_fen).to_fen(), fen);
+        assert_eq!(Board::from_fen("rheakaehr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RHEAKAEHR r").to_fen(), fen);
+    }
+
+    #[test]
+    fn test_opening_book() {
+        use crate::opening_book::OpeningBook;
+
+        let board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let entry = |from_sq: u32, to_sq: u32| [&board.hash_key.to_le_bytes()[..], &from_sq.to_le_bytes(), &to_sq.to_le_bytes()].concat();
+        let path = std::env::temp_dir().join(format!("test_opening_book_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        // A square off the board
+        std::fs::write(path, entry(70, 200)).unwrap();
+        assert_eq!(OpeningBook::load(path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        // Black's rook a9a8 with Red to move, as from a hash collision, next to h2e2
+        std::fs::write(path, [entry(0, 9), entry(70, 67)].concat()).unwrap();
+        let book = OpeningBook::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        for _ in 0..20 {
+            assert_eq!(book.query(&board, false).map(|mv| mv.to_uci_string()), Some("h2e2".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_tt_file() {
+        use crate::r#move::Move;
+        use crate::tt::{TranspositionTable, TtFlag};
+
+        let path = std::env::temp_dir().join(format!("test_tt_file_{}.bin", std::process::id()));
+        let tt = TranspositionTable::new(1);
+        tt.store(12345, 7, 42, TtFlag::Exact, Move::new(70, 67, None));
+        tt.save(&path).unwrap();
+        let mut loaded = TranspositionTable::new(2);
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.size_mb(), 1);
+        assert_eq!(loaded.probe(12345).map(|entry| entry.score), Some(42));
+
+        // A header claiming more buckets than the file holds
+        let saved = std::fs::read(&path).unwrap();
+        let mut bytes = saved.clone();
+        bytes[17..25].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(loaded.load(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        // A truncated file
+        std::fs::write(&path, &saved[..saved.len() - 16]).unwrap();
+        assert_eq!(loaded.load(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        // A move from square 127
+        let mut bytes = saved.clone();
+        bytes[25 + 8] |= 0x7F;
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(loaded.load(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(Move::from_u32(Move::new(89, 90, None).to_u32()), None);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.probe(12345).map(|entry| entry.score), Some(42));
+    }
+
+    #[test]
+    fn test_bitboard_utils() {
+        use crate::bitboard::{Bitboard, SQUARE_MASKS};
+        use crate::bitboard::utils::{self, Direction};
+
+        assert_eq!(utils::shift(SQUARE_MASKS[8], Direction::East), Bitboard::EMPTY);
+        assert_eq!(utils::shift(SQUARE_MASKS[9], Direction::West), Bitboard::EMPTY);
+        assert_eq!(utils::shift(SQUARE_MASKS[85], Direction::South), Bitboard::EMPTY);
+        assert_eq!(utils::shift(SQUARE_MASKS[85], Direction::North), SQUARE_MASKS[76]);
+        assert_eq!(utils::between(4, 85).count_ones(), 8);
+        assert_eq!(utils::between(85, 4), utils::between(4, 85));
+        assert_eq!(utils::between(0, 10), Bitboard::EMPTY);
+        assert_eq!(utils::line(0, 8), crate::bitboard::RANK_MASKS[0]);
+    }
+
+    /// The red and black shares of the trace term `name` of `fen`.
+    fn eval_term(fen: &str, config: &crate::config::Config, name: &str) -> (i32, i32) {
+        let trace = crate::evaluate::evaluate_trace(&Board::from_fen(fen), config);
+        let term = trace.terms.iter().find(|term| term.name == name).unwrap();
+        (term.red, term.black)
+    }
+
+    #[test]
+    fn test_eval_terms() {
+        use crate::evaluate::Score;
+
+        // Flat bonuses, so the terms don't depend on the phase.
+        let config = crate::config::Config {
+            bonus_king_file_control: Score::flat(30),
+            bonus_crossed_pawn: Score::flat(20),
+            ..Default::default()
+        };
+        let kings = "3k5/9/9/9/9/9/9/9/9/4K4 w - - 0 1";
+
+        // A rook reaching nine squares up its file and three along its rank.
+        let rook = "3k5/9/9/9/9/9/9/9/9/R3K4 w - - 0 1";
+        assert_eq!(eval_term(rook, &config, "mobility"), (12, 0));
+        assert_eq!(eval_term(rook, &config, "trapped pieces"), (0, 0));
+        assert_eq!(eval_term(rook, &config, "rook placement"), (20, 0));
+        assert_eq!(eval_term("3k5/9/9/9/9/p8/9/9/9/R3K4 w - - 0 1", &config, "rook placement"), (10, 0));
+        // A rook shut in by its own horse and bishop.
+        assert_eq!(eval_term("3k5/9/9/9/9/9/9/B8/9/RN2K4 w - - 0 1", &config, "trapped pieces"), (-40, 0));
+
+        assert_eq!(eval_term("C2k5/9/9/9/9/9/9/9/9/4K4 w - - 0 1", &config, "patterns"), (80, 0));
+        assert_eq!(eval_term("3k5/9/9/9/9/9/9/9/9/4K4 w - - 0 1", &config, "patterns"), (0, 0));
+
+        // Each king has its three palace files open; a rook adds four units against Red.
+        let attacked = "3k5/9/9/9/9/9/9/9/r8/4K4 w - - 0 1";
+        assert_eq!(eval_term(kings, &config, "king safety"), (-12, -12));
+        assert_eq!(eval_term(attacked, &config, "king safety"), (-56, -12));
+        assert_eq!(eval_term(attacked, &config, "exposed king"), (-10, 0));
+        assert_eq!(eval_term("3k5/9/9/9/9/9/9/9/4A4/r3K4 w - - 0 1", &config, "exposed king"), (0, 0));
+
+        assert_eq!(eval_term(kings, &config, "king opposition"), (30, 30));
+        assert_eq!(eval_term("3k5/9/9/9/4p4/9/9/9/9/4K4 w - - 0 1", &config, "king opposition"), (0, 30));
+
+        // A rook attacking three squares of a palace without guards.
+        assert_eq!(eval_term("3k5/R8/9/9/9/9/9/9/9/4K4 w - - 0 1", &config, "dynamic bonus"), (90, 0));
+        assert_eq!(eval_term("3k1a3/R8/9/9/9/9/9/9/9/4K4 w - - 0 1", &config, "dynamic bonus"), (45, 0));
+
+        // Doubled rooks, both on the file of the enemy king, and a cannon there too.
+        assert_eq!(eval_term("3k5/9/9/9/9/9/9/3C5/3R5/3RK4 w - - 0 1", &config, "file pressure"), (55, 0));
+
+        // Two crossed pawns side by side and a lone pawn short of the river.
+        let pawns = "3k5/9/9/9/2PP5/9/8P/9/9/4K4 w - - 0 1";
+        assert_eq!(eval_term(pawns, &config, "pawn structure"), (50, 0));
+
+        // Guards on their home squares and bishops protecting each other and the
+        // central point.
+        assert_eq!(eval_term("3k5/9/9/9/9/9/9/4B4/9/2BAKA3 w - - 0 1", &config, "defensive structure"), (40, 0));
+        assert_eq!(eval_term("3k5/9/9/9/9/9/9/4B4/3N5/2BAKA3 w - - 0 1", &config, "defensive structure"), (4, 0));
+
+        assert_eq!(eval_term(kings, &config, "tempo"), (10, 0));
+        assert_eq!(eval_term("3k5/9/9/9/9/9/9/9/9/4K4 b - - 0 1", &config, "tempo"), (0, 10));
+    }
+
+    #[test]
+    fn test_eval_trace_sum() {
+        let config = crate::config::Config::default();
+        for (fen, _) in crate::perft::PERFT_SUITE {
+            let board = Board::from_fen(fen);
+            let trace = crate::evaluate::evaluate_trace(&board, &config);
+            // The midgame and endgame PST halves are shown besides the tapered one.
+            let terms = trace.terms.iter().filter(|term| !term.name.starts_with("pst "));
+            let (count, sum) = terms.fold((0, 0), |(count, sum), term| (count + 1, sum + term.net()));
+            let score = crate::evaluate::evaluate(&board, &config);
+            let score = if board.player_to_move == crate::constants::Player::Red { score } else { -score };
+            assert_eq!(trace.score, score, "{}", fen);
+            // Each term is rounded on its own.
+            assert!((sum - trace.score).abs() <= count, "{}: {} vs {}", fen, sum, trace.score);
+        }
+    }
+
+    #[test]
+    fn test_eval_caches() {
+        use crate::evaluate::{EvalCache, Score, evaluate, lazy_score};
+
+        // Red is a rook up.
+        let board = Board::from_fen("rnbakabn1/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let mut config = crate::config::Config::default();
+        let score = evaluate(&board, &config);
+        let mut cache = EvalCache::new();
+        assert_eq!(cache.evaluate(&board, &config), score);
+
+        // The cache keeps the score of the config it was computed with until cleared.
+        config.mobility_bonus_rook = Score::flat(10);
+        let new_score = evaluate(&board, &config);
+        assert_ne!(new_score, score);
+        assert_eq!(cache.evaluate(&board, &config), score);
+        cache.clear();
+        assert_eq!(cache.evaluate(&board, &config), new_score);
+
+        // The pawn table keys on the weights as well as the pawns.
+        let pawns = "3k5/9/9/9/2PP5/9/8P/9/9/4K4 w - - 0 1";
+        let before = eval_term(pawns, &config, "pawn structure");
+        config.bonus_connected_pawns = Score::flat(50);
+        assert_eq!(eval_term(pawns, &config, "pawn structure").0, before.0 + 30);
+
+        // Lazy evaluation only answers for windows far from the material and PST score.
+        let lazy = lazy_score(&board, &config, -100, 100).unwrap();
+        assert!(lazy > 100 + config.lazy_eval_margin);
+        assert_eq!(lazy_score(&board, &config, lazy - 10, lazy + 10), None);
+        cache.clear();
+        assert_eq!(cache.evaluate_lazy(&board, &config, -100, 100), lazy);
+        assert_ne!(lazy, new_score);
+        // The lazy score was not stored.
+        assert_eq!(cache.evaluate(&board, &config), evaluate(&board, &config));
+        assert_eq!(cache.evaluate_lazy(&board, &config, -100, 100), evaluate(&board, &config));
+    }
+
+    #[test]
+    fn test_tt_replacement() {
+        use crate::r#move::Move;
+        use crate::tt::{TranspositionTable, TtFlag};
+
+        let mut tt = TranspositionTable::new(1);
+        // Keys this far apart share a bucket of four entries.
+        let buckets = (1024 * 1024 / 64) as u64;
+        let key = |i: u64| 1 + i * buckets;
+        let mv = Move::new(70, 67, None);
+        let depth = |tt: &TranspositionTable, i| tt.probe(key(i)).map(|entry| entry.depth);
+
+        tt.store(key(0), 10, 0, TtFlag::LowerBound, mv);
+        for i in 1..=3 {
+            tt.store(key(i), i as i32 + 1, 0, TtFlag::LowerBound, mv);
+        }
+        // A fifth entry replaces the shallowest but keeps the deepest.
+        tt.store(key(4), 5, 0, TtFlag::LowerBound, mv);
+        assert_eq!(depth(&tt, 0), Some(10));
+        assert_eq!(depth(&tt, 1), None);
+        assert_eq!(depth(&tt, 4), Some(5));
+
+        // A shallower bound doesn't replace a deeper one of the same position, an exact
+        // score does.
+        tt.store(key(0), 1, 0, TtFlag::LowerBound, mv);
+        assert_eq!(depth(&tt, 0), Some(10));
+        tt.store(key(0), 9, 0, TtFlag::Exact, mv);
+        assert_eq!(depth(&tt, 0), Some(9));
+
+        // In the next search, old entries give way to new ones however deep they are.
+        tt.new_search();
+        tt.store(key(5), 1, 0, TtFlag::LowerBound, mv);
+        assert_eq!(depth(&tt, 5), Some(1));
+        assert_eq!(depth(&tt, 0), Some(9));
+        assert_eq!(depth(&tt, 2), None);
+        assert_eq!(tt.probe(key(5)).unwrap().generation, 1);
+        tt.store(key(3), 1, 0, TtFlag::LowerBound, mv);
+        assert_eq!(depth(&tt, 3), Some(1));
+
+        assert_eq!(tt.size_mb(), 1);
+        tt.resize(2);
+        assert_eq!(tt.size_mb(), 2);
+        assert_eq!(depth(&tt, 0), None);
+    }
+
+    #[test]
+    fn test_mcts() {
+        use crate::mcts::MctsSearcher;
+        use crate::search_limits::SearchLimits;
+
+        let fen = "3k5/9/9/9/9/9/9/9/R8/R3K4 w - - 0 1";
+        let mut board = Board::from_fen(fen);
+        let mut mcts = MctsSearcher::new();
+        mcts.print_info = false;
+        let limits = SearchLimits { nodes: Some(2000), ..Default::default() };
+        let result = mcts.search(&mut board, &limits);
+        assert_eq!(board.to_fen(), Board::from_fen(fen).to_fen());
+        // Either rook mates on the d-file.
+        let mates = ["a0d0", "a1d1"];
+        assert!(mates.contains(&result.best_move.to_uci_string().as_str()));
+
+        // Restricted to the other moves, it plays one of them.
+        let mut moves = MoveList::new();
+        board.generate_legal_moves(&mut moves);
+        let searchmoves: Vec<_> = moves.as_slice().iter().copied().filter(|mv| !mates.contains(&mv.to_uci_string().as_str())).collect();
+        let limits = SearchLimits { nodes: Some(2000), searchmoves: searchmoves.clone(), ..Default::default() };
+        let result = mcts.search(&mut board, &limits);
+        assert!(searchmoves.contains(&result.best_move));
+    }
+}
@@ -0,0 +1,333 @@
+//! Monte-Carlo tree search with PUCT selection, an alternative to the alpha-beta engine.
+//!
+//! Moves get uniform priors and leaves are valued by the static evaluation, so
+//! `policy` and `value` are the places to plug in a trained network.
+
+use crate::r#move::Move;
+use crate::bitboard::Board;
+use crate::config::Config;
+use crate::engine::{PvLine, SearchResult};
+use crate::evaluate;
+use crate::movelist::MoveList;
+use crate::output::{self, Output};
+use crate::search_limits::SearchLimits;
+use crate::searcher::Searcher;
+use crate::stop_token::StopToken;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Exploration constant of the PUCT formula.
+const PUCT_C: f32 = 1.5;
+/// The evaluation, in centipawns, that maps to a value of tanh(1).
+const VALUE_SCALE: f32 = 400.0;
+/// The value of a position without legal moves: a loss for the side to move, in check
+/// or not, as in the alpha-beta search.
+const TERMINAL_VALUE: f32 = -1.0;
+/// The search stops once the tree holds this many nodes, about 100 MB.
+const MAX_TREE_NODES: usize = 4_000_000;
+/// Selection never goes deeper than this, to stay within the board's history.
+const MAX_TREE_DEPTH: usize = 64;
+/// The number of playouts when the search has no other limit.
+const DEFAULT_PLAYOUTS: u64 = 100_000;
+
+/// A node of the search tree, reached from its parent by `mv`.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    mv: Move,
+    prior: f32,
+    visits: u32,
+    /// Sum of the playout values through this node, for the player who made `mv`.
+    value_sum: f32,
+    first_child: u32,
+    num_children: u16,
+    expanded: bool,
+}
+
+impl Node {
+    fn new(mv: Move, prior: f32) -> Self {
+        Self {
+            mv,
+            prior,
+            visits: 0,
+            value_sum: 0.0,
+            first_child: 0,
+            num_children: 0,
+            expanded: false,
+        }
+    }
+
+    /// The mean playout value, or 0 for an unvisited node.
+    fn q(&self) -> f32 {
+        if self.visits == 0 {
+            0.0
+        } else {
+            self.value_sum / self.visits as f32
+        }
+    }
+
+    fn children(&self) -> std::ops::Range<usize> {
+        let first = self.first_child as usize;
+        first..first + self.num_children as usize
+    }
+}
+
+/// A Monte-Carlo tree searcher. The tree is rebuilt for every search.
+pub struct MctsSearcher {
+    nodes: Vec<Node>,
+    /// Stops the running search from another thread.
+    pub stop: StopToken,
+    /// Set from another thread to deliver a `ponderhit` to the running search.
+    pub ponderhit_signal: Arc<AtomicBool>,
+    pub config: Config,
+    /// Whether the search reports its `info` lines.
+    pub print_info: bool,
+    /// Receives the `info` line of each search, stdout unless a front end sets its own.
+    pub output: Output,
+    /// Root moves the current search is restricted to (`go searchmoves`). Empty means
+    /// all moves.
+    search_moves: Vec<Move>,
+}
+
+impl Default for MctsSearcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MctsSearcher {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            stop: StopToken::new(),
+            ponderhit_signal: Arc::new(AtomicBool::new(false)),
+            config: Config::default(),
+            print_info: true,
+            output: output::stdout(),
+            search_moves: Vec::new(),
+        }
+    }
+
+    /// Runs playouts from `board` until a limit is reached, then plays the most
+    /// visited root move. `limits.nodes` counts playouts, and `limits.depth` and
+    /// `limits.mate` are ignored.
+    pub fn search(&mut self, board: &mut Board, limits: &SearchLimits) -> SearchResult {
+        self.nodes.clear();
+        self.nodes.push(Node::new(Move::new(0, 0, None), 1.0));
+        self.search_moves = limits.searchmoves.clone();
+        if limits.mate.is_some() && self.print_info {
+            (self.output)(
+                "info string the MCTS backend cannot search for mates, searching normally",
+            );
+        }
+
+        let mut start_time = Instant::now();
+        let mut ponder = limits.ponder;
+        let time_limit_ms = limits.time_limit_ms(board.player_to_move);
+        let max_playouts = match limits.nodes {
+            Some(nodes) => nodes,
+            None if time_limit_ms.is_none() && !limits.infinite && !ponder => DEFAULT_PLAYOUTS,
+            None => u64::MAX,
+        };
+
+        let mut playouts = 0;
+        let mut max_depth = 0;
+        while playouts < max_playouts
+            && !self.stop.is_stopped()
+            && self.nodes.len() < MAX_TREE_NODES
+        {
+            if playouts % 256 == 0 {
+                if ponder && self.ponderhit_signal.swap(false, Ordering::Relaxed) {
+                    ponder = false;
+                    start_time = Instant::now();
+                }
+                if !ponder
+                    && let Some(limit) = time_limit_ms
+                    && start_time.elapsed().as_millis() >= limit
+                {
+                    break;
+                }
+            }
+            max_depth = max_depth.max(self.playout(board));
+            playouts += 1;
+        }
+
+        let pv = self.principal_variation();
+        let best_move = pv.first().copied().unwrap_or(Move::new(0, 0, None));
+        let q = self.best_child(0).map_or(0.0, |child| self.nodes[child].q());
+        let score = (q.clamp(-0.999, 0.999).atanh() * VALUE_SCALE) as i32;
+
+        let elapsed_ms = start_time.elapsed().as_millis();
+        if self.print_info {
+            (self.output)(&format!(
+                "info depth {} score cp {} nodes {} nps {} time {} pv {}",
+                max_depth,
+                score,
+                playouts,
+                playouts as u128 * 1000 / elapsed_ms.max(1),
+                elapsed_ms,
+                pv.iter().map(|mv| mv.to_uci_string()).collect::<Vec<_>>().join(" ")
+            ));
+        }
+
+        SearchResult {
+            best_move,
+            ponder_move: pv.get(1).copied(),
+            score,
+            depth: max_depth as i32,
+            pv: pv.clone(),
+            lines: vec![PvLine { score, pv }],
+        }
+    }
+
+    /// Walks down the tree to a leaf, expands it and backs its value up.
+    /// Returns the depth of the leaf.
+    fn playout(&mut self, board: &mut Board) -> usize {
+        let mut path = vec![0];
+
+        let mut node = 0;
+        let mut rule_value = None;
+        while self.nodes[node].expanded
+            && self.nodes[node].num_children > 0
+            && path.len() <= MAX_TREE_DEPTH
+        {
+            node = self.select_child(node);
+            board.make_move(self.nodes[node].mv);
+            path.push(node);
+            rule_value = self.rule_value(board);
+            if rule_value.is_some() {
+                break;
+            }
+        }
+
+        // The value for the side to move at the leaf.
+        let mut value = if let Some(value) = rule_value {
+            value
+        } else if !self.nodes[node].expanded {
+            self.expand(node, board)
+        } else if self.nodes[node].num_children == 0 {
+            TERMINAL_VALUE
+        } else {
+            self.value(board)
+        };
+
+        // Each node stores the value for the player who moved into it.
+        for &node in path.iter().rev() {
+            value = -value;
+            self.nodes[node].visits += 1;
+            self.nodes[node].value_sum += value;
+        }
+
+        for _ in 1..path.len() {
+            board.undo_move();
+        }
+        path.len() - 1
+    }
+
+    /// Adds the children of `node` and returns the value of its position. The root
+    /// only gets the children `searchmoves` allows, matched on squares only.
+    fn expand(&mut self, node: usize, board: &mut Board) -> f32 {
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+        self.nodes[node].expanded = true;
+        if legal_moves.is_empty() {
+            return TERMINAL_VALUE;
+        }
+
+        let moves: Vec<Move> = legal_moves
+            .as_slice()
+            .iter()
+            .copied()
+            .filter(|mv| {
+                node != 0
+                    || self.search_moves.is_empty()
+                    || self
+                        .search_moves
+                        .iter()
+                        .any(|m| m.from_sq() == mv.from_sq() && m.to_sq() == mv.to_sq())
+            })
+            .collect();
+        let priors = self.policy(board, &moves);
+        self.nodes[node].first_child = self.nodes.len() as u32;
+        self.nodes[node].num_children = moves.len() as u16;
+        for (mv, prior) in moves.into_iter().zip(priors) {
+            self.nodes.push(Node::new(mv, prior));
+        }
+        self.value(board)
+    }
+
+    /// Picks the child of `node` with the highest PUCT score.
+    fn select_child(&self, node: usize) -> usize {
+        let parent = &self.nodes[node];
+        let sqrt_visits = (parent.visits as f32).sqrt();
+
+        let mut best_child = parent.first_child as usize;
+        let mut best_score = f32::NEG_INFINITY;
+        for child in parent.children() {
+            let c = &self.nodes[child];
+            let score = c.q() + PUCT_C * c.prior * sqrt_visits / (1 + c.visits) as f32;
+            if score > best_score {
+                best_score = score;
+                best_child = child;
+            }
+        }
+        best_child
+    }
+
+    /// The most visited child of `node`, if it has been visited at all.
+    fn best_child(&self, node: usize) -> Option<usize> {
+        self.nodes[node]
+            .children()
+            .filter(|&child| self.nodes[child].visits > 0)
+            .max_by_key(|&child| self.nodes[child].visits)
+    }
+
+    /// Follows the most visited children from the root.
+    fn principal_variation(&self) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut node = 0;
+        while let Some(child) = self.best_child(node) {
+            pv.push(self.nodes[child].mv);
+            node = child;
+        }
+        pv
+    }
+
+    /// The prior probability of each of `moves`.
+    fn policy(&self, _board: &Board, moves: &[Move]) -> Vec<f32> {
+        vec![1.0 / moves.len() as f32; moves.len()]
+    }
+
+    /// The value for the side to move of a position the rules end the game in, by a
+    /// third repetition or the move rule, scored like the alpha-beta search does.
+    fn rule_value(&self, board: &mut Board) -> Option<f32> {
+        if let Some(start) = board.third_repetition_start() {
+            return Some(match self.config.rules.repetition_outcome(board, start).loser() {
+                Some(player) if player == board.player_to_move => -1.0,
+                Some(_) => 1.0,
+                None => 0.0,
+            });
+        }
+        let plies = self.config.rules.move_rule_plies()?;
+        board.is_move_rule_draw(plies).then_some(0.0)
+    }
+
+    /// The value of `board` in [-1, 1] for the side to move.
+    fn value(&self, board: &Board) -> f32 {
+        (evaluate::evaluate(board, &self.config) as f32 / VALUE_SCALE).tanh()
+    }
+}
+
+impl Searcher for MctsSearcher {
+    fn search(&mut self, board: &mut Board, limits: &SearchLimits) -> SearchResult {
+        MctsSearcher::search(self, board, limits)
+    }
+
+    fn new_game(&mut self) {
+        self.nodes.clear();
+    }
+
+    fn stop_token(&self) -> StopToken {
+        self.stop.clone()
+    }
+}
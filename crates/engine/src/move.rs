@@ -1,26 +1,32 @@
 //! Defines the representation of a move in the engine.
 
 use crate::constants::Piece;
+use crate::square::Square;
 
 /// Represents a single move.
 ///
-/// A move is encoded as a 16-bit integer:
+/// A move is encoded in the low 18 bits of a 32-bit integer:
 /// - Bits 0-6:   from_sq (0-89)
 /// - Bits 7-13:  to_sq (0-89)
-/// - Bits 14-15: flags (e.g., capture, promotion - though Xiangqi has no promotion)
+/// - Bits 14-17: the captured piece as a 4-bit signed value, 0 for a quiet move
+///
+/// Since the captured piece is part of the move, a move taken from another position,
+/// such as a transposition table move, only equals a generated move if it captures
+/// the same piece.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Move(u16);
+pub struct Move(u32);
+
+/// The number of bits of a move's encoding that are in use.
+pub const MOVE_BITS: u32 = 18;
 
 impl Move {
     /// Creates a new move.
-    pub fn new(from_sq: usize, to_sq: usize, captured_piece: Option<Piece>) -> Self {
-        let mut move_val = (from_sq as u16) | ((to_sq as u16) << 7);
-        if captured_piece.is_some() {
-            // For now, we can use a simple flag for captures.
-            // A more robust system could store the captured piece type.
-            move_val |= 1 << 14;
-        }
-        Move(move_val)
+    pub const fn new(from_sq: usize, to_sq: usize, captured_piece: Option<Piece>) -> Self {
+        let captured = match captured_piece {
+            Some(piece) => piece as i8 as u32 & 0xF,
+            None => 0,
+        };
+        Move((from_sq as u32) | ((to_sq as u32) << 7) | (captured << 14))
     }
 
     /// Gets the source square.
@@ -33,34 +39,50 @@ impl Move {
         ((self.0 >> 7) & 0x7F) as usize
     }
 
+    /// The source square.
+    pub fn from(&self) -> Square {
+        Square::from_index(self.from_sq()).unwrap()
+    }
+
+    /// The destination square.
+    pub fn to(&self) -> Square {
+        Square::from_index(self.to_sq()).unwrap()
+    }
+
     /// Checks if the move is a capture.
     pub fn is_capture(&self) -> bool {
-        (self.0 >> 14) & 1 != 0
+        (self.0 >> 14) & 0xF != 0
+    }
+
+    /// Gets the captured piece, `Piece::Empty` for a quiet move.
+    pub fn captured(&self) -> Piece {
+        // Shift the 4-bit value to the top of an i8 and back to sign-extend it.
+        Piece::from_abs((((self.0 >> 14) as u8) << 4) as i8 >> 4)
     }
 
     /// Returns a mirrored version of the move.
     pub fn mirrored(&self) -> Self {
-        let from = self.from_sq();
-        let to = self.to_sq();
+        let mirrored_from = self.from().mirrored().index();
+        let mirrored_to = self.to().mirrored().index();
 
-        let mirrored_from = (from / 9) * 9 + (8 - (from % 9));
-        let mirrored_to = (to / 9) * 9 + (8 - (to % 9));
+        Move((mirrored_from as u32) | ((mirrored_to as u32) << 7) | (self.0 & (0xF << 14)))
+    }
+
+    /// Returns the raw encoding of the move, which fits in `MOVE_BITS` bits.
+    pub fn to_u32(&self) -> u32 {
+        self.0
+    }
 
-        let mut move_val = (mirrored_from as u16) | ((mirrored_to as u16) << 7);
-        if self.is_capture() {
-            move_val |= 1 << 14;
-        }
-        Move(move_val)
+    /// Creates a move from its raw encoding, as returned by `to_u32`. Returns `None`
+    /// if either square is off the board, so that `from` and `to` cannot fail.
+    pub fn from_u32(value: u32) -> Option<Self> {
+        let mv = Move(value & ((1 << MOVE_BITS) - 1));
+        (mv.from_sq() < Square::COUNT && mv.to_sq() < Square::COUNT).then_some(mv)
     }
 
+    /// Formats the move in ICCS coordinates, see `notation::iccs`.
     pub fn to_uci_string(&self) -> String {
-        let from_sq = self.from_sq();
-        let to_sq = self.to_sq();
-        let from_file = (from_sq % 9) as u8 + b'a';
-        let from_rank = 9 - (from_sq / 9) as u8;
-        let to_file = (to_sq % 9) as u8 + b'a';
-        let to_rank = 9 - (to_sq / 9) as u8;
-        format!("{}{}{}{}", from_file as char, from_rank, to_file as char, to_rank)
+        crate::notation::iccs::format(*self)
     }
 }
 
@@ -1,23 +1,16 @@
 //! Move generation logic, including pre-computed attack tables.
 
-use crate::bitboard::{Bitboard, SQUARE_MASKS};
-use once_cell::sync::Lazy;
+use crate::bitboard::utils::{self, Direction};
+use crate::bitboard::{Bitboard, FILE_MASKS, SQUARE_MASKS};
+use crate::constants::{Piece, Player};
 
 // Helper functions matching the C implementation
 pub const fn sq_to_idx(r: usize, c: usize) -> usize { r * 9 + c }
 const fn is_valid(r: isize, c: isize) -> bool { r >= 0 && r < 10 && c >= 0 && c < 9 }
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Direction {
-    North,
-    East,
-    South,
-    West,
-}
-
 /// A struct to hold all the pre-computed attack tables.
-/// The tables are initialized once and then accessed globally.
+/// The tables are computed at compile time and live in static data.
 pub struct AttackTables {
     pub king: [Bitboard; 90],
     pub guard: [Bitboard; 90],
@@ -26,176 +19,295 @@ pub struct AttackTables {
     pub horse: [Bitboard; 90],
     pub horse_legs: [[usize; 90]; 90],
     pub pawn: [[Bitboard; 90]; 2], // [player_idx][square]
+    pub pawn_attackers: [[Bitboard; 90]; 2], // [player_idx][square]: the squares of the player's pawns attacking the square
     pub rays: [[Bitboard; 90]; 4], // [direction][square]
+    pub rank_rook: [[u16; 512]; 9], // [file][rank occupancy]: the files a rook reaches on its rank
+    pub rank_cannon: [[u16; 512]; 9], // [file][rank occupancy]: the files a cannon moves or captures to on its rank
+    pub file_rook: [[u16; 1024]; 10], // [rank][file occupancy]: the ranks a rook reaches on its file
+    pub file_cannon: [[u16; 1024]; 10], // [rank][file occupancy]: the ranks a cannon moves or captures to on its file
+    pub file_spread: [Bitboard; 1024], // [set of ranks]: those ranks' squares on the first file
     pub red_half_mask: Bitboard,
     pub black_half_mask: Bitboard,
 }
 
+// Const fns cannot use `for` loops or the bitboard operators, so the tables are filled
+// with `while` loops and `with_square`.
 impl AttackTables {
-    fn new() -> Self {
+    const fn new() -> Self {
         let mut tables = AttackTables {
-            king: [0; 90],
-            guard: [0; 90],
-            bishop: [0; 90],
+            king: [Bitboard::EMPTY; 90],
+            guard: [Bitboard::EMPTY; 90],
+            bishop: [Bitboard::EMPTY; 90],
             bishop_legs: [[0; 90]; 90],
-            horse: [0; 90],
+            horse: [Bitboard::EMPTY; 90],
             horse_legs: [[0; 90]; 90],
-            pawn: [[0; 90]; 2],
-            rays: [[0; 90]; 4],
-            red_half_mask: 0,
-            black_half_mask: 0,
+            pawn: [[Bitboard::EMPTY; 90]; 2],
+            pawn_attackers: [[Bitboard::EMPTY; 90]; 2],
+            rays: [[Bitboard::EMPTY; 90]; 4],
+            rank_rook: [[0; 512]; 9],
+            rank_cannon: [[0; 512]; 9],
+            file_rook: [[0; 1024]; 10],
+            file_cannon: [[0; 1024]; 10],
+            file_spread: [Bitboard::EMPTY; 1024],
+            red_half_mask: Bitboard::EMPTY,
+            black_half_mask: Bitboard::EMPTY,
         };
 
         tables.precompute_king_and_guard_attacks();
         tables.precompute_bishop_and_horse_attacks();
         tables.precompute_pawn_attacks();
         tables.precompute_rays();
+        tables.precompute_line_moves();
         tables.precompute_side_masks();
 
         tables
     }
 
-    fn precompute_king_and_guard_attacks(&mut self) {
-        for r in 0..10 {
-            for c in 0..9 {
-                let sq = sq_to_idx(r, c);
+    const fn precompute_king_and_guard_attacks(&mut self) {
+        const KING_STEPS: [(isize, isize); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+        const GUARD_STEPS: [(isize, isize); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+        let mut sq = 0;
+        while sq < 90 {
+            let (r, c) = ((sq / 9) as isize, (sq % 9) as isize);
+            let mut i = 0;
+            while i < 4 {
                 // King
-                for (dr, dc) in [(0, 1), (0, -1), (1, 0), (-1, 0)] {
-                    let (nr, nc) = (r as isize + dr, c as isize + dc);
-                    if nc >= 3 && nc <= 5 && ((nr >= 0 && nr <= 2) || (nr >= 7 && nr <= 9)) {
-                        self.king[sq] |= SQUARE_MASKS[sq_to_idx(nr as usize, nc as usize)];
-                    }
+                let (nr, nc) = (r + KING_STEPS[i].0, c + KING_STEPS[i].1);
+                if in_a_palace(nr, nc) {
+                    self.king[sq] = with_square(self.king[sq], sq_to_idx(nr as usize, nc as usize));
                 }
                 // Guard
-                for (dr, dc) in [(1, 1), (1, -1), (-1, 1), (-1, -1)] {
-                    let (nr, nc) = (r as isize + dr, c as isize + dc);
-                    if nc >= 3 && nc <= 5 && ((nr >= 0 && nr <= 2) || (nr >= 7 && nr <= 9)) {
-                        self.guard[sq] |= SQUARE_MASKS[sq_to_idx(nr as usize, nc as usize)];
-                    }
+                let (nr, nc) = (r + GUARD_STEPS[i].0, c + GUARD_STEPS[i].1);
+                if in_a_palace(nr, nc) {
+                    self.guard[sq] = with_square(self.guard[sq], sq_to_idx(nr as usize, nc as usize));
+                }
+                i += 1;
+            }
+            sq += 1;
+        }
+    }
+
+    const fn precompute_bishop_and_horse_attacks(&mut self) {
+        const BISHOP_STEPS: [(isize, isize); 4] = [(2, 2), (2, -2), (-2, 2), (-2, -2)];
+        const HORSE_STEPS: [(isize, isize); 8] = [(2, 1), (2, -1), (-2, 1), (-2, -1), (1, 2), (1, -2), (-1, 2), (-1, -2)];
+        let mut from_sq = 0;
+        while from_sq < 90 {
+            let (r, c) = ((from_sq / 9) as isize, (from_sq % 9) as isize);
+            // Bishop
+            let mut i = 0;
+            while i < 4 {
+                let (dr, dc) = BISHOP_STEPS[i];
+                let (nr, nc) = (r + dr, c + dc);
+                if is_valid(nr, nc) {
+                    let to_sq = sq_to_idx(nr as usize, nc as usize);
+                    self.bishop[from_sq] = with_square(self.bishop[from_sq], to_sq);
+                    self.bishop_legs[from_sq][to_sq] = sq_to_idx((r + dr / 2) as usize, (c + dc / 2) as usize);
                 }
+                i += 1;
             }
+            // Horse
+            let mut i = 0;
+            while i < 8 {
+                let (dr, dc) = HORSE_STEPS[i];
+                let (nr, nc) = (r + dr, c + dc);
+                if is_valid(nr, nc) {
+                    let to_sq = sq_to_idx(nr as usize, nc as usize);
+                    self.horse[from_sq] = with_square(self.horse[from_sq], to_sq);
+                    let (leg_r, leg_c) = if dr.abs() == 2 { (r + dr / 2, c) } else { (r, c + dc / 2) };
+                    self.horse_legs[from_sq][to_sq] = sq_to_idx(leg_r as usize, leg_c as usize);
+                }
+                i += 1;
+            }
+            from_sq += 1;
         }
     }
 
-    fn precompute_bishop_and_horse_attacks(&mut self) {
-        for r in 0..10 {
-            for c in 0..9 {
-                let from_sq = sq_to_idx(r, c);
-                // Bishop
-                for (dr, dc) in [(2, 2), (2, -2), (-2, 2), (-2, -2)] {
-                    let (nr, nc) = (r as isize + dr, c as isize + dc);
+    const fn precompute_pawn_attacks(&mut self) {
+        let mut sq = 0;
+        while sq < 90 {
+            let (r, c) = ((sq / 9) as isize, (sq % 9) as isize);
+            // Red Pawn (player_idx 0), then Black Pawn (player_idx 1)
+            let mut player_idx = 0;
+            while player_idx < 2 {
+                let (forward, crossed_river) = if player_idx == 0 { (-1, r < 5) } else { (1, r > 4) };
+                let mut targets = [(r + forward, c), (r, c - 1), (r, c + 1)];
+                if !crossed_river {
+                    targets[1] = (-1, -1);
+                    targets[2] = (-1, -1);
+                }
+                let mut i = 0;
+                while i < 3 {
+                    let (nr, nc) = targets[i];
                     if is_valid(nr, nc) {
-                        let to_sq = sq_to_idx(nr as usize, nc as usize);
-                        self.bishop[from_sq] |= SQUARE_MASKS[to_sq];
-                        let leg_sq = sq_to_idx((r as isize + dr / 2) as usize, (c as isize + dc / 2) as usize);
-                        self.bishop_legs[from_sq][to_sq] = leg_sq;
+                        self.pawn[player_idx][sq] = with_square(self.pawn[player_idx][sq], sq_to_idx(nr as usize, nc as usize));
                     }
+                    i += 1;
                 }
-                // Horse
-                for (dr, dc) in [(2, 1), (2, -1), (-2, 1), (-2, -1), (1, 2), (1, -2), (-1, 2), (-1, -2)] {
-                    let (nr, nc) = (r as isize + dr, c as isize + dc);
-                    if is_valid(nr, nc) {
-                        let to_sq = sq_to_idx(nr as usize, nc as usize);
-                        self.horse[from_sq] |= SQUARE_MASKS[to_sq];
-                        let (leg_r, leg_c) = if dr.abs() == 2 { (r as isize + dr/2, c as isize) } else { (r as isize, c as isize + dc/2) };
-                        self.horse_legs[from_sq][to_sq] = sq_to_idx(leg_r as usize, leg_c as usize);
+                player_idx += 1;
+            }
+            sq += 1;
+        }
+        // Pawns move sideways only across the river, so the reverse lookup needs its own table.
+        let mut player_idx = 0;
+        while player_idx < 2 {
+            let mut from_sq = 0;
+            while from_sq < 90 {
+                let targets = self.pawn[player_idx][from_sq].to_u128();
+                let mut to_sq = 0;
+                while to_sq < 90 {
+                    if targets & 1 << to_sq != 0 {
+                        self.pawn_attackers[player_idx][to_sq] = with_square(self.pawn_attackers[player_idx][to_sq], from_sq);
                     }
+                    to_sq += 1;
                 }
+                from_sq += 1;
             }
+            player_idx += 1;
         }
     }
 
-    fn precompute_pawn_attacks(&mut self) {
-        for r in 0..10 {
-            for c in 0..9 {
-                let sq = sq_to_idx(r, c);
-                // Red Pawn (player_idx 0)
-                if is_valid(r as isize - 1, c as isize) { self.pawn[0][sq] |= SQUARE_MASKS[sq_to_idx(r - 1, c)]; }
-                if r < 5 { // Crossed river
-                    if is_valid(r as isize, c as isize - 1) { self.pawn[0][sq] |= SQUARE_MASKS[sq_to_idx(r, c - 1)]; }
-                    if is_valid(r as isize, c as isize + 1) { self.pawn[0][sq] |= SQUARE_MASKS[sq_to_idx(r, c + 1)]; }
-                }
-                // Black Pawn (player_idx 1)
-                if is_valid(r as isize + 1, c as isize) { self.pawn[1][sq] |= SQUARE_MASKS[sq_to_idx(r + 1, c)]; }
-                if r > 4 { // Crossed river
-                    if is_valid(r as isize, c as isize - 1) { self.pawn[1][sq] |= SQUARE_MASKS[sq_to_idx(r, c - 1)]; }
-                    if is_valid(r as isize, c as isize + 1) { self.pawn[1][sq] |= SQUARE_MASKS[sq_to_idx(r, c + 1)]; }
+    const fn precompute_rays(&mut self) {
+        let mut sq = 0;
+        while sq < 90 {
+            let (r, c) = (sq / 9, sq % 9);
+            let mut i = 0;
+            while i < 10 {
+                if i < r { self.rays[Direction::North as usize][sq] = with_square(self.rays[Direction::North as usize][sq], sq_to_idx(i, c)); }
+                if i > c && i < 9 { self.rays[Direction::East as usize][sq] = with_square(self.rays[Direction::East as usize][sq], sq_to_idx(r, i)); }
+                if i > r { self.rays[Direction::South as usize][sq] = with_square(self.rays[Direction::South as usize][sq], sq_to_idx(i, c)); }
+                if i < c { self.rays[Direction::West as usize][sq] = with_square(self.rays[Direction::West as usize][sq], sq_to_idx(r, i)); }
+                i += 1;
+            }
+            sq += 1;
+        }
+    }
+
+    const fn precompute_line_moves(&mut self) {
+        let mut pos = 0;
+        while pos < 10 {
+            let mut occ = 0;
+            while occ < 1024 {
+                if pos < 9 && occ < 512 {
+                    self.rank_rook[pos][occ] = line_moves(pos, occ as u16, 9, false);
+                    self.rank_cannon[pos][occ] = line_moves(pos, occ as u16, 9, true);
                 }
+                self.file_rook[pos][occ] = line_moves(pos, occ as u16, 10, false);
+                self.file_cannon[pos][occ] = line_moves(pos, occ as u16, 10, true);
+                occ += 1;
+            }
+            pos += 1;
+        }
+        let mut ranks = 0;
+        while ranks < 1024 {
+            let mut r = 0;
+            while r < 10 {
+                if ranks & (1 << r) != 0 { self.file_spread[ranks] = with_square(self.file_spread[ranks], sq_to_idx(r, 0)); }
+                r += 1;
             }
+            ranks += 1;
         }
     }
 
-    fn precompute_rays(&mut self) {
-        for sq in 0..90 {
-            let (r, c) = (sq / 9, sq % 9);
-            for i in (0..r).rev() { self.rays[Direction::North as usize][sq] |= SQUARE_MASKS[sq_to_idx(i, c)]; } // North
-            for i in (c + 1)..9 { self.rays[Direction::East as usize][sq] |= SQUARE_MASKS[sq_to_idx(r, i)]; } // East
-            for i in (r + 1)..10 { self.rays[Direction::South as usize][sq] |= SQUARE_MASKS[sq_to_idx(i, c)]; } // South
-            for i in (0..c).rev() { self.rays[Direction::West as usize][sq] |= SQUARE_MASKS[sq_to_idx(r, i)]; } // West
+    const fn precompute_side_masks(&mut self) {
+        let mut sq = 0;
+        while sq < 90 {
+            if sq < 45 {
+                self.black_half_mask = with_square(self.black_half_mask, sq); // Ranks 9-5 (Black's side)
+            } else {
+                self.red_half_mask = with_square(self.red_half_mask, sq); // Ranks 4-0 (Red's side)
+            }
+            sq += 1;
         }
     }
+}
+
+// The global static instance of the attack tables, computed at compile time.
+pub static ATTACK_TABLES: AttackTables = AttackTables::new();
 
-    fn precompute_side_masks(&mut self) {
-        for i in 0..45 { self.black_half_mask |= SQUARE_MASKS[i]; } // Ranks 9-5 (Black's side)
-        for i in 45..90 { self.red_half_mask |= SQUARE_MASKS[i]; } // Ranks 4-0 (Red's side)
+/// `bb` with `sq` added, for the const fns filling the tables.
+const fn with_square(bb: Bitboard, sq: usize) -> Bitboard {
+    Bitboard::from_u128(bb.to_u128() | 1 << sq)
+}
+
+/// Whether the point at row `r` and column `c` lies in either palace.
+const fn in_a_palace(r: isize, c: isize) -> bool {
+    c >= 3 && c <= 5 && ((r >= 0 && r <= 2) || (r >= 7 && r <= 9))
+}
+
+/// The moves of a rook or, if `is_cannon`, a cannon at `pos` on a line of `len` points
+/// occupied at `occ`, as a set of points. Used to fill the occupancy-indexed tables.
+const fn line_moves(pos: usize, occ: u16, len: usize, is_cannon: bool) -> u16 {
+    let mut moves = 0;
+    let mut step = -1isize;
+    while step <= 1 {
+        let mut screened = false;
+        let mut p = pos as isize + step;
+        while p >= 0 && p < len as isize {
+            let point = 1 << p;
+            if occ & point == 0 {
+                if !screened { moves |= point; }
+            } else if is_cannon && !screened {
+                screened = true;
+            } else {
+                moves |= point;
+                break;
+            }
+            p += step;
+        }
+        step += 2;
     }
+    moves
+}
+
+/// The occupancy of rank `r` in `occupied`, one bit per file.
+#[inline(always)]
+fn rank_occupancy(occupied: Bitboard, r: usize) -> usize {
+    ((occupied >> (r * 9)).words()[0] & 0x1FF) as usize
 }
 
-// The global static instance of the attack tables, initialized lazily and only once.
-pub static ATTACK_TABLES: Lazy<AttackTables> = Lazy::new(AttackTables::new);
+/// The occupancy of file `c` in `occupied`, one bit per rank. The first eight ranks of
+/// the file lie nine bits apart in the low word, and the multiplication gathers them
+/// into its top byte; the last two are in the high word.
+#[inline(always)]
+fn file_occupancy(occupied: Bitboard, c: usize) -> usize {
+    let [low, high] = ((occupied >> c) & FILE_MASKS[0]).words();
+    ((low.wrapping_mul(0x0101_0101_0101_0101) >> 56) | (high >> 8 & 1) << 8 | (high >> 17 & 1) << 9) as usize
+}
 
 /// Generates the attack bitboard for a rook on a given square.
+#[inline]
 pub fn get_rook_moves_bb(sq: usize, occupied: Bitboard) -> Bitboard {
-    get_sliding_piece_moves(sq, occupied, false)
+    let (r, c) = (sq / 9, sq % 9);
+    let rank_moves = Bitboard::from_u128(ATTACK_TABLES.rank_rook[c][rank_occupancy(occupied, r)] as u128) << (r * 9);
+    let file_moves = ATTACK_TABLES.file_spread[ATTACK_TABLES.file_rook[r][file_occupancy(occupied, c)] as usize] << c;
+    rank_moves | file_moves
 }
 
 /// Generates the attack bitboard for a cannon on a given square.
+#[inline]
 pub fn get_cannon_moves_bb(sq: usize, occupied: Bitboard) -> Bitboard {
-    get_sliding_piece_moves(sq, occupied, true)
+    let (r, c) = (sq / 9, sq % 9);
+    let rank_moves = Bitboard::from_u128(ATTACK_TABLES.rank_cannon[c][rank_occupancy(occupied, r)] as u128) << (r * 9);
+    let file_moves = ATTACK_TABLES.file_spread[ATTACK_TABLES.file_cannon[r][file_occupancy(occupied, c)] as usize] << c;
+    rank_moves | file_moves
 }
 
-fn get_sliding_piece_moves(sq: usize, occupied: Bitboard, is_cannon: bool) -> Bitboard {
-    let mut final_attacks = 0;
-
-    for dir in [Direction::North, Direction::East, Direction::South, Direction::West] {
-        let ray = ATTACK_TABLES.rays[dir as usize][sq];
-        let blockers = occupied & ray;
-
-        if !is_cannon {
-            if blockers != 0 {
-                let first_blocker = if dir == Direction::North || dir == Direction::West {
-                    127 - blockers.leading_zeros() as usize
-                } else {
-                    blockers.trailing_zeros() as usize
-                };
-                final_attacks |= (ray ^ ATTACK_TABLES.rays[dir as usize][first_blocker]) | SQUARE_MASKS[first_blocker];
+/// Generates the squares a cannon on a given square attacks: those behind exactly one
+/// screen, up to and including the next piece.
+pub fn get_cannon_attacks_bb(sq: usize, occupied: Bitboard) -> Bitboard {
+    let mut attacks = Bitboard::EMPTY;
+    for dir in Direction::ALL {
+        let blockers = occupied & ATTACK_TABLES.rays[dir as usize][sq];
+        if !blockers.is_empty() {
+            let screen = if dir == Direction::North || dir == Direction::West {
+                127 - blockers.leading_zeros() as usize
             } else {
-                final_attacks |= ray;
-            }
-        } else {
-            if blockers != 0 {
-                let screen = if dir == Direction::North || dir == Direction::West {
-                    127 - blockers.leading_zeros() as usize
-                } else {
-                    blockers.trailing_zeros() as usize
-                };
-                final_attacks |= (ray ^ ATTACK_TABLES.rays[dir as usize][screen]) ^ SQUARE_MASKS[screen];
-                let remaining_blockers = blockers ^ SQUARE_MASKS[screen];
-                if remaining_blockers != 0 {
-                    let target = if dir == Direction::North || dir == Direction::West {
-                        127 - remaining_blockers.leading_zeros() as usize
-                    } else {
-                        remaining_blockers.trailing_zeros() as usize
-                    };
-                    final_attacks |= SQUARE_MASKS[target];
-                }
-            } else {
-                final_attacks |= ray;
-            }
+                blockers.trailing_zeros() as usize
+            };
+            attacks |= get_rook_moves_bb(screen, occupied) & ATTACK_TABLES.rays[dir as usize][screen];
         }
     }
-
-    final_attacks
+    attacks
 }
 
 /// Checks if a given square is attacked by the specified player.
@@ -223,42 +335,36 @@ pub fn is_square_attacked_by(board: &crate::bitboard::Board, sq: usize, attacker
 
 fn is_attacked_by_pawn(board: &crate::bitboard::Board, sq: usize, attacker_player: crate::constants::Player) -> bool {
     let pawn_type = if attacker_player == crate::constants::Player::Red { crate::constants::Piece::RPawn } else { crate::constants::Piece::BPawn };
-    let defender_idx = if attacker_player == crate::constants::Player::Red { 1 } else { 0 };
-    (ATTACK_TABLES.pawn[defender_idx][sq] & board.piece_bitboards[pawn_type.get_bb_index().unwrap()]) != 0
+    !(ATTACK_TABLES.pawn_attackers[attacker_player.get_bb_idx()][sq] & board.piece_bitboards[pawn_type.get_bb_index().unwrap()]).is_empty()
 }
 
 fn is_attacked_by_king(board: &crate::bitboard::Board, sq: usize, attacker_player: crate::constants::Player) -> bool {
     let king_type = if attacker_player == crate::constants::Player::Red { crate::constants::Piece::RKing } else { crate::constants::Piece::BKing };
-    (ATTACK_TABLES.king[sq] & board.piece_bitboards[king_type.get_bb_index().unwrap()]) != 0
+    !(ATTACK_TABLES.king[sq] & board.piece_bitboards[king_type.get_bb_index().unwrap()]).is_empty()
 }
 
 fn is_attacked_by_horse(board: &crate::bitboard::Board, sq: usize, attacker_player: crate::constants::Player) -> bool {
     let horse_type = if attacker_player == crate::constants::Player::Red { crate::constants::Piece::RHorse } else { crate::constants::Piece::BHorse };
-    let mut potential_horses = ATTACK_TABLES.horse[sq] & board.piece_bitboards[horse_type.get_bb_index().unwrap()];
-    while potential_horses != 0 {
-        let from_sq = potential_horses.trailing_zeros() as usize;
+    for from_sq in ATTACK_TABLES.horse[sq] & board.piece_bitboards[horse_type.get_bb_index().unwrap()] {
         let leg_sq = ATTACK_TABLES.horse_legs[from_sq][sq];
-        if (board.occupied_bitboard() & SQUARE_MASKS[leg_sq]) == 0 {
+        if (board.occupied_bitboard() & SQUARE_MASKS[leg_sq]).is_empty() {
             return true;
         }
-        potential_horses &= !SQUARE_MASKS[from_sq];
     }
     false
 }
 
 fn is_attacked_by_bishop(board: &crate::bitboard::Board, sq: usize, attacker_player: crate::constants::Player) -> bool {
     let bishop_type = if attacker_player == crate::constants::Player::Red { crate::constants::Piece::RBishop } else { crate::constants::Piece::BBishop };
-    let mut potential_bishops = ATTACK_TABLES.bishop[sq] & board.piece_bitboards[bishop_type.get_bb_index().unwrap()];
-    if potential_bishops != 0 {
+    let potential_bishops = ATTACK_TABLES.bishop[sq] & board.piece_bitboards[bishop_type.get_bb_index().unwrap()];
+    if !potential_bishops.is_empty() {
         let side_mask = if attacker_player == crate::constants::Player::Red { ATTACK_TABLES.red_half_mask } else { ATTACK_TABLES.black_half_mask };
-        if (side_mask & SQUARE_MASKS[sq]) != 0 { // Bishops can only attack on their own side
-            while potential_bishops != 0 {
-                let from_sq = potential_bishops.trailing_zeros() as usize;
+        if !(side_mask & SQUARE_MASKS[sq]).is_empty() { // Bishops can only attack on their own side
+            for from_sq in potential_bishops {
                 let leg_sq = ATTACK_TABLES.bishop_legs[from_sq][sq];
-                if (board.occupied_bitboard() & SQUARE_MASKS[leg_sq]) == 0 {
+                if (board.occupied_bitboard() & SQUARE_MASKS[leg_sq]).is_empty() {
                     return true;
                 }
-                potential_bishops &= !SQUARE_MASKS[from_sq];
             }
         }
     }
@@ -267,18 +373,18 @@ fn is_attacked_by_bishop(board: &crate::bitboard::Board, sq: usize, attacker_pla
 
 fn is_attacked_by_rook(board: &crate::bitboard::Board, sq: usize, attacker_player: crate::constants::Player) -> bool {
     let rook_type = if attacker_player == crate::constants::Player::Red { crate::constants::Piece::RRook } else { crate::constants::Piece::BRook };
-    (get_rook_moves_bb(sq, board.occupied_bitboard()) & board.piece_bitboards[rook_type.get_bb_index().unwrap()]) != 0
+    !(get_rook_moves_bb(sq, board.occupied_bitboard()) & board.piece_bitboards[rook_type.get_bb_index().unwrap()]).is_empty()
 }
 
 fn is_attacked_by_cannon(board: &crate::bitboard::Board, sq: usize, attacker_player: crate::constants::Player) -> bool {
     let cannon_type = if attacker_player == crate::constants::Player::Red { crate::constants::Piece::RCannon } else { crate::constants::Piece::BCannon };
-    (get_cannon_moves_bb(sq, board.occupied_bitboard()) & board.piece_bitboards[cannon_type.get_bb_index().unwrap()]) != 0
+    !(get_cannon_moves_bb(sq, board.occupied_bitboard()) & board.piece_bitboards[cannon_type.get_bb_index().unwrap()]).is_empty()
 }
 
 pub fn is_king_in_check(board: &crate::bitboard::Board, player: crate::constants::Player) -> bool {
     let king_piece = if player == crate::constants::Player::Red { crate::constants::Piece::RKing } else { crate::constants::Piece::BKing };
     let king_bb = board.piece_bitboards[king_piece.get_bb_index().unwrap()];
-    if king_bb == 0 { return true; } // Should not happen
+    if king_bb.is_empty() { return true; } // Should not happen
     let king_sq = king_bb.trailing_zeros() as usize;
 
     // 1. Check if attacked by opponent's pieces using the general attack checker
@@ -289,30 +395,200 @@ pub fn is_king_in_check(board: &crate::bitboard::Board, player: crate::constants
     // 2. Check for "flying general"
     let opponent_king_piece = if player == crate::constants::Player::Red { crate::constants::Piece::BKing } else { crate::constants::Piece::RKing };
     let opponent_king_bb = board.piece_bitboards[opponent_king_piece.get_bb_index().unwrap()];
-    if opponent_king_bb == 0 { return false; } // No opponent king, no check
+    if opponent_king_bb.is_empty() { return false; } // No opponent king, no check
     let opponent_king_sq = opponent_king_bb.trailing_zeros() as usize;
 
     if (king_sq % 9) != (opponent_king_sq % 9) {
         return false;
     }
 
-    let occupied = board.occupied_bitboard();
-    let min_sq = king_sq.min(opponent_king_sq);
-    let max_sq = king_sq.max(opponent_king_sq);
-    
-    let mut between_mask = 0;
-    for s in (min_sq + 9)..max_sq {
-        if s % 9 == king_sq % 9 { // Ensure it's on the same file
-            between_mask |= crate::bitboard::SQUARE_MASKS[s];
+    // Flying general check
+    (board.occupied_bitboard() & utils::between(king_sq, opponent_king_sq)).is_empty()
+}
+
+/// The nearest to the ray's origin of `blockers`, which must all lie on the ray in `dir`.
+fn nearest_on_ray(dir: Direction, blockers: Bitboard) -> usize {
+    if dir == Direction::North || dir == Direction::West {
+        127 - blockers.leading_zeros() as usize
+    } else {
+        blockers.trailing_zeros() as usize
+    }
+}
+
+/// The bitboard of `player`'s pieces of the kind of `red_piece`.
+fn pieces_of(board: &crate::bitboard::Board, red_piece: Piece, player: Player) -> Bitboard {
+    board.piece_bitboards[Piece::from_abs(red_piece as i8 * player as i8).get_bb_index().unwrap()]
+}
+
+/// All pieces of `attacker_player` attacking a given square.
+pub fn attackers_to(board: &crate::bitboard::Board, sq: usize, attacker_player: Player) -> Bitboard {
+    attackers_to_occupied(board, sq, attacker_player, board.occupied_bitboard())
+}
+
+/// All pieces of `attacker_player` attacking a given square if the occupied squares
+/// were `occupied`.
+fn attackers_to_occupied(board: &crate::bitboard::Board, sq: usize, attacker_player: Player, occupied: Bitboard) -> Bitboard {
+    let mut attackers = Bitboard::EMPTY;
+    attackers |= ATTACK_TABLES.pawn_attackers[attacker_player.get_bb_idx()][sq] & pieces_of(board, Piece::RPawn, attacker_player);
+    // The king and guard tables also hold moves from outside the palace, so these pieces
+    // are looked up from their own squares.
+    for from_sq in pieces_of(board, Piece::RKing, attacker_player) | pieces_of(board, Piece::RGuard, attacker_player) {
+        let targets = if board.board[from_sq].abs_val() == Piece::RKing as u8 { ATTACK_TABLES.king[from_sq] } else { ATTACK_TABLES.guard[from_sq] };
+        if !(targets & SQUARE_MASKS[sq]).is_empty() {
+            attackers |= SQUARE_MASKS[from_sq];
         }
     }
+    attackers |= get_rook_moves_bb(sq, occupied) & pieces_of(board, Piece::RRook, attacker_player);
+    attackers |= get_cannon_moves_bb(sq, occupied) & pieces_of(board, Piece::RCannon, attacker_player);
 
-    if (occupied & between_mask) == 0 {
-        return true; // Flying general check
+    for from_sq in ATTACK_TABLES.horse[sq] & pieces_of(board, Piece::RHorse, attacker_player) {
+        if (occupied & SQUARE_MASKS[ATTACK_TABLES.horse_legs[from_sq][sq]]).is_empty() {
+            attackers |= SQUARE_MASKS[from_sq];
+        }
     }
 
-    false
+    let side_mask = if attacker_player == Player::Red { ATTACK_TABLES.red_half_mask } else { ATTACK_TABLES.black_half_mask };
+    if !(side_mask & SQUARE_MASKS[sq]).is_empty() { // Bishops can only attack on their own side
+        for from_sq in ATTACK_TABLES.bishop[sq] & pieces_of(board, Piece::RBishop, attacker_player) {
+            if (occupied & SQUARE_MASKS[ATTACK_TABLES.bishop_legs[from_sq][sq]]).is_empty() {
+                attackers |= SQUARE_MASKS[from_sq];
+            }
+        }
+    }
+    attackers
 }
 
+/// The opponent's pieces giving check to `player`'s king, the opponent's king included
+/// when the two kings face each other.
+pub fn checkers(board: &crate::bitboard::Board, player: Player) -> Bitboard {
+    let king_bb = pieces_of(board, Piece::RKing, player);
+    if king_bb.is_empty() { return Bitboard::EMPTY; }
+    let king_sq = king_bb.trailing_zeros() as usize;
+    let opponent = player.opponent();
+
+    // Kings never share a rank, so the rook moves only reach the opponent's king on the file.
+    let facing_king = get_rook_moves_bb(king_sq, board.occupied_bitboard()) & pieces_of(board, Piece::RKing, opponent);
+    attackers_to(board, king_sq, opponent) | facing_king
+}
 
+/// The pieces of `player` shielding its king from a check: a piece alone between the
+/// king and an enemy rook or the enemy king, `player`'s pieces among the two between
+/// the king and an enemy cannon, and a piece on the leg of an enemy horse aimed at the
+/// king. A pinned piece on a line with the king may still move along that line.
+pub fn pinned(board: &crate::bitboard::Board, player: Player) -> Bitboard {
+    let king_bb = pieces_of(board, Piece::RKing, player);
+    if king_bb.is_empty() { return Bitboard::EMPTY; }
+    let king_sq = king_bb.trailing_zeros() as usize;
+    let opponent = player.opponent();
+    let occupied = board.occupied_bitboard();
+    let own = board.color_bitboards[player.get_bb_idx()];
+    let rooks = pieces_of(board, Piece::RRook, opponent) | pieces_of(board, Piece::RKing, opponent);
+    let cannons = pieces_of(board, Piece::RCannon, opponent);
+
+    let mut pinned = Bitboard::EMPTY;
+    for dir in Direction::ALL {
+        // The first three pieces on the ray, nearest first.
+        let mut blockers = occupied & ATTACK_TABLES.rays[dir as usize][king_sq];
+        let mut line = [0; 3];
+        let mut count = 0;
+        while !blockers.is_empty() && count < 3 {
+            line[count] = nearest_on_ray(dir, blockers);
+            blockers &= !SQUARE_MASKS[line[count]];
+            count += 1;
+        }
+        if count >= 2 && !(rooks & SQUARE_MASKS[line[1]]).is_empty() {
+            pinned |= own & SQUARE_MASKS[line[0]];
+        }
+        if count == 3 && !(cannons & SQUARE_MASKS[line[2]]).is_empty() {
+            pinned |= own & (SQUARE_MASKS[line[0]] | SQUARE_MASKS[line[1]]);
+        }
+    }
 
+    for from_sq in ATTACK_TABLES.horse[king_sq] & pieces_of(board, Piece::RHorse, opponent) {
+        pinned |= own & SQUARE_MASKS[ATTACK_TABLES.horse_legs[from_sq][king_sq]];
+    }
+    pinned
+}
+
+/// Where each of `player`'s pieces other than the king may move without leaving the
+/// king in check, by square, so moves need not be tried on the board: everywhere for
+/// a piece nothing is lined up against. A checker must be captured or, except for a
+/// pawn, blocked. A piece alone between the king and an enemy rook or the enemy king,
+/// either of two between the king and an enemy cannon and a piece on the leg of an
+/// enemy horse must stay in the way. The screen of a checking cannon must leave the
+/// line instead, and no piece may become the screen of a cannon with nothing between
+/// it and the king. King moves are checked with `is_king_move_safe`.
+pub fn legal_targets(board: &crate::bitboard::Board, player: Player) -> [Bitboard; 90] {
+    let mut targets = [!Bitboard::EMPTY; 90];
+    let king_bb = pieces_of(board, Piece::RKing, player);
+    if king_bb.is_empty() { return targets; }
+    let king_sq = king_bb.trailing_zeros() as usize;
+    let opponent = player.opponent();
+    let occupied = board.occupied_bitboard();
+    let own = board.color_bitboards[player.get_bb_idx()] & !king_bb;
+    let rooks = pieces_of(board, Piece::RRook, opponent) | pieces_of(board, Piece::RKing, opponent);
+    let cannons = pieces_of(board, Piece::RCannon, opponent);
+    let mut limit = |pieces: Bitboard, allowed: Bitboard| {
+        for sq in pieces & own {
+            targets[sq] &= allowed;
+        }
+    };
+
+    for dir in Direction::ALL {
+        // The first three pieces on the ray, nearest first.
+        let mut blockers = occupied & ATTACK_TABLES.rays[dir as usize][king_sq];
+        let mut line = [0; 3];
+        let mut count = 0;
+        while !blockers.is_empty() && count < 3 {
+            line[count] = nearest_on_ray(dir, blockers);
+            blockers &= !SQUARE_MASKS[line[count]];
+            count += 1;
+        }
+        for (i, &attacker_sq) in line.iter().enumerate().take(count) {
+            let attacker = SQUARE_MASKS[attacker_sq];
+            let between = utils::between(king_sq, attacker_sq);
+            // Moves capturing the attacker or adding a piece between it and the king.
+            let block_or_capture = (between & !occupied) | attacker;
+            if !(rooks & attacker).is_empty() {
+                match i {
+                    0 => limit(own, block_or_capture),
+                    1 => limit(SQUARE_MASKS[line[0]], block_or_capture),
+                    _ => {}
+                }
+            } else if !(cannons & attacker).is_empty() {
+                match i {
+                    0 => limit(own, !between),
+                    1 => {
+                        limit(own & !SQUARE_MASKS[line[0]], block_or_capture);
+                        limit(SQUARE_MASKS[line[0]], !between);
+                    }
+                    _ => limit(SQUARE_MASKS[line[0]] | SQUARE_MASKS[line[1]], block_or_capture),
+                }
+            }
+        }
+    }
+
+    for from_sq in ATTACK_TABLES.horse[king_sq] & pieces_of(board, Piece::RHorse, opponent) {
+        let leg = SQUARE_MASKS[ATTACK_TABLES.horse_legs[from_sq][king_sq]];
+        if (occupied & leg).is_empty() {
+            limit(own, leg | SQUARE_MASKS[from_sq]);
+        } else {
+            limit(leg, SQUARE_MASKS[from_sq]);
+        }
+    }
+
+    for from_sq in ATTACK_TABLES.pawn_attackers[opponent.get_bb_idx()][king_sq] & pieces_of(board, Piece::RPawn, opponent) {
+        limit(own, SQUARE_MASKS[from_sq]);
+    }
+    targets
+}
+
+/// Whether `player`'s king can move from `from_sq` to `to_sq` without being in check
+/// there, from the enemy king on its file included.
+pub fn is_king_move_safe(board: &crate::bitboard::Board, player: Player, from_sq: usize, to_sq: usize) -> bool {
+    let occupied = (board.occupied_bitboard() & !SQUARE_MASKS[from_sq]) | SQUARE_MASKS[to_sq];
+    let opponent = player.opponent();
+    let attackers = attackers_to_occupied(board, to_sq, opponent, occupied) | (get_rook_moves_bb(to_sq, occupied) & pieces_of(board, Piece::RKing, opponent));
+    // A piece captured on `to_sq` attacks nothing.
+    (attackers & !SQUARE_MASKS[to_sq]).is_empty()
+}
@@ -41,6 +41,87 @@ impl MoveList {
     pub fn as_mut_slice(&mut self) -> &mut [Move] {
         &mut self.moves[0..self.count]
     }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Move> {
+        self.as_slice().iter()
+    }
+
+    /// Adds every move of `moves`, as far as there is room.
+    pub fn extend_from_slice(&mut self, moves: &[Move]) {
+        for &mv in moves {
+            self.add(mv);
+        }
+    }
+
+    /// Keeps only the moves for which `keep` returns true, in their order.
+    pub fn retain(&mut self, mut keep: impl FnMut(Move) -> bool) {
+        let mut kept = 0;
+        for i in 0..self.count {
+            let mv = self.moves[i];
+            if keep(mv) {
+                self.moves[kept] = mv;
+                kept += 1;
+            }
+        }
+        self.count = kept;
+    }
+
+    pub fn sort_unstable_by_key<K: Ord>(&mut self, key: impl FnMut(&Move) -> K) {
+        self.as_mut_slice().sort_unstable_by_key(key);
+    }
+
+    /// Sorts the moves by `score`, highest first, keeping the order of moves with equal
+    /// scores. Each move is scored once, unlike with `sort_unstable_by_key`.
+    pub fn sort_by_score(&mut self, mut score: impl FnMut(Move) -> i32) {
+        let mut scores = [0; MAX_MOVES];
+        for (mv_score, &mv) in scores.iter_mut().zip(self.as_slice()) {
+            *mv_score = score(mv);
+        }
+        // Insertion sort: move lists are short, and it is stable.
+        for i in 1..self.count {
+            let (mv, mv_score) = (self.moves[i], scores[i]);
+            let mut j = i;
+            while j > 0 && scores[j - 1] < mv_score {
+                self.moves[j] = self.moves[j - 1];
+                scores[j] = scores[j - 1];
+                j -= 1;
+            }
+            self.moves[j] = mv;
+            scores[j] = mv_score;
+        }
+    }
+}
+
+impl Extend<Move> for MoveList {
+    fn extend<I: IntoIterator<Item = Move>>(&mut self, moves: I) {
+        for mv in moves {
+            self.add(mv);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = std::iter::Take<std::array::IntoIter<Move, MAX_MOVES>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.moves.into_iter().take(self.count)
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Index<usize> for MoveList {
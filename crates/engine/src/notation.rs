@@ -0,0 +1,4 @@
+//! Move notations for game records and user interfaces.
+
+pub mod chinese;
+pub mod iccs;
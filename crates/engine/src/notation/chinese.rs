@@ -0,0 +1,198 @@
+//! Traditional Chinese move notation, such as 炮二平五 or 马8进7.
+//!
+//! A move is written as the piece and the file it stands on, the direction of the
+//! move (进 forward, 退 backward, 平 sideways), and then either the file it moves to
+//! or, for pieces moving in a straight line forward or backward, the number of ranks.
+//! Files are counted from each side's right: Red writes them in Chinese numerals,
+//! Black in Arabic digits.
+//!
+//! Identical pieces on the same file are told apart by their order from the front
+//! (前 front, 中 middle, 后 rear, or 一 to 五 for four or more pawns). If two files
+//! hold several pawns each, the file number takes the place of the piece.
+
+use crate::bitboard::Board;
+use crate::constants::{Piece, Player};
+use crate::r#move::Move;
+use crate::movelist::MoveList;
+
+const RED_NUMERALS: [char; 9] = ['一', '二', '三', '四', '五', '六', '七', '八', '九'];
+const BLACK_NUMERALS: [char; 9] = ['1', '2', '3', '4', '5', '6', '7', '8', '9'];
+
+fn piece_char(piece: Piece) -> char {
+    match piece {
+        Piece::RKing => '帅',
+        Piece::RGuard => '仕',
+        Piece::RBishop => '相',
+        Piece::RHorse => '马',
+        Piece::RRook => '车',
+        Piece::RCannon => '炮',
+        Piece::RPawn => '兵',
+        Piece::BKing => '将',
+        Piece::BGuard => '士',
+        Piece::BBishop => '象',
+        Piece::BHorse => '马',
+        Piece::BRook => '车',
+        Piece::BCannon => '炮',
+        Piece::BPawn => '卒',
+        Piece::Empty => ' ',
+    }
+}
+
+/// The character for `piece` on a drawn board. Unlike in move notation, every piece of
+/// Black's has a different character from Red's of the same kind, as on real pieces.
+pub fn board_char(piece: Piece) -> char {
+    match piece {
+        Piece::RHorse => '傌',
+        Piece::RRook => '俥',
+        Piece::BHorse => '馬',
+        Piece::BRook => '車',
+        Piece::BCannon => '砲',
+        Piece::Empty => '＋',
+        _ => piece_char(piece),
+    }
+}
+
+/// Draws `board` in Chinese characters, with the files numbered as each side counts
+/// them: Black's along the top and Red's along the bottom. Every character is full
+/// width, so the columns line up in a terminal.
+pub fn format_board(board: &Board) -> String {
+    const FULL_WIDTH_DIGITS: [char; 9] = ['１', '２', '３', '４', '５', '６', '７', '８', '９'];
+    const RIVER: &str = "　楚河　　　漢界　";
+
+    let mut text: String = (0..9)
+        .map(|col| FULL_WIDTH_DIGITS[file_number(Player::Black, col) - 1])
+        .collect();
+    text.push('\n');
+    for row in 0..10 {
+        text.extend((0..9).map(|col| board_char(board.board[row * 9 + col])));
+        text.push('\n');
+        if row == 4 {
+            text.push_str(RIVER);
+            text.push('\n');
+        }
+    }
+    text.extend((0..9).map(|col| numeral(Player::Red, file_number(Player::Red, col))));
+    text.push('\n');
+    text
+}
+
+/// The numeral for `n`, from 1 to 9, as written by `player`.
+fn numeral(player: Player, n: usize) -> char {
+    match player {
+        Player::Red => RED_NUMERALS[n - 1],
+        Player::Black => BLACK_NUMERALS[n - 1],
+    }
+}
+
+/// The file number of board column `col`, counted from `player`'s right.
+fn file_number(player: Player, col: usize) -> usize {
+    match player {
+        Player::Red => 9 - col,
+        Player::Black => col + 1,
+    }
+}
+
+/// The rows of the pieces like `piece` on column `col`, frontmost first.
+fn rows_on_file(board: &Board, piece: Piece, col: usize) -> Vec<usize> {
+    let mut rows: Vec<usize> = (0..10)
+        .filter(|&row| board.board[row * 9 + col] == piece)
+        .collect();
+    if piece.player() == Some(Player::Black) {
+        rows.reverse();
+    }
+    rows
+}
+
+/// Formats `mv`, a legal move in `board`'s position, in Chinese notation.
+pub fn format(board: &Board, mv: Move) -> String {
+    let piece = board.board[mv.from_sq()];
+    let player = piece.player().expect("no piece on the move's from square");
+    let (from_row, from_col) = (mv.from_sq() / 9, mv.from_sq() % 9);
+    let (to_row, to_col) = (mv.to_sq() / 9, mv.to_sq() % 9);
+
+    let mut text = String::new();
+    let rows = rows_on_file(board, piece, from_col);
+    if rows.len() == 1 {
+        text.push(piece_char(piece));
+        text.push(numeral(player, file_number(player, from_col)));
+    } else {
+        let index = rows.iter().position(|&row| row == from_row).unwrap();
+        text.push(match (rows.len(), index) {
+            (2 | 3, 0) => '前',
+            (2, 1) | (3, 2) => '后',
+            (3, 1) => '中',
+            _ => RED_NUMERALS[index],
+        });
+        let other_stacked_file =
+            (0..9).any(|col| col != from_col && rows_on_file(board, piece, col).len() > 1);
+        if other_stacked_file {
+            text.push(numeral(player, file_number(player, from_col)));
+        } else {
+            text.push(piece_char(piece));
+        }
+    }
+
+    if from_row == to_row {
+        text.push('平');
+        text.push(numeral(player, file_number(player, to_col)));
+    } else {
+        let forward = (to_row < from_row) == (player == Player::Red);
+        text.push(if forward { '进' } else { '退' });
+        let moves_straight = matches!(
+            piece,
+            Piece::RKing
+                | Piece::BKing
+                | Piece::RRook
+                | Piece::BRook
+                | Piece::RCannon
+                | Piece::BCannon
+                | Piece::RPawn
+                | Piece::BPawn
+        );
+        if moves_straight {
+            text.push(numeral(player, from_row.abs_diff(to_row)));
+        } else {
+            text.push(numeral(player, file_number(player, to_col)));
+        }
+    }
+    text
+}
+
+/// Maps the variants in which the notation is commonly written to the characters
+/// `format` uses: traditional characters, both sides' numerals and piece names, and
+/// full-width digits.
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            if let Some(i) = RED_NUMERALS.iter().position(|&n| n == c) {
+                return BLACK_NUMERALS[i];
+            }
+            match c {
+                '１'..='９' => char::from_u32(c as u32 - '１' as u32 + '1' as u32).unwrap(),
+                '帥' | '將' | '将' => '帅',
+                '士' => '仕',
+                '象' => '相',
+                '卒' => '兵',
+                '俥' | '車' => '车',
+                '傌' | '馬' => '马',
+                '砲' | '炮' => '炮',
+                '進' => '进',
+                '後' => '后',
+                _ => c,
+            }
+        })
+        .collect()
+}
+
+/// Parses a move in Chinese notation, returning it if it is legal in `board`'s position.
+pub fn parse(board: &mut Board, text: &str) -> Option<Move> {
+    let text = normalize(text);
+    let mut legal_moves = MoveList::new();
+    board.generate_legal_moves(&mut legal_moves);
+    legal_moves
+        .as_slice()
+        .iter()
+        .copied()
+        .find(|&mv| normalize(&format(board, mv)) == text)
+}
@@ -0,0 +1,58 @@
+//! ICCS coordinate notation, as used by the UCI and UCCI protocols.
+//!
+//! A move is written as its from and to squares, each a file letter from `a` to `i`
+//! counted from Red's left and a rank digit from `0` to `9` counted from Red's side,
+//! such as `h2e2`. The upper case form with a dash, `H2-E2`, is also read.
+
+use crate::bitboard::Board;
+use crate::constants::Piece;
+use crate::r#move::Move;
+use crate::square::{File, Rank, Square};
+
+/// Formats a square, such as `e0` for Red's king square.
+pub fn format_square(sq: Square) -> String {
+    sq.to_string()
+}
+
+/// Parses a square formatted by `format_square`, in either case.
+pub fn parse_square(text: &str) -> Result<Square, String> {
+    let mut chars = text.chars().map(|c| c.to_ascii_lowercase());
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(file @ 'a'..='i'), Some(rank @ '0'..='9'), None) => {
+            let file = File::new(file as usize - 'a' as usize).unwrap();
+            let rank = Rank::new(rank as usize - '0' as usize).unwrap();
+            Ok(Square::new(file, rank))
+        }
+        _ => Err(format!("invalid square `{}`", text)),
+    }
+}
+
+/// Formats a move, such as `h2e2`.
+pub fn format(mv: Move) -> String {
+    format_square(mv.from()) + &format_square(mv.to())
+}
+
+/// Parses the from and to squares of a move.
+pub fn parse_squares(text: &str) -> Result<(Square, Square), String> {
+    let squares: String = text.chars().filter(|&c| c != '-').collect();
+    if !squares.is_ascii() || squares.len() != 4 {
+        return Err(format!("invalid move `{}`", text));
+    }
+    let from_sq =
+        parse_square(&squares[..2]).map_err(|e| format!("invalid move `{}`: {}", text, e))?;
+    let to_sq =
+        parse_square(&squares[2..]).map_err(|e| format!("invalid move `{}`: {}", text, e))?;
+    Ok((from_sq, to_sq))
+}
+
+/// Parses a move in `board`'s position, taking the captured piece from the board.
+/// The move is not checked for legality.
+pub fn parse(board: &Board, text: &str) -> Result<Move, String> {
+    let (from, to) = parse_squares(text)?;
+    let captured = board.piece_at(to);
+    Ok(Move::new(
+        from.index(),
+        to.index(),
+        (captured != Piece::Empty).then_some(captured),
+    ))
+}
@@ -2,10 +2,15 @@
 
 use crate::r#move::Move;
 use crate::bitboard::Board;
+use crate::constants::Piece;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, Read};
+use std::sync::Arc;
+
+/// The book engines use unless they are given another one.
+pub const DEFAULT_BOOK_FILE: &str = "opening_book.bin";
 
 // Define the structure for a book entry
 #[derive(Debug, Clone, Copy)]
@@ -14,14 +19,82 @@ pub struct BookEntry {
     pub mv: Move,
 }
 
-// The opening book, stored as a HashMap for quick lookup
-pub static OPENING_BOOK: Lazy<HashMap<u64, Vec<Move>>> = Lazy::new(|| {
-    let mut book = HashMap::new();
+/// An opening book: the moves stored for each position, by hash key.
+#[derive(Debug, Clone, Default)]
+pub struct OpeningBook {
+    moves: HashMap<u64, Vec<Move>>,
+}
+
+impl OpeningBook {
+    /// Loads the book in `filename`.
+    pub fn load(filename: &str) -> io::Result<Self> {
+        let mut book = Self::default();
+        load_opening_book_from_file(&mut book.moves, filename)?;
+        Ok(book)
+    }
+
+    /// The number of positions in the book.
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// Queries the book for a move in the current position.
+    /// Returns a random move from the book if found, otherwise None.
+    ///
+    /// With `mirror`, the moves stored for the position mirrored left to right count
+    /// too, mirrored back, so a book holding only one side of a symmetric opening
+    /// covers both. Moves that are not legal in the position, as from a hash
+    /// collision, are never returned.
+    pub fn query(&self, board: &Board, mirror: bool) -> Option<Move> {
+        // `is_legal` tries the move on the board.
+        let mut scratch = board.clone();
+        // The book stores squares only; the captured piece comes from the board.
+        let candidates: Vec<Move> = self
+            .candidates(board, mirror)
+            .into_iter()
+            .map(|mv| {
+                let captured = board.board[mv.to_sq()];
+                Move::new(mv.from_sq(), mv.to_sq(), (captured != Piece::Empty).then_some(captured))
+            })
+            .filter(|&mv| scratch.is_legal(mv))
+            .collect();
+
+        use rand::seq::SliceRandom;
+        let mut rng = rand::thread_rng();
+        candidates.choose(&mut rng).copied()
+    }
+
+    fn candidates(&self, board: &Board, mirror: bool) -> Vec<Move> {
+        let mut candidates: Vec<Move> = self.moves.get(&board.hash_key).cloned().unwrap_or_default();
+
+        // A symmetric position is its own mirror image, so its moves are only added once.
+        if mirror
+            && board.get_mirrored_hash() != board.hash_key
+            && let Some(moves) = self.moves.get(&board.get_mirrored_hash())
+        {
+            for mv in moves.iter().map(Move::mirrored) {
+                if !candidates.contains(&mv) {
+                    candidates.push(mv);
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// The book in `DEFAULT_BOOK_FILE`, loaded on first use and shared by every engine
+/// that was not given another one.
+pub static DEFAULT_BOOK: Lazy<Arc<OpeningBook>> = Lazy::new(|| {
     // Attempt to load the book from a binary file
-    if let Err(e) = load_opening_book_from_file(&mut book, "opening_book.bin") {
+    let book = OpeningBook::load(DEFAULT_BOOK_FILE).unwrap_or_else(|e| {
         eprintln!("Warning: Could not load opening book: {}", e);
-    }
-    book
+        OpeningBook::default()
+    });
+    Arc::new(book)
 });
 
 fn load_opening_book_from_file(book: &mut HashMap<u64, Vec<Move>>, filename: &str) -> io::Result<()> {
@@ -39,36 +112,13 @@ fn load_opening_book_from_file(book: &mut HashMap<u64, Vec<Move>>, filename: &st
         let hash = u64::from_le_bytes(chunk[0..8].try_into().unwrap());
         let from_sq = u32::from_le_bytes(chunk[8..12].try_into().unwrap()) as usize;
         let to_sq = u32::from_le_bytes(chunk[12..16].try_into().unwrap()) as usize;
-        
+        if from_sq >= 90 || to_sq >= 90 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid book move {} -> {}", from_sq, to_sq)));
+        }
+
         let mv = Move::new(from_sq, to_sq, None);
         book.entry(hash).or_default().push(mv);
     }
 
     Ok(())
 }
-
-/// Queries the opening book for a move in the current position.
-/// Returns a random move from the book if found, otherwise None.
-pub fn query_opening_book(board: &Board) -> Option<Move> {
-    if let Some(moves) = OPENING_BOOK.get(&board.hash_key) {
-        if !moves.is_empty() {
-            use rand::seq::SliceRandom;
-            let mut rng = rand::thread_rng();
-            return moves.choose(&mut rng).copied();
-        }
-    }
-
-    // If no move is found, try the mirrored position
-    let mirrored_hash = board.get_mirrored_hash();
-    if let Some(moves) = OPENING_BOOK.get(&mirrored_hash) {
-        if !moves.is_empty() {
-            use rand::seq::SliceRandom;
-            let mut rng = rand::thread_rng();
-            if let Some(mv) = moves.choose(&mut rng) {
-                return Some(mv.mirrored());
-            }
-        }
-    }
-
-    None
-}
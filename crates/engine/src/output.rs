@@ -0,0 +1,20 @@
+//! Where a search writes the lines it reports, `info` lines above all.
+//!
+//! A front end can collect or log the lines by giving the searcher its own `Output`.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Receives each line a search reports, without the newline.
+pub type Output = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Writes each line to stdout and flushes it at once, so a GUI sees it while the search
+/// is still running.
+pub fn stdout() -> Output {
+    Arc::new(|line| {
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", line)
+            .and_then(|()| stdout.flush())
+            .ok();
+    })
+}
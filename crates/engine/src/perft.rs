@@ -0,0 +1,117 @@
+//! Perft: counting the leaf nodes of the legal move tree, to validate the move generator.
+
+use crate::bitboard::Board;
+use crate::r#move::Move;
+use crate::movelist::MoveList;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Positions with their known node counts, for depths 1 and up.
+///
+/// The start position's counts are the published ones. The others cover checks, pins,
+/// cannon screens and facing kings, and were counted with the independent generator in
+/// the tests (`tests::reference`), written from the rules on a plain array and sharing
+/// no code with `Board`. It reproduces the published counts, and
+/// `test_reference_perft_full` checks all counts below up to depth 4 with it.
+pub const PERFT_SUITE: [(&str, &[u64]); 5] = [
+    (
+        "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1",
+        &[44, 1920, 79666, 3290240, 133312995],
+    ),
+    (
+        "r2a1kbnr/3na2C1/8b/pcp1p1p1p/9/2P3C2/P3P1P1P/9/9/RNBAKABNR b - - 0 1",
+        &[26, 953, 26543, 988517],
+    ),
+    (
+        "1n1a1k3/r1C1a4/b2cb2c1/p7r/1C2pnp1p/2B6/P1P1P1P1P/R8/5K3/1N1A1ABNR b - - 0 1",
+        &[46, 1908, 84670, 3406840],
+    ),
+    (
+        "2bak4/4a4/n7C/p5p2/2n6/P2RP3P/2Pc5/5rr2/3K5/9 b - - 0 1",
+        &[43, 992, 41559, 966217],
+    ),
+    (
+        "3a5/3k5/9/9/2b1C4/2B6/2P6/8B/4K4/9 w - - 0 1",
+        &[19, 88, 1627, 8846],
+    ),
+];
+
+/// Counts the leaf nodes of the legal move tree of `board` at `depth`.
+pub fn perft(board: &mut Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let mut moves = MoveList::new();
+    board.generate_legal_moves(&mut moves);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for &mv in moves.as_slice() {
+        board.make_move(mv);
+        nodes += perft(board, depth - 1);
+        board.undo_move();
+    }
+    nodes
+}
+
+/// Like `perft`, but counts the nodes below each root move separately, to narrow a
+/// wrong count down to the move whose subtree is wrong.
+pub fn perft_divide(board: &mut Board, depth: u32) -> Vec<(Move, u64)> {
+    let mut moves = MoveList::new();
+    board.generate_legal_moves(&mut moves);
+    moves
+        .as_slice()
+        .iter()
+        .map(|&mv| {
+            board.make_move(mv);
+            let nodes = perft(board, depth.saturating_sub(1));
+            board.undo_move();
+            (mv, nodes)
+        })
+        .collect()
+}
+
+/// Like `perft`, but the root moves are shared out over `threads` threads, each
+/// searching its own copy of the board. Uses all available cores if `threads` is 0.
+pub fn perft_parallel(board: &Board, depth: u32, threads: usize) -> u64 {
+    if depth <= 1 {
+        return perft(&mut board.clone(), depth);
+    }
+
+    let threads = if threads == 0 {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        threads
+    };
+
+    let mut root_moves = MoveList::new();
+    board.clone().generate_legal_moves(&mut root_moves);
+    let root_moves = root_moves.as_slice();
+
+    // Threads take the next root move as they finish, since subtrees vary a lot in size.
+    let next_move = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(root_moves.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut board = board.clone();
+                    let mut nodes = 0;
+                    while let Some(&mv) = root_moves.get(next_move.fetch_add(1, Ordering::Relaxed))
+                    {
+                        board.make_move(mv);
+                        nodes += perft(&mut board, depth - 1);
+                        board.undo_move();
+                    }
+                    nodes
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .sum()
+    })
+}
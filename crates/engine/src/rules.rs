@@ -0,0 +1,62 @@
+//! The rule sets games can be adjudicated by.
+//!
+//! Servers and GUIs disagree on how repetitions and long games without captures end,
+//! so the search and `Game` both follow the `Rules` in effect instead of fixing one
+//! convention.
+
+use crate::bitboard::{Board, RepetitionOutcome};
+use crate::constants::MOVE_RULE_PLIES;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rules {
+    /// Perpetual check and perpetual chase lose, and 60 moves without a capture draw.
+    #[default]
+    ChineseOfficial,
+    /// Perpetual check and perpetual chase lose. Long games without captures are left
+    /// to the arbiter, so there is no move-count rule.
+    AsianRules,
+    /// Every repetition is a draw, whoever forces it, and 60 moves without a capture
+    /// draw.
+    SimpleRepetitionDraw,
+}
+
+impl Rules {
+    pub const NAMES: [&'static str; 3] = ["ChineseOfficial", "AsianRules", "SimpleRepetitionDraw"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rules::ChineseOfficial => Self::NAMES[0],
+            Rules::AsianRules => Self::NAMES[1],
+            Rules::SimpleRepetitionDraw => Self::NAMES[2],
+        }
+    }
+
+    /// Parses a rule set name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Rules::ChineseOfficial,
+            Rules::AsianRules,
+            Rules::SimpleRepetitionDraw,
+        ]
+        .into_iter()
+        .find(|rules| rules.name().eq_ignore_ascii_case(name))
+    }
+
+    /// How the repetition of the positions of `board` since history ply `start` ends.
+    pub fn repetition_outcome(self, board: &mut Board, start: usize) -> RepetitionOutcome {
+        match self {
+            Rules::ChineseOfficial | Rules::AsianRules => board.repetition_outcome(start),
+            Rules::SimpleRepetitionDraw => RepetitionOutcome::Draw,
+        }
+    }
+
+    /// The plies without a capture after which the game is drawn, if there is a limit.
+    pub fn move_rule_plies(self) -> Option<u32> {
+        match self {
+            Rules::ChineseOfficial | Rules::SimpleRepetitionDraw => Some(MOVE_RULE_PLIES),
+            Rules::AsianRules => None,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Limits that control how long and how deep a search runs.
+
+use crate::constants::Player;
+use crate::r#move::Move;
+
+/// The depth searched when no explicit depth limit is given.
+pub const DEFAULT_MAX_DEPTH: i32 = 64;
+
+/// Everything a `go` command can ask of the search.
+///
+/// All limits are optional; the search stops as soon as any of them is reached.
+/// Times are in milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct SearchLimits {
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<u128>,
+    pub wtime: Option<u128>,
+    pub btime: Option<u128>,
+    pub winc: Option<u128>,
+    pub binc: Option<u128>,
+    pub movestogo: Option<u128>,
+    /// Search for a mate in this many moves.
+    pub mate: Option<i32>,
+    /// Search until stopped.
+    pub infinite: bool,
+    /// Think on the opponent's time until `ponderhit` or `stop`.
+    pub ponder: bool,
+    /// Only consider these root moves. Empty means all moves.
+    pub searchmoves: Vec<Move>,
+    /// Time kept back from every timed move for the GUI and the connection to pass
+    /// the move on. Not part of the `go` command.
+    pub move_overhead: u128,
+}
+
+impl SearchLimits {
+    /// Limits for a search of a fixed depth.
+    pub fn depth(depth: i32) -> Self {
+        Self { depth: Some(depth), ..Self::default() }
+    }
+
+    /// Limits for a search of a fixed time.
+    pub fn movetime(movetime_ms: u128) -> Self {
+        Self { movetime: Some(movetime_ms), ..Self::default() }
+    }
+
+    /// The maximum iterative deepening depth.
+    ///
+    /// A mate in `n` moves leaves the mated side to move, with no legal move, at ply
+    /// `2n - 1`. Depth `2n` searches every move there rather than only the captures
+    /// quiescence search looks at, so a mate search never goes deeper than that.
+    pub fn max_depth(&self) -> i32 {
+        let depth = self.depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        match self.mate {
+            Some(moves) => depth.min(2 * moves.max(1)),
+            None => depth,
+        }
+    }
+
+    /// The time budget for this move when `player` is to move, if the search is timed.
+    ///
+    /// A fixed `movetime` wins over the clock. Otherwise the remaining time is spread
+    /// over `movestogo` moves (or 20 if unknown), plus the increment. Either way the
+    /// move overhead is kept back, leaving at least a millisecond.
+    pub fn time_limit_ms(&self, player: Player) -> Option<u128> {
+        if self.infinite {
+            return None;
+        }
+        self.budget_ms(player)
+            .map(|budget| budget.saturating_sub(self.move_overhead).max(1))
+    }
+
+    fn budget_ms(&self, player: Player) -> Option<u128> {
+        if self.movetime.is_some() {
+            return self.movetime;
+        }
+
+        let (time_left, increment) = if player == Player::Red {
+            (self.wtime, self.winc.unwrap_or(0))
+        } else {
+            (self.btime, self.binc.unwrap_or(0))
+        };
+        let moves_to_go = self.movestogo.filter(|&moves| moves > 0).unwrap_or(20);
+
+        time_left.map(|t| t / moves_to_go + increment)
+    }
+
+    /// Formats the limits as a UCI `go` command.
+    pub fn to_uci_command(&self) -> String {
+        let mut command = String::from("go");
+        if self.ponder {
+            command.push_str(" ponder");
+        }
+        let numeric_limits = [
+            ("depth", self.depth.map(|v| v.to_string())),
+            ("nodes", self.nodes.map(|v| v.to_string())),
+            ("movetime", self.movetime.map(|v| v.to_string())),
+            ("wtime", self.wtime.map(|v| v.to_string())),
+            ("btime", self.btime.map(|v| v.to_string())),
+            ("winc", self.winc.map(|v| v.to_string())),
+            ("binc", self.binc.map(|v| v.to_string())),
+            ("movestogo", self.movestogo.map(|v| v.to_string())),
+            ("mate", self.mate.map(|v| v.to_string())),
+        ];
+        for (name, value) in numeric_limits {
+            if let Some(value) = value {
+                command.push_str(&format!(" {} {}", name, value));
+            }
+        }
+        if self.infinite {
+            command.push_str(" infinite");
+        }
+        if !self.searchmoves.is_empty() {
+            command.push_str(" searchmoves");
+            for mv in &self.searchmoves {
+                command.push(' ');
+                command.push_str(&mv.to_uci_string());
+            }
+        }
+        command
+    }
+}
@@ -0,0 +1,53 @@
+//! The interface shared by the search backends.
+
+use crate::bitboard::Board;
+use crate::engine::SearchResult;
+use crate::search_limits::SearchLimits;
+use crate::stop_token::StopToken;
+
+/// A search algorithm that picks a move for a position.
+///
+/// Implemented by the alpha-beta `Engine` and by `MctsSearcher`, so callers can
+/// switch backends at runtime.
+pub trait Searcher: Send {
+    /// Searches `board` within `limits`. The board is restored before returning.
+    fn search(&mut self, board: &mut Board, limits: &SearchLimits) -> SearchResult;
+
+    /// Forgets everything learned from previous searches.
+    fn new_game(&mut self);
+
+    /// A handle that stops the running search from another thread.
+    fn stop_token(&self) -> StopToken;
+}
+
+/// The available search backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchBackend {
+    /// Negamax alpha-beta search, the default.
+    #[default]
+    AlphaBeta,
+    /// Monte-Carlo tree search with PUCT selection.
+    Mcts,
+}
+
+impl SearchBackend {
+    pub const NAMES: [&'static str; 2] = ["AlphaBeta", "MCTS"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SearchBackend::AlphaBeta => Self::NAMES[0],
+            SearchBackend::Mcts => Self::NAMES[1],
+        }
+    }
+
+    /// Parses a backend name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case(Self::NAMES[0]) {
+            Some(SearchBackend::AlphaBeta)
+        } else if name.eq_ignore_ascii_case(Self::NAMES[1]) {
+            Some(SearchBackend::Mcts)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Strength limiting, so weaker players can enjoy a game against the engine.
+
+use crate::engine::PvLine;
+use rand::Rng;
+
+pub const MIN_SKILL_LEVEL: u8 = 1;
+/// The default level, at which the engine plays at full strength.
+pub const MAX_SKILL_LEVEL: u8 = 20;
+
+/// The number of root lines searched at reduced levels, to pick a move from.
+const SKILL_MULTI_PV: usize = 4;
+
+/// A skill level from `MIN_SKILL_LEVEL` to `MAX_SKILL_LEVEL`.
+///
+/// Below the maximum, the search depth and node count are capped and the played
+/// move is picked at random among the moves that score close to the best one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Skill {
+    level: u8,
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Self {
+            level: MAX_SKILL_LEVEL,
+        }
+    }
+}
+
+impl Skill {
+    pub fn new(level: u8) -> Self {
+        Self {
+            level: level.clamp(MIN_SKILL_LEVEL, MAX_SKILL_LEVEL),
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Whether the engine is weakened at all.
+    pub fn enabled(&self) -> bool {
+        self.level < MAX_SKILL_LEVEL
+    }
+
+    /// The deepest iteration searched at this level.
+    pub fn max_depth(&self) -> i32 {
+        1 + self.level as i32 / 2
+    }
+
+    /// The node budget of a search at this level.
+    pub fn node_limit(&self) -> u64 {
+        2000 * (self.level as u64).pow(2)
+    }
+
+    /// The number of root lines to search, so there is something to choose from.
+    pub fn multi_pv(&self) -> usize {
+        SKILL_MULTI_PV
+    }
+
+    /// How far below the best line, in centipawns, a line may score and still be played.
+    fn score_margin(&self) -> i32 {
+        (MAX_SKILL_LEVEL - self.level) as i32 * 15
+    }
+
+    /// Picks the line to play from `lines`, which are sorted best first.
+    ///
+    /// Lines within the margin of the best score are equally likely, except that a
+    /// found mate is always played.
+    pub fn pick_line(&self, lines: &[PvLine]) -> usize {
+        let Some(best) = lines.first() else {
+            return 0;
+        };
+        if best.score.abs() > crate::constants::MATE_VALUE - 100 {
+            return 0;
+        }
+
+        let candidates = lines
+            .iter()
+            .take_while(|line| line.score >= best.score - self.score_margin())
+            .count();
+        rand::thread_rng().gen_range(0..candidates)
+    }
+}
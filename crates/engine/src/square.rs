@@ -0,0 +1,157 @@
+//! Board coordinates: files, ranks and squares.
+//!
+//! Files and ranks are counted as in ICCS notation, from Red's left and from Red's
+//! side, whereas square indices, as used by `Board::board` and the bitboards, count
+//! rows from Black's side. Going through these types keeps the two apart.
+
+use crate::constants::Player;
+use std::fmt;
+use std::str::FromStr;
+
+/// A file, from 0 for `a` on Red's left to 8 for `i` on Red's right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct File(u8);
+
+impl File {
+    pub const COUNT: usize = 9;
+
+    pub const fn new(index: usize) -> Option<File> {
+        if index < Self::COUNT {
+            Some(File(index as u8))
+        } else {
+            None
+        }
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// The file on the other side of the middle file.
+    pub const fn mirrored(self) -> File {
+        File(8 - self.0)
+    }
+
+    /// The file's number as `player` counts them in Chinese notation, from 1 on
+    /// their right.
+    pub const fn number_for(self, player: Player) -> usize {
+        match player {
+            Player::Red => 9 - self.index(),
+            Player::Black => self.index() + 1,
+        }
+    }
+}
+
+impl fmt::Display for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", (b'a' + self.0) as char)
+    }
+}
+
+/// A rank, from 0 for Red's back rank to 9 for Black's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Rank(u8);
+
+impl Rank {
+    pub const COUNT: usize = 10;
+
+    pub const fn new(index: usize) -> Option<Rank> {
+        if index < Self::COUNT {
+            Some(Rank(index as u8))
+        } else {
+            None
+        }
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// The rank counted from `player`'s side instead, so 0 is their back rank.
+    pub const fn relative_to(self, player: Player) -> usize {
+        match player {
+            Player::Red => self.index(),
+            Player::Black => 9 - self.index(),
+        }
+    }
+
+    /// The row of the rank in square indices, counted from Black's side.
+    pub const fn row(self) -> usize {
+        9 - self.index()
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A square of the board, written `e0` and so on, see `notation::iccs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Square(u8);
+
+impl Square {
+    pub const COUNT: usize = 90;
+
+    pub const fn new(file: File, rank: Rank) -> Square {
+        Square((rank.row() * 9 + file.index()) as u8)
+    }
+
+    /// The square with the index `index`, as used by `Board::board` and the bitboards.
+    pub const fn from_index(index: usize) -> Option<Square> {
+        if index < Self::COUNT {
+            Some(Square(index as u8))
+        } else {
+            None
+        }
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn file(self) -> File {
+        File(self.0 % 9)
+    }
+
+    pub const fn rank(self) -> Rank {
+        Rank(9 - self.0 / 9)
+    }
+
+    /// The square mirrored left to right.
+    pub const fn mirrored(self) -> Square {
+        Square::new(self.file().mirrored(), self.rank())
+    }
+
+    /// Whether the square is in `player`'s palace.
+    pub const fn in_palace(self, player: Player) -> bool {
+        let file = self.file().index();
+        file >= 3 && file <= 5 && self.rank().relative_to(player) <= 2
+    }
+
+    /// Whether the square is across the river from `player`'s side.
+    pub const fn across_river(self, player: Player) -> bool {
+        self.rank().relative_to(player) >= 5
+    }
+}
+
+impl From<Square> for usize {
+    fn from(sq: Square) -> usize {
+        sq.index()
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.file(), self.rank())
+    }
+}
+
+impl FromStr for Square {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Square, String> {
+        crate::notation::iccs::parse_square(text)
+    }
+}
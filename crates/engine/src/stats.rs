@@ -0,0 +1,102 @@
+//! Counters that describe how a search went, for judging search changes.
+
+use std::fmt;
+
+/// Beta cutoffs are counted for the first `CUTOFF_ORDINALS - 1` moves individually,
+/// and for all later moves together in the last bucket.
+pub const CUTOFF_ORDINALS: usize = 8;
+
+/// Statistics collected by `Engine::search` when `Engine::stats` is set.
+#[derive(Debug, Clone, Default)]
+pub struct SearchStats {
+    /// Beta cutoffs in the main search, by the ordinal of the move that caused them.
+    pub cutoffs_by_move: [u64; CUTOFF_ORDINALS],
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    pub null_move_tries: u64,
+    pub null_move_cutoffs: u64,
+    /// Reduced searches by late move reduction, and how many had to be searched again.
+    pub lmr_searches: u64,
+    pub lmr_researches: u64,
+    pub main_nodes: u64,
+    pub qsearch_nodes: u64,
+}
+
+/// `part / whole`, or 0 when `whole` is 0.
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 { 0.0 } else { part as f64 / whole as f64 }
+}
+
+impl SearchStats {
+    /// Records a beta cutoff by the `ordinal`-th move searched (starting at 1).
+    pub fn record_cutoff(&mut self, ordinal: usize) {
+        let bucket = ordinal.clamp(1, CUTOFF_ORDINALS) - 1;
+        self.cutoffs_by_move[bucket] += 1;
+    }
+
+    pub fn total_cutoffs(&self) -> u64 {
+        self.cutoffs_by_move.iter().sum()
+    }
+
+    /// The share of cutoffs caused by the first move, a measure of move ordering.
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        ratio(self.cutoffs_by_move[0], self.total_cutoffs())
+    }
+
+    pub fn tt_hit_rate(&self) -> f64 {
+        ratio(self.tt_hits, self.tt_probes)
+    }
+
+    pub fn null_move_cutoff_rate(&self) -> f64 {
+        ratio(self.null_move_cutoffs, self.null_move_tries)
+    }
+
+    pub fn lmr_research_rate(&self) -> f64 {
+        ratio(self.lmr_researches, self.lmr_searches)
+    }
+
+    /// The share of all nodes spent in quiescence search.
+    pub fn qsearch_share(&self) -> f64 {
+        ratio(self.qsearch_nodes, self.main_nodes + self.qsearch_nodes)
+    }
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "nodes: {} main, {} qsearch ({:.1}% qsearch)",
+            self.main_nodes,
+            self.qsearch_nodes,
+            self.qsearch_share() * 100.0
+        )?;
+        writeln!(
+            f,
+            "tt: {} probes, {:.1}% hits",
+            self.tt_probes,
+            self.tt_hit_rate() * 100.0
+        )?;
+        writeln!(
+            f,
+            "null move: {} tries, {:.1}% cutoffs",
+            self.null_move_tries,
+            self.null_move_cutoff_rate() * 100.0
+        )?;
+        writeln!(
+            f,
+            "lmr: {} searches, {:.1}% re-searched",
+            self.lmr_searches,
+            self.lmr_research_rate() * 100.0
+        )?;
+        write!(
+            f,
+            "cutoffs: {} total, {:.1}% on the first move, by move:",
+            self.total_cutoffs(),
+            self.first_move_cutoff_rate() * 100.0
+        )?;
+        for count in self.cutoffs_by_move {
+            write!(f, " {}", count)?;
+        }
+        Ok(())
+    }
+}
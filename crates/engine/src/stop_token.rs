@@ -0,0 +1,33 @@
+//! A flag for stopping a search from another thread.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A cloneable handle that asks a running search to stop.
+///
+/// All clones share the same flag, so a token handed to another thread before the
+/// search starts can stop it at any time without locking the engine.
+#[derive(Debug, Clone, Default)]
+pub struct StopToken {
+    stopped: Arc<AtomicBool>,
+}
+
+impl StopToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the search to stop as soon as possible.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears a previous stop request, before starting a new search.
+    pub fn reset(&self) {
+        self.stopped.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
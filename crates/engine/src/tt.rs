@@ -1,6 +1,16 @@
 //! Transposition Table for caching search results.
+//!
+//! The table needs no lock to be shared between threads: every entry is written as
+//! two atomic words, the key being stored XORed with the data, so an entry torn by
+//! concurrent writes no longer matches its key and is simply missed. The search itself
+//! still runs on one thread.
 
-use crate::r#move::Move;
+use crate::r#move::{MOVE_BITS, Move};
+use crate::zobrist;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
 
 // Transposition Table Entry Flags
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -18,6 +28,8 @@ pub struct TtEntry {
     pub score: i32,
     pub flag: TtFlag,
     pub best_move: Move,
+    /// The search that stored this entry, see `TranspositionTable::new_search`.
+    pub generation: u8,
 }
 
 impl TtEntry {
@@ -28,47 +40,334 @@ impl TtEntry {
             score: 0,
             flag: TtFlag::Exact,
             best_move: Move::new(0, 0, None), // Represents a null move
+            generation: 0,
         }
     }
 }
 
-/// The transposition table itself.
+/// Generations are stored in 6 bits and wrap around.
+const GENERATION_MASK: u8 = 0x3F;
+
+/// Bit offsets of the fields packed into `PackedEntry::data`.
+const SCORE_SHIFT: u32 = MOVE_BITS;
+const DEPTH_SHIFT: u32 = SCORE_SHIFT + 16;
+const FLAG_SHIFT: u32 = DEPTH_SHIFT + 8;
+const GENERATION_SHIFT: u32 = FLAG_SHIFT + 2;
+
+/// An entry as stored: the hash key, and the other fields packed into one word.
+///
+/// `data` holds, from the lowest bit: the move (`MOVE_BITS` bits), the score (16 bits),
+/// the depth (8 bits), the flag (2 bits) and the generation (6 bits).
+#[derive(Debug, Clone, Copy, Default)]
+struct PackedEntry {
+    key: u64,
+    data: u64,
+}
+
+impl PackedEntry {
+    fn pack(entry: &TtEntry) -> Self {
+        let flag = match entry.flag {
+            TtFlag::Exact => 0,
+            TtFlag::LowerBound => 1,
+            TtFlag::UpperBound => 2,
+        };
+        let data = entry.best_move.to_u32() as u64
+            | (entry.score.clamp(i16::MIN as i32, i16::MAX as i32) as i16 as u16 as u64) << SCORE_SHIFT
+            | (entry.depth.clamp(0, u8::MAX as i32) as u64) << DEPTH_SHIFT
+            | flag << FLAG_SHIFT
+            | ((entry.generation & GENERATION_MASK) as u64) << GENERATION_SHIFT;
+        Self { key: entry.hash_key, data }
+    }
+
+    fn unpack(&self) -> TtEntry {
+        let data = self.data;
+        TtEntry {
+            hash_key: self.key,
+            depth: (data >> DEPTH_SHIFT) as u8 as i32,
+            score: (data >> SCORE_SHIFT) as u16 as i16 as i32,
+            flag: match (data >> FLAG_SHIFT) & 0x3 {
+                0 => TtFlag::Exact,
+                1 => TtFlag::LowerBound,
+                _ => TtFlag::UpperBound,
+            },
+            // `load` rejects files with moves off the board, so this never falls back.
+            best_move: Move::from_u32(data as u32).unwrap_or(Move::new(0, 0, None)),
+            generation: (data >> GENERATION_SHIFT) as u8 & GENERATION_MASK,
+        }
+    }
+}
+
+/// A `PackedEntry` in a form that can be read and written from several threads.
+#[derive(Debug, Default)]
+struct AtomicEntry {
+    /// The hash key XORed with `data`.
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl AtomicEntry {
+    fn load(&self) -> PackedEntry {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed) ^ data;
+        PackedEntry { key, data }
+    }
+
+    fn store(&self, entry: PackedEntry) {
+        self.key.store(entry.key ^ entry.data, Ordering::Relaxed);
+        self.data.store(entry.data, Ordering::Relaxed);
+    }
+}
+
+/// Identifies a file written by `TranspositionTable::save`.
+const FILE_MAGIC: &[u8; 4] = b"XQTT";
+/// Changes whenever the layout of the file or of `PackedEntry` changes.
+const FILE_VERSION: u32 = 2;
+
+/// The largest table, in MB, that `load` takes on from a file.
+pub const MAX_SIZE_MB: usize = 4096;
+
+/// The number of entries sharing one table index. A bucket fills one 64-byte cache line.
+const BUCKET_SIZE: usize = 4;
+
+/// The entries stored at one index. The first slot keeps the deepest result of the
+/// current search, the others always take the newest results, replacing the
+/// shallowest or oldest of them.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Bucket {
+    entries: [AtomicEntry; BUCKET_SIZE],
+}
+
+impl Bucket {
+    fn load(&self) -> [PackedEntry; BUCKET_SIZE] {
+        std::array::from_fn(|slot| self.entries[slot].load())
+    }
+}
+
+/// The transposition table itself. All methods take `&self`, so several threads can
+/// share one table, e.g. through an `Arc`.
 pub struct TranspositionTable {
-    entries: Vec<TtEntry>,
+    buckets: Vec<Bucket>,
+    generation: AtomicU8,
 }
 
 impl TranspositionTable {
     /// Creates a new transposition table with a given size in MB.
     pub fn new(size_mb: usize) -> Self {
-        let num_entries = size_mb * 1024 * 1024 / std::mem::size_of::<TtEntry>();
         Self {
-            entries: vec![TtEntry::new_empty(); num_entries],
+            buckets: Self::allocate(size_mb),
+            generation: AtomicU8::new(0),
         }
     }
 
+    fn allocate(size_mb: usize) -> Vec<Bucket> {
+        let num_buckets = (size_mb * 1024 * 1024 / std::mem::size_of::<Bucket>()).max(1);
+        (0..num_buckets).map(|_| Bucket::default()).collect()
+    }
+
+    /// Changes the size of the table to `size_mb` MB. All entries are lost.
+    pub fn resize(&mut self, size_mb: usize) {
+        // Free the old table first, so both never take up memory at the same time.
+        self.buckets = Vec::new();
+        self.buckets = Self::allocate(size_mb);
+    }
+
+    /// The size of the table in MB.
+    pub fn size_mb(&self) -> usize {
+        self.buckets.len() * std::mem::size_of::<Bucket>() / (1024 * 1024)
+    }
+
+    fn generation(&self) -> u8 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// Starts a new generation. Entries from earlier searches stay usable, but are
+    /// replaced before the entries of the current one.
+    pub fn new_search(&self) {
+        let generation = self.generation().wrapping_add(1) & GENERATION_MASK;
+        self.generation.store(generation, Ordering::Relaxed);
+    }
+
+    /// How many searches ago `entry` was stored.
+    fn age(&self, entry: &TtEntry) -> i32 {
+        (self.generation().wrapping_sub(entry.generation) & GENERATION_MASK) as i32
+    }
+
+    /// How valuable `entry` is to keep: deep entries are, old ones less so.
+    fn keep_value(&self, entry: &TtEntry) -> i32 {
+        entry.depth - 8 * self.age(entry)
+    }
+
+    fn bucket_index(&self, hash_key: u64) -> usize {
+        hash_key as usize % self.buckets.len()
+    }
+
+    /// Starts loading the bucket of `hash_key` into the cache, so a later `probe` or
+    /// `store` doesn't wait on memory. A no-op on architectures without a prefetch hint.
+    #[inline]
+    pub fn prefetch(&self, hash_key: u64) {
+        let bucket = &self.buckets[self.bucket_index(hash_key)] as *const Bucket;
+        #[cfg(target_arch = "x86_64")]
+        {
+            use std::arch::x86_64::{_MM_HINT_T0, _mm_prefetch};
+            // SAFETY: prefetching has no observable effect besides the cache, and SSE
+            // is part of the x86_64 baseline.
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(bucket as *const i8) };
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = bucket;
+    }
+
     /// Probes the transposition table for a given hash key.
-    pub fn probe(&self, hash_key: u64) -> Option<&TtEntry> {
-        let index = hash_key as usize % self.entries.len();
-        let entry = &self.entries[index];
-        if entry.hash_key == hash_key {
-            Some(entry)
+    pub fn probe(&self, hash_key: u64) -> Option<TtEntry> {
+        self.buckets[self.bucket_index(hash_key)]
+            .load()
+            .iter()
+            .find(|entry| entry.key == hash_key)
+            .map(PackedEntry::unpack)
+    }
+
+    /// Stores an entry in the transposition table.
+    pub fn store(&self, hash_key: u64, depth: i32, score: i32, flag: TtFlag, best_move: Move) {
+        let index = self.bucket_index(hash_key);
+        let new_entry = TtEntry {
+            hash_key,
+            depth,
+            score,
+            flag,
+            best_move,
+            generation: self.generation(),
+        };
+
+        let new_entry = PackedEntry::pack(&new_entry);
+        let bucket = &self.buckets[index];
+        let packed = bucket.load();
+
+        // A position already in the bucket is updated, unless that would replace a
+        // deeper bound of the current search with a shallower one, e.g. from quiescence.
+        if let Some(slot) = packed.iter().position(|entry| entry.key == hash_key) {
+            let old_entry = packed[slot].unpack();
+            if depth >= old_entry.depth || flag == TtFlag::Exact || self.age(&old_entry) > 0 {
+                bucket.entries[slot].store(new_entry);
+            }
+            return;
+        }
+
+        let entries = packed.map(|entry| entry.unpack());
+
+        // The always-replace slot least worth keeping makes room.
+        let victim = (1..BUCKET_SIZE)
+            .min_by_key(|&slot| self.keep_value(&entries[slot]))
+            .unwrap_or(0);
+        let replace_deepest = depth >= entries[0].depth || self.age(&entries[0]) > 0;
+
+        // Depth-preferred slot: a deeper or newer result demotes the old one.
+        if replace_deepest {
+            bucket.entries[victim].store(packed[0]);
+            bucket.entries[0].store(new_entry);
         } else {
-            None
+            bucket.entries[victim].store(new_entry);
         }
     }
 
-    /// Stores an entry in the transposition table.
-    pub fn store(&mut self, hash_key: u64, depth: i32, score: i32, flag: TtFlag, best_move: Move) {
-        let index = hash_key as usize % self.entries.len();
-        let entry = &self.entries[index];
+    /// Estimates how full the table is with entries of the current search, in permill,
+    /// from a sample of its first entries.
+    pub fn hashfull(&self) -> usize {
+        let sample = &self.buckets[..self.buckets.len().min(1000 / BUCKET_SIZE)];
+        let used = sample
+            .iter()
+            .flat_map(|bucket| bucket.load())
+            .filter(|entry| entry.key != 0 && entry.unpack().generation == self.generation())
+            .count();
+        used * 1000 / (sample.len() * BUCKET_SIZE)
+    }
+
+    /// Writes the table to `path`, to be restored by `load`.
+    ///
+    /// The file holds a header (magic, version, Zobrist fingerprint, generation and
+    /// number of buckets) followed by the key and data of every entry, little-endian.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(FILE_MAGIC)?;
+        writer.write_all(&FILE_VERSION.to_le_bytes())?;
+        writer.write_all(&zobrist::fingerprint().to_le_bytes())?;
+        writer.write_all(&[self.generation()])?;
+        writer.write_all(&(self.buckets.len() as u64).to_le_bytes())?;
+        for entry in self.buckets.iter().flat_map(|bucket| bucket.load()) {
+            writer.write_all(&entry.key.to_le_bytes())?;
+            writer.write_all(&entry.data.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Replaces the table with the one saved to `path` by `save`, taking on its size.
+    /// Fails without touching the table if the file was written by another version
+    /// or with other Zobrist keys, or if its size disagrees with its header or is
+    /// over `MAX_SIZE_MB`.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let read_u64 = |reader: &mut BufReader<File>| -> io::Result<u64> {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        };
 
-        // Depth-preferred replacement strategy
-        if depth >= entry.depth {
-            self.entries[index] = TtEntry { hash_key, depth, score, flag, best_move };
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != FILE_MAGIC {
+            return Err(invalid("Not a transposition table file"));
         }
+        if u32::from_le_bytes(header[4..8].try_into().unwrap()) != FILE_VERSION {
+            return Err(invalid("Unsupported transposition table file version"));
+        }
+        if read_u64(&mut reader)? != zobrist::fingerprint() {
+            return Err(invalid("Transposition table file uses other Zobrist keys"));
+        }
+        let mut generation = [0];
+        reader.read_exact(&mut generation)?;
+        let generation = generation[0] & GENERATION_MASK;
+        let num_buckets = read_u64(&mut reader)? as usize;
+        if num_buckets == 0 {
+            return Err(invalid("Empty transposition table file"));
+        }
+        // Check the size in the header before allocating that many buckets.
+        let header_len = 4 + 4 + 8 + 1 + 8;
+        let bucket_len = (BUCKET_SIZE * 16) as u64;
+        if num_buckets > MAX_SIZE_MB * 1024 * 1024 / std::mem::size_of::<Bucket>() {
+            return Err(invalid("Transposition table file is too large"));
+        }
+        if file_len.checked_sub(header_len) != Some(num_buckets as u64 * bucket_len) {
+            return Err(invalid("Transposition table file size disagrees with its header"));
+        }
+
+        let mut buckets = Vec::with_capacity(num_buckets);
+        for _ in 0..num_buckets {
+            let bucket = Bucket::default();
+            for entry in &bucket.entries {
+                let key = read_u64(&mut reader)?;
+                let data = read_u64(&mut reader)?;
+                if Move::from_u32(data as u32).is_none() {
+                    return Err(invalid("Transposition table file holds a move off the board"));
+                }
+                entry.store(PackedEntry { key, data });
+            }
+            buckets.push(bucket);
+        }
+
+        self.buckets = buckets;
+        self.generation.store(generation, Ordering::Relaxed);
+        Ok(())
     }
 
-    pub fn clear(&mut self) {
-        self.entries.iter_mut().for_each(|entry| *entry = TtEntry::new_empty());
+    /// Empties the table and starts over at the first generation.
+    pub fn clear(&self) {
+        self.generation.store(0, Ordering::Relaxed);
+        self.buckets
+            .iter()
+            .flat_map(|bucket| bucket.entries.iter())
+            .for_each(|entry| entry.store(PackedEntry::default()));
     }
 }
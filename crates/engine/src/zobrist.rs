@@ -188,3 +188,18 @@ pub const ZOBRIST_KEYS: [[[u64; 9]; 10]; 14] = [
         [0xafdb4e0b01716f1d, 0xb7ed75b463d0fccd, 0x2c05b870cd34bcd7, 0x261cec19fff83cec, 0x0671493804fe973d, 0x52c553b5d2f74429, 0x00f2460382951fe0, 0x0c15a57509c64c1d, 0xecf150e6c6f149c7, ],
     ],
 ];
+
+/// A hash of all the keys above. Stored alongside saved hash keys, so keys
+/// produced by a different scheme are recognised.
+pub fn fingerprint() -> u64 {
+    // FNV-1a over the keys.
+    ZOBRIST_KEYS
+        .iter()
+        .flatten()
+        .flatten()
+        .chain(std::iter::once(&ZOBRIST_PLAYER))
+        .flat_map(|key| key.to_le_bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
@@ -24,7 +24,10 @@ use std::thread;
 use engine::{
     bitboard::Board,
     constants::{Piece, Player},
+    notation::chinese,
     r#move::Move,
+    search_limits::SearchLimits,
+    skill::MAX_SKILL_LEVEL,
 };
 use futures::{channel::mpsc, stream::BoxStream};
 
@@ -42,8 +45,11 @@ const INITIAL_FEN: &str = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNB
 const UCI_ENGINE_PATH: &str = "./target/release/uci";
 const UCI_CMD_UCI: &str = "uci";
 const UCI_CMD_ISREADY: &str = "isready";
+const UCI_CMD_UCINEWGAME: &str = "ucinewgame";
 const UCI_CMD_POSITION_FEN: &str = "position fen";
-const UCI_CMD_GO_MOVETIME: &str = "go movetime 5000"; // 3 seconds
+const ENGINE_MOVETIME_MS: u128 = 5000;
+/// Lower this for a weaker opponent, down to `MIN_SKILL_LEVEL`.
+const ENGINE_SKILL_LEVEL: u8 = MAX_SKILL_LEVEL;
 const UCI_RESPONSE_UCIOK: &str = "uciok";
 const UCI_RESPONSE_READYOK: &str = "readyok";
 const UCI_RESPONSE_BESTMOVE: &str = "bestmove";
@@ -73,8 +79,8 @@ enum Message {
     UciResponse(String),
     FenInputChanged(String),
     LoadFen,
-    /// Result of a player's move attempt. Contains the move, captured piece, new FEN, and optional game over message.
-    PlayerMoveFinalized(Result<(Move, Piece, String, Option<String>), ()>),
+    /// Result of a player's move attempt. Contains the move, new FEN, and optional game over message.
+    PlayerMoveFinalized(Result<(Move, String, Option<String>), ()>),
 }
 
 /// The main application state (the "Model").
@@ -87,7 +93,6 @@ struct XiangqiApp {
     // --- UI-specific state ---
     selected_square: Option<usize>,
     last_move: Option<Move>,
-    move_history: Vec<(Move, Piece)>,
     fen_input: String,
     game_state: GameState,
     game_id: u64,
@@ -121,7 +126,6 @@ impl Application for XiangqiApp {
             uci_stdout: Arc::new(Mutex::new(stdout)),
             selected_square: None,
             last_move: None,
-            move_history: Vec::new(),
             fen_input: INITIAL_FEN.to_string(),
             game_state: GameState::PlayerTurn,
             game_id: 0,
@@ -271,6 +275,9 @@ impl XiangqiApp {
         writeln!(&stdin, "{}", UCI_CMD_UCI).expect("Failed to write to UCI stdin");
         Self::wait_for_uci_response(&mut stdout, UCI_RESPONSE_UCIOK);
 
+        writeln!(&stdin, "setoption name Skill Level value {}", ENGINE_SKILL_LEVEL)
+            .expect("Failed to write to UCI stdin");
+
         writeln!(&stdin, "{}", UCI_CMD_ISREADY).expect("Failed to write to UCI stdin");
         Self::wait_for_uci_response(&mut stdout, UCI_RESPONSE_READYOK);
 
@@ -302,9 +309,7 @@ impl XiangqiApp {
                 Command::none()
             }
             Message::PlayerMoveFinalized(result) => match result {
-                Ok((mv, captured, fen, game_over_state)) => {
-                    self.apply_player_move(mv, captured, fen, game_over_state)
-                }
+                Ok((mv, fen, game_over_state)) => self.apply_player_move(mv, fen, game_over_state),
                 Err(()) => Command::none(), // Invalid move, do nothing.
             },
             Message::LoadFen => self.handle_load_fen(),
@@ -363,12 +368,10 @@ impl XiangqiApp {
     fn apply_player_move(
         &mut self,
         mv: Move,
-        captured: Piece,
         fen: String,
         game_over_state: Option<String>,
     ) -> Command<Message> {
         self.fen_input = fen;
-        self.move_history.push((mv, captured));
         self.last_move = Some(mv);
         self.board_cache.clear();
 
@@ -386,10 +389,9 @@ impl XiangqiApp {
         if let Some(move_str) = parts.get(1) {
             let board_lock = self.board.clone();
             let mut board = board_lock.lock().unwrap();
-            if let Some(mv) = self.parse_uci_move(&board, move_str) {
-                let captured = board.move_piece(mv);
+            if let Some(mv) = board.parse_uci_move(move_str) {
+                board.make_move(mv);
                 self.fen_input = board.to_fen();
-                self.move_history.push((mv, captured));
                 self.last_move = Some(mv);
                 self.board_cache.clear();
 
@@ -408,12 +410,13 @@ impl XiangqiApp {
         self.game_state = GameState::EngineThinking;
         let board_fen = self.board.lock().unwrap().to_fen();
         let uci_stdin = self.uci_stdin.clone();
+        let go_command = SearchLimits::movetime(ENGINE_MOVETIME_MS).to_uci_command();
 
         Command::perform(
             async move {
                 let mut uci_stdin = uci_stdin.lock().unwrap();
                 writeln!(uci_stdin, "{} {}", UCI_CMD_POSITION_FEN, board_fen).ok();
-                writeln!(uci_stdin, "{}", UCI_CMD_GO_MOVETIME).ok();
+                writeln!(uci_stdin, "{}", go_command).ok();
             },
             |_| Message::UciResponse("".to_string()), // Response is handled by the UciSubscription
         )
@@ -436,7 +439,6 @@ impl XiangqiApp {
         self.uci_stdout = Arc::new(Mutex::new(new_stdout));
         self.selected_square = None;
         self.last_move = None;
-        self.move_history.clear();
         self.fen_input = INITIAL_FEN.to_string();
         self.game_state = GameState::PlayerTurn;
         self.game_id += 1;
@@ -447,22 +449,16 @@ impl XiangqiApp {
 
     /// Undoes the last full turn (player and engine).
     fn handle_undo_move(&mut self) -> Command<Message> {
-        if self.move_history.len() >= 2 {
-            let board_lock = self.board.clone();
-            let mut board = board_lock.lock().unwrap();
-
-            // Un-do engine move
-            if let Some((mv, captured)) = self.move_history.pop() {
-                board.unmove_piece(mv, captured);
-            }
-            // Un-do player move
-            if let Some((mv, captured)) = self.move_history.pop() {
-                board.unmove_piece(mv, captured);
-            }
+        let board_lock = self.board.clone();
+        let mut board = board_lock.lock().unwrap();
+        if board.history_ply >= 2 {
+            // Un-do the engine move, then the player move
+            board.undo_move();
+            board.undo_move();
 
             self.fen_input = board.to_fen();
             self.game_state = GameState::PlayerTurn;
-            self.last_move = self.move_history.last().map(|(mv, _)| *mv);
+            self.last_move = board.last_move();
             self.selected_square = None;
             self.board_cache.clear();
         }
@@ -471,43 +467,18 @@ impl XiangqiApp {
 
     /// Loads a new board state from the FEN string in the input box.
     fn handle_load_fen(&mut self) -> Command<Message> {
-        // Use catch_unwind to prevent a panic from a malformed FEN string from crashing the app.
-        if let Ok(board) = std::panic::catch_unwind(|| Board::from_fen(&self.fen_input)) {
+        // A malformed FEN string or an invalid position, see `Board::validate`, is ignored.
+        if let Ok(board) = Board::try_from_fen(&self.fen_input) {
             self.board = Arc::new(Mutex::new(board));
             self.selected_square = None;
             self.last_move = None;
-            self.move_history.clear();
             self.game_state = GameState::PlayerTurn;
             self.board_cache.clear();
-        }
-        Command::none()
-    }
 
-    /// Parses a move in UCI format (e.g., "a0a1") into a `Move` object.
-    fn parse_uci_move(&self, board: &Board, move_str: &str) -> Option<Move> {
-        if move_str.len() < 4 {
-            return None;
+            // The engine keeps its hash table between moves, so tell it this is a new game.
+            writeln!(self.uci_stdin.lock().unwrap(), "{}", UCI_CMD_UCINEWGAME).ok();
         }
-        let from_file = move_str.chars().next()? as u8 - b'a';
-        let from_rank = move_str.chars().nth(1)? as u8 - b'0';
-        let to_file = move_str.chars().nth(2)? as u8 - b'a';
-        let to_rank = move_str.chars().nth(3)? as u8 - b'0';
-
-        let from_sq = (9 - from_rank) as usize * 9 + from_file as usize;
-        let to_sq = (9 - to_rank) as usize * 9 + to_file as usize;
-
-        let captured_piece = board.board[to_sq];
-        let is_capture = captured_piece != Piece::Empty;
-
-        Some(Move::new(
-            from_sq,
-            to_sq,
-            if is_capture {
-                Some(captured_piece)
-            } else {
-                None
-            },
-        ))
+        Command::none()
     }
 }
 
@@ -522,20 +493,16 @@ async fn validate_and_perform_player_move(
     board: Arc<Mutex<Board>>,
     from_sq: usize,
     to_sq: usize,
-) -> Result<(Move, Piece, String, Option<String>), ()> {
+) -> Result<(Move, String, Option<String>), ()> {
     let mut board = board.lock().unwrap();
-    let mut legal_moves = MoveList::new();
-    board.generate_legal_moves(&mut legal_moves);
+    let captured = board.board[to_sq];
+    let mv = Move::new(from_sq, to_sq, (captured != Piece::Empty).then_some(captured));
 
-    if let Some(&mv) = legal_moves
-        .as_slice()
-        .iter()
-        .find(|m| m.from_sq() == from_sq && m.to_sq() == to_sq)
-    {
-        let captured = board.move_piece(mv);
+    if board.is_legal(mv) {
+        board.make_move(mv);
         let fen = board.to_fen();
         let game_over_state = check_game_over_state(&mut board);
-        Ok((mv, captured, fen, game_over_state))
+        Ok((mv, fen, game_over_state))
     } else {
         Err(()) // Invalid move
     }
@@ -716,12 +683,8 @@ impl BoardCanvas {
 
     /// Draws all the pieces on the board.
     fn draw_pieces(&self, frame: &mut Frame, board: &Board) {
-        for (i, &piece) in board.board.iter().enumerate() {
-            if piece != Piece::Empty {
-                let r = i / 9;
-                let c = i % 9;
-                self.draw_single_piece(frame, piece, r, c);
-            }
+        for (sq, piece) in board.pieces() {
+            self.draw_single_piece(frame, piece, sq.rank().row(), sq.file().index());
         }
     }
 
@@ -754,7 +717,7 @@ impl BoardCanvas {
 
         // Draw piece character
         frame.fill_text(canvas::Text {
-            content: get_chinese_piece_char(piece).to_string(),
+            content: chinese::board_char(piece).to_string(),
             position: Point::new(x, y),
             color,
             size: Pixels(SQUARE_SIZE * 0.6),
@@ -792,26 +755,3 @@ impl BoardCanvas {
         frame.fill(&path, color);
     }
 }
-
-// --- Utility Functions ---
-
-/// Maps a `Piece` enum to its corresponding Chinese character representation.
-fn get_chinese_piece_char(piece: Piece) -> char {
-    match piece {
-        Piece::BKing => '将',
-        Piece::BGuard => '士',
-        Piece::BBishop => '象',
-        Piece::BHorse => '馬',
-        Piece::BRook => '車',
-        Piece::BCannon => '砲',
-        Piece::BPawn => '卒',
-        Piece::Empty => '·',
-        Piece::RKing => '帅',
-        Piece::RGuard => '仕',
-        Piece::RBishop => '相',
-        Piece::RHorse => '傌',
-        Piece::RRook => '俥',
-        Piece::RCannon => '炮',
-        Piece::RPawn => '兵',
-    }
-}
@@ -0,0 +1,250 @@
+//! SPSA tuning of the engine's `Config` constants through self-play.
+//!
+//! Every iteration perturbs all tuned constants at once in a random direction,
+//! plays a short match between the two perturbed configs and moves the constants
+//! towards the side that scored better. The current estimate is written to the
+//! output file after every iteration, in the format `Config::from_file` reads.
+
+use engine::bitboard::Board;
+use engine::config::{Config, TUNABLES, Tunable};
+use engine::constants::Player;
+use engine::engine::Engine;
+use engine::movelist::MoveList;
+use engine::search_limits::SearchLimits;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use std::env;
+use std::fs;
+use std::process;
+
+const START_FEN: &str = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1";
+
+/// Random plies played from the start position to get varied openings.
+const OPENING_PLIES: usize = 4;
+/// Games still running after this many plies are drawn.
+const MAX_GAME_PLIES: usize = 150;
+const HASH_MB: usize = 16;
+
+/// The SPSA schedule, as in Fishtest: perturbations shrink with `GAMMA`, steps
+/// with `ALPHA`, and `LEARNING_RATE` is the step size relative to the squared
+/// perturbation at the last iteration.
+const ALPHA: f64 = 0.602;
+const GAMMA: f64 = 0.101;
+const LEARNING_RATE: f64 = 0.002;
+
+struct Options {
+    iterations: usize,
+    /// Game pairs per iteration; each opening is played with both colors.
+    game_pairs: usize,
+    nodes: u64,
+    /// The constants to tune. All of `TUNABLES` if none are given.
+    params: Vec<Tunable>,
+    config: Config,
+    output: String,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: tune [--iterations N] [--pairs N] [--nodes N] [--params a,b,...] \
+         [--config FILE] [--output FILE]"
+    );
+    process::exit(1);
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        iterations: 200,
+        game_pairs: 2,
+        nodes: 2000,
+        params: TUNABLES.to_vec(),
+        config: Config::default(),
+        output: "tuned_config.toml".to_string(),
+    };
+    let args: Vec<String> = env::args().skip(1).collect();
+    for pair in args.chunks(2) {
+        let [flag, value] = pair else { usage() };
+        match flag.as_str() {
+            "--iterations" => options.iterations = value.parse().unwrap_or_else(|_| usage()),
+            "--pairs" => options.game_pairs = value.parse().unwrap_or_else(|_| usage()),
+            "--nodes" => options.nodes = value.parse().unwrap_or_else(|_| usage()),
+            "--params" => {
+                options.params = value
+                    .split(',')
+                    .map(|name| {
+                        *TUNABLES.iter().find(|t| t.name == name).unwrap_or_else(|| {
+                            eprintln!("Unknown config field `{}`", name);
+                            process::exit(1);
+                        })
+                    })
+                    .collect();
+            }
+            "--config" => {
+                options.config = Config::from_file(value).unwrap_or_else(|e| {
+                    eprintln!("Could not load config: {}", e);
+                    process::exit(1);
+                });
+            }
+            "--output" => options.output = value.clone(),
+            _ => usage(),
+        }
+    }
+    options
+}
+
+/// A start position reached by a few random moves.
+fn random_opening(rng: &mut impl Rng) -> Board {
+    let mut board = Board::from_fen(START_FEN);
+    for _ in 0..OPENING_PLIES {
+        let mut moves = MoveList::new();
+        board.generate_legal_moves(&mut moves);
+        board.make_move(*moves.as_slice().choose(rng).unwrap());
+    }
+    board
+}
+
+/// Plays one game from `opening` and returns the score of `red` (1, 0.5 or 0).
+fn play_game(red: &mut Engine, black: &mut Engine, opening: &Board, nodes: u64) -> f64 {
+    red.new_game();
+    black.new_game();
+    let limits = SearchLimits {
+        nodes: Some(nodes),
+        ..SearchLimits::default()
+    };
+    let mut board = opening.clone();
+    // The opening's random moves are part of the game too.
+    let mut game_history = opening.history[..opening.history_ply].to_vec();
+    for ply in 0..MAX_GAME_PLIES {
+        let mut moves = MoveList::new();
+        board.generate_legal_moves(&mut moves);
+        if moves.is_empty() {
+            // Whoever cannot move has lost, stalemate included.
+            return if ply % 2 == 0 { 0.0 } else { 1.0 };
+        }
+        if board.repetition_count(&game_history) >= 2 {
+            // A draw, unless the rules make the side that forced it lose.
+            let rules = red.config.rules;
+            let first = game_history.iter().position(|&hash| hash == board.hash_key);
+            return match first.and_then(|ply| rules.repetition_outcome(&mut board, ply).loser()) {
+                Some(Player::Red) => 0.0,
+                Some(Player::Black) => 1.0,
+                None => 0.5,
+            };
+        }
+        if red
+            .config
+            .rules
+            .move_rule_plies()
+            .is_some_and(|plies| board.halfmove_clock >= plies)
+        {
+            return 0.5;
+        }
+        let engine = if ply % 2 == 0 { &mut *red } else { &mut *black };
+        let result = engine.search(&mut board.clone(), &limits);
+        game_history.push(board.hash_key);
+        board.make_move(result.best_move);
+    }
+    0.5
+}
+
+/// Plays each opening with both colors and returns the score of `first` minus
+/// the score of `second`.
+fn play_match(first: &mut Engine, second: &mut Engine, openings: &[Board], nodes: u64) -> f64 {
+    openings
+        .iter()
+        .map(|opening| {
+            let as_red = play_game(first, second, opening, nodes);
+            let as_black = 1.0 - play_game(second, first, opening, nodes);
+            (as_red + as_black) - (2.0 - as_red - as_black)
+        })
+        .sum()
+}
+
+fn new_engine() -> Engine {
+    let mut engine = Engine::new(HASH_MB);
+    engine.own_book = false;
+    engine.print_info = false;
+    engine
+}
+
+/// `config` with the tuned constants set to `values`, rounded and clamped.
+fn with_values(config: &Config, params: &[Tunable], values: &[f64]) -> Config {
+    let mut config = config.clone();
+    for (tunable, &value) in params.iter().zip(values) {
+        let value = (value.round() as i32).clamp(tunable.min, tunable.max);
+        config.set(tunable.name, value).unwrap();
+    }
+    config
+}
+
+fn main() {
+    let options = parse_options();
+    if options.params.is_empty() {
+        usage();
+    }
+    let params = &options.params;
+    let n = options.iterations as f64;
+
+    let mut theta: Vec<f64> = params
+        .iter()
+        .map(|t| options.config.get(t.name).unwrap() as f64)
+        .collect();
+    // The perturbation at the last iteration, per constant. At least 1, or
+    // rounding would undo it.
+    let c_end: Vec<f64> = params
+        .iter()
+        .map(|t| ((t.max - t.min) as f64 / 200.0).max(1.0))
+        .collect();
+    let stability = n * 0.1;
+
+    let mut rng = rand::thread_rng();
+    let (mut plus_engine, mut minus_engine) = (new_engine(), new_engine());
+    for k in 0..options.iterations {
+        let k = k as f64 + 1.0;
+        let delta: Vec<f64> = params
+            .iter()
+            .map(|_| if rng.gen_bool(0.5) { 1.0 } else { -1.0 })
+            .collect();
+        let c_k: Vec<f64> = c_end
+            .iter()
+            .map(|c| c * n.powf(GAMMA) / k.powf(GAMMA))
+            .collect();
+
+        let shifted = |sign: f64| -> Vec<f64> {
+            theta
+                .iter()
+                .zip(&c_k)
+                .zip(&delta)
+                .map(|((t, c), d)| t + sign * c * d)
+                .collect()
+        };
+        plus_engine.config = with_values(&options.config, params, &shifted(1.0));
+        minus_engine.config = with_values(&options.config, params, &shifted(-1.0));
+
+        let openings: Vec<Board> = (0..options.game_pairs)
+            .map(|_| random_opening(&mut rng))
+            .collect();
+        let result = play_match(
+            &mut plus_engine,
+            &mut minus_engine,
+            &openings,
+            options.nodes,
+        );
+
+        for (i, tunable) in params.iter().enumerate() {
+            let a = LEARNING_RATE * c_end[i] * c_end[i] * (n + stability).powf(ALPHA);
+            let a_k = a / (k + stability).powf(ALPHA);
+            theta[i] = (theta[i] + a_k * result * delta[i] / c_k[i])
+                .clamp(tunable.min as f64, tunable.max as f64);
+        }
+
+        let config = with_values(&options.config, params, &theta);
+        if let Err(e) = fs::write(&options.output, config.to_toml()) {
+            eprintln!("Could not write {}: {}", options.output, e);
+        }
+        let summary: Vec<String> = params
+            .iter()
+            .map(|t| format!("{}={}", t.name, config.get(t.name).unwrap()))
+            .collect();
+        println!("iteration {} result {:+} {}", k, result, summary.join(" "));
+    }
+}
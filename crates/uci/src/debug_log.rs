@@ -0,0 +1,52 @@
+//! Logging of the protocol traffic, for debugging a GUI's conversation with the
+//! engine.
+//!
+//! Nothing is logged unless a file is given, with the `Debug Log File` option or the
+//! `XIANGQI_LOG_FILE` environment variable, so the engine also runs where it cannot
+//! write to its working directory.
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// The environment variable naming the log file to use from the start.
+pub const LOG_FILE_ENV: &str = "XIANGQI_LOG_FILE";
+
+/// A handle to the log, shared by the command loop and the searches' output.
+#[derive(Debug, Clone, Default)]
+pub struct DebugLog {
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl DebugLog {
+    /// A log to the file named by `XIANGQI_LOG_FILE`, if it is set and can be opened.
+    pub fn from_env() -> Self {
+        let log = Self::default();
+        if let Ok(path) = env::var(LOG_FILE_ENV)
+            && let Err(e) = log.open(&path)
+        {
+            eprintln!("Warning: Could not open log file {}: {}", path, e);
+        }
+        log
+    }
+
+    /// Logs to `path` from now on, appending to the file, or stops logging if `path`
+    /// is empty. On an error, logging stops.
+    pub fn open(&self, path: &str) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        *file = None;
+        if !path.is_empty() {
+            *file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        }
+        Ok(())
+    }
+
+    /// Logs `line` with `direction`, `Received` or `Sent`. A failed write is ignored,
+    /// as the log must not stop the engine.
+    pub fn write(&self, direction: &str, line: &str) {
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            let _ = writeln!(file, "{}: {}", direction, line);
+        }
+    }
+}
@@ -1,192 +1,467 @@
+use debug_log::DebugLog;
+use engine::bench::BENCH_DEPTH;
 use engine::bitboard::Board;
+use engine::config::{CONFIG_FILE, Config};
 use engine::engine::Engine;
-use engine::r#move::Move;
-use std::fs::File;
-use std::io::{self, BufRead, Write};
+use engine::evaluate::evaluate_trace;
+use engine::evaluate::params::{self, PARAMS_FILE};
+use engine::game::START_FEN;
+use engine::mcts::MctsSearcher;
+use engine::output::{self, Output};
+use engine::perft::{perft_divide, perft_parallel};
+use engine::search_limits::SearchLimits;
+use engine::searcher::{SearchBackend, Searcher};
+use engine::stop_token::StopToken;
+use options::{DEFAULT_HASH_MB, Settings};
+use std::env;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use ucci::UcciState;
 
-fn parse_uci_move(board: &Board, move_str: &str) -> Option<Move> {
-    if move_str.len() != 4 {
-        return None;
-    }
-    let from_file = move_str.chars().nth(0).unwrap() as u8 - b'a';
-    let from_rank = move_str.chars().nth(1).unwrap() as u8 - b'0';
-    let to_file = move_str.chars().nth(2).unwrap() as u8 - b'a';
-    let to_rank = move_str.chars().nth(3).unwrap() as u8 - b'0';
-
-    let from_sq = (9 - from_rank) as usize * 9 + from_file as usize;
-    let to_sq = (9 - to_rank) as usize * 9 + to_file as usize;
-
-    let captured_piece = board.board[to_sq];
-
-    Some(Move::new(
-        from_sq,
-        to_sq,
-        if captured_piece == engine::constants::Piece::Empty {
-            None
-        } else {
-            Some(captured_piece)
-        },
-    ))
-}
+mod debug_log;
+mod options;
+mod ucci;
 
-pub fn parse_go_command(parts: &[&str], board: &Board) -> (i32, Option<u128>) {
-    let mut depth = 64; // Default depth
-    let mut time_limit_ms = None;
+/// Tokens that start a new `go` parameter, ending a `searchmoves` list.
+const GO_KEYWORDS: [&str; 12] = [
+    "searchmoves",
+    "ponder",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "depth",
+    "nodes",
+    "mate",
+    "movetime",
+    "infinite",
+];
 
-    if parts.contains(&"infinite") {
-        return (i32::MAX, None);
-    }
+pub fn parse_go_command(board: &Board, parts: &[&str]) -> SearchLimits {
+    let mut limits = SearchLimits::default();
 
-    if let Some(depth_idx) = parts.iter().position(|&x| x == "depth") {
-        if let Some(depth_val) = parts.get(depth_idx + 1) {
-            if let Ok(d) = depth_val.parse() {
-                depth = d;
+    let mut i = 1;
+    while i < parts.len() {
+        let value = parts.get(i + 1);
+        match parts[i] {
+            "depth" => limits.depth = value.and_then(|s| s.parse().ok()),
+            "nodes" => limits.nodes = value.and_then(|s| s.parse().ok()),
+            "movetime" => limits.movetime = value.and_then(|s| s.parse().ok()),
+            "wtime" => limits.wtime = value.and_then(|s| s.parse().ok()),
+            "btime" => limits.btime = value.and_then(|s| s.parse().ok()),
+            "winc" => limits.winc = value.and_then(|s| s.parse().ok()),
+            "binc" => limits.binc = value.and_then(|s| s.parse().ok()),
+            "movestogo" => limits.movestogo = value.and_then(|s| s.parse().ok()),
+            "mate" => limits.mate = value.and_then(|s| s.parse().ok()),
+            "infinite" => {
+                limits.infinite = true;
+                i += 1;
+                continue;
+            }
+            "ponder" => {
+                limits.ponder = true;
+                i += 1;
+                continue;
+            }
+            "searchmoves" => {
+                i += 1;
+                let mut board = board.clone();
+                while i < parts.len() && !GO_KEYWORDS.contains(&parts[i]) {
+                    if let Some(mv) = board.parse_uci_move(parts[i]) {
+                        limits.searchmoves.push(mv);
+                    }
+                    i += 1;
+                }
+                continue;
+            }
+            _ => {
+                i += 1;
+                continue;
             }
         }
+        i += 2;
     }
+    limits
+}
 
-    if let Some(movetime_idx) = parts.iter().position(|&x| x == "movetime") {
-        if let Some(movetime_val) = parts.get(movetime_idx + 1) {
-            if let Ok(t) = movetime_val.parse() {
-                time_limit_ms = Some(t);
-            }
-        }
+/// Whether a search is still running. A search that finished or stopped is joined.
+fn search_running(search_thread: &mut Option<JoinHandle<()>>, stop: &StopToken) -> bool {
+    if search_thread
+        .as_ref()
+        .is_some_and(|handle| handle.is_finished() || stop.is_stopped())
+    {
+        search_thread.take().unwrap().join().unwrap();
     }
+    search_thread.is_some()
+}
 
-    if time_limit_ms.is_none() {
-        let mut wtime: Option<u128> = None;
-        let mut btime: Option<u128> = None;
-        let mut winc: Option<u128> = Some(0);
-        let mut binc: Option<u128> = Some(0);
-        let mut movestogo: Option<u128> = None;
-
-        let mut i = 0;
-        while i < parts.len() {
-            match parts[i] {
-                "wtime" => {
-                    wtime = parts.get(i + 1).and_then(|s| s.parse().ok());
-                    i += 2;
-                }
-                "btime" => {
-                    btime = parts.get(i + 1).and_then(|s| s.parse().ok());
-                    i += 2;
-                }
-                "winc" => {
-                    winc = parts.get(i + 1).and_then(|s| s.parse().ok());
-                    i += 2;
-                }
-                "binc" => {
-                    binc = parts.get(i + 1).and_then(|s| s.parse().ok());
-                    i += 2;
-                }
-                "movestogo" => {
-                    movestogo = parts.get(i + 1).and_then(|s| s.parse().ok());
-                    i += 2;
-                }
-                _ => i += 1,
-            }
+/// Parses the position of `position` or `probe`: `startpos` or `fen <fen>`, then
+/// optionally `moves <move>...`. The FEN must be a valid position and every move
+/// legal where it is played, or the engine's board would silently differ from the GUI's.
+fn parse_position(parts: &[&str]) -> Result<Board, String> {
+    let moves_idx = parts.iter().position(|&x| x == "moves");
+    let mut board = match parts.get(1) {
+        Some(&"startpos") => Board::from_fen(START_FEN),
+        Some(&"fen") => Board::try_from_fen(&parts[2..moves_idx.unwrap_or(parts.len())].join(" "))?,
+        _ => return Err("expected startpos or fen".to_string()),
+    };
+
+    if let Some(moves_idx) = moves_idx {
+        for (i, move_str) in parts[moves_idx + 1..].iter().enumerate() {
+            let mv = board.parse_uci_move(move_str).ok_or_else(|| {
+                format!(
+                    "move {} `{}` is not legal in {}",
+                    i + 1,
+                    move_str,
+                    board.to_fen()
+                )
+            })?;
+            board.make_move(mv);
         }
+    }
+    Ok(board)
+}
 
-        let (time_to_use, increment) = if board.player_to_move == engine::constants::Player::Red {
-            (wtime, winc.unwrap_or(0))
-        } else {
-            (btime, binc.unwrap_or(0))
-        };
+/// Holds back the result of a ponder search until `ponderhit` or `stop`, and of an
+/// infinite search until `stop`, as the GUI must not get a bestmove before then. A
+/// search can end early, on a book move, a mate or its depth limit.
+fn wait_before_bestmove(pondering: &AtomicBool, infinite: bool, stop: &StopToken) {
+    while (infinite || pondering.load(Ordering::Relaxed)) && !stop.is_stopped() {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
 
-        if let Some(t) = time_to_use {
-            if let Some(moves) = movestogo {
-                time_limit_ms = Some(t / moves as u128 + increment);
-            } else {
-                time_limit_ms = Some(t / 20u128 + increment);
-            }
-        }
+/// Forgets everything the backends learned in the previous game. With `skip_book`,
+/// the new game is played without the opening book.
+fn new_game(engine: &Mutex<Engine>, mcts: &Mutex<MctsSearcher>, skip_book: bool) {
+    let mut engine = engine.lock().unwrap();
+    engine.new_game();
+    engine.skip_book = skip_book;
+    drop(engine);
+    mcts.lock().unwrap().new_game();
+}
+
+/// Stops the running search, if any, and waits for it to print its bestmove.
+fn stop_search_thread(search_thread: &mut Option<JoinHandle<()>>, stop: &StopToken) {
+    if let Some(handle) = search_thread.take() {
+        stop.stop();
+        handle.join().unwrap();
     }
-    (depth, time_limit_ms)
 }
 
 fn main() {
-    let mut log_file = File::create("uci.log").unwrap();
-    let engine = Arc::new(Mutex::new(Engine::new(128)));
-    let mut board: Option<Board> = None;
+    let debug_log = DebugLog::from_env();
+    // Must happen before any board is set up, as boards keep scores incrementally.
+    if Path::new(PARAMS_FILE).exists()
+        && let Err(e) = params::load(PARAMS_FILE)
+    {
+        eprintln!("Warning: Could not load evaluation parameters: {}", e);
+    }
+    // Set by `--ucci` or the `ucci` command, see `ucci`.
+    let ucci_mode = Arc::new(AtomicBool::new(env::args().any(|arg| arg == "--ucci")));
+    // The protocol traffic and everything the searches report goes to stdout at
+    // once, and to the debug log.
+    let output: Output = {
+        let stdout = output::stdout();
+        let debug_log = debug_log.clone();
+        let ucci_mode = Arc::clone(&ucci_mode);
+        Arc::new(move |line| {
+            let line = if ucci_mode.load(Ordering::Relaxed) {
+                ucci::translate(line)
+            } else {
+                line.into()
+            };
+            let line = line.as_ref();
+            stdout(line);
+            debug_log.write("Sent", line);
+        })
+    };
+    let engine = Arc::new(Mutex::new(Engine::new(DEFAULT_HASH_MB)));
+    engine.lock().unwrap().output = Arc::clone(&output);
+    if Path::new(CONFIG_FILE).exists() {
+        match Config::from_file(CONFIG_FILE) {
+            Ok(config) => engine.lock().unwrap().config = config,
+            Err(e) => eprintln!("Warning: Could not load engine config: {}", e),
+        }
+    }
+    // The position the next `go` searches. Until the GUI sets one, and after a new
+    // game starts, it is the start position.
+    let mut board = Board::from_fen(START_FEN);
+    // Advertised with the defaults from the config file, without locking the engine
+    // while it searches.
+    let uci_options = options::options(&engine.lock().unwrap().config);
+
+    // The search runs on its own thread while the engine is locked, so these
+    // signals are the only way to reach it until it finishes. Commands that need
+    // the engine are refused or wait for the search, everything else is answered
+    // at once.
+    let (stop, ponderhit_signal) = {
+        let engine_lock = engine.lock().unwrap();
+        (
+            engine_lock.stop.clone(),
+            Arc::clone(&engine_lock.ponderhit_signal),
+        )
+    };
+    let mut search_thread: Option<JoinHandle<()>> = None;
+    // Set while a `go ponder` search waits for `ponderhit`. Unlike the engine's
+    // signal, which the search consumes, it stays cleared once the ponderhit came.
+    let pondering = Arc::new(AtomicBool::new(false));
+
+    // The MCTS backend shares the engine's signals, so `stop` and `ponderhit`
+    // reach whichever backend is searching.
+    let mcts = {
+        let mut mcts = MctsSearcher::new();
+        mcts.stop = stop.clone();
+        mcts.ponderhit_signal = Arc::clone(&ponderhit_signal);
+        mcts.output = Arc::clone(&output);
+        Arc::new(Mutex::new(mcts))
+    };
+    let mut settings = Settings {
+        debug_log: debug_log.clone(),
+        ..Settings::default()
+    };
+    let mut ucci_state = UcciState::default();
 
     let stdin = io::stdin();
     for line in stdin.lock().lines() {
         let line = line.unwrap();
-        writeln!(log_file, "Received: {}", line).unwrap();
+        debug_log.write("Received", &line);
         let parts: Vec<&str> = line.split_whitespace().collect();
-        if let Some(command) = parts.get(0) {
+        if let Some(command) = parts.first() {
             match *command {
                 "uci" => {
-                    println!("id name Xiangqi");
-                    println!("id author Hezhaoyun");
-                    println!("uciok");
+                    output("id name Xiangqi");
+                    output("id author Hezhaoyun");
+                    for option in &uci_options {
+                        output(&option.to_uci_string());
+                    }
+                    output("uciok");
+                }
+                "ucci" => {
+                    ucci_mode.store(true, Ordering::Relaxed);
+                    for line in ucci::handshake(&uci_options) {
+                        output(&line);
+                    }
+                }
+                "setoption" => {
+                    if search_running(&mut search_thread, &stop) {
+                        output("info string setoption ignored while searching");
+                        continue;
+                    }
+                    if ucci_mode.load(Ordering::Relaxed) {
+                        let result = ucci::set_option(
+                            &mut engine.lock().unwrap(),
+                            &mut settings,
+                            &mut ucci_state,
+                            &parts,
+                        );
+                        match result {
+                            // UCCI's `ucinewgame`, `setoption newgame [nobook]`.
+                            Ok(true) => {
+                                new_game(&engine, &mcts, parts[2..].contains(&"nobook"));
+                                board = Board::from_fen(START_FEN);
+                                ucci_state.banned_moves.clear();
+                            }
+                            Ok(false) => {}
+                            Err(e) => output(&format!("info string {}", e)),
+                        }
+                        continue;
+                    }
+                    let name_idx = parts.iter().position(|&x| x == "name");
+                    let value_idx = parts.iter().position(|&x| x == "value");
+                    // Buttons are set without a value.
+                    if let Some(name_idx) = name_idx {
+                        let name = parts[name_idx + 1..value_idx.unwrap_or(parts.len())].join(" ");
+                        let value = value_idx.map_or(String::new(), |i| parts[i + 1..].join(" "));
+                        let mut engine = engine.lock().unwrap();
+                        if let Err(e) =
+                            options::set_option(&mut engine, &mut settings, &name, &value)
+                        {
+                            output(&format!("info string {}", e));
+                        }
+                    }
                 }
                 "isready" => {
-                    println!("readyok");
+                    output("readyok");
                 }
+                // `ucinewgame [nobook]`, where `nobook` keeps the engine out of its
+                // opening book for the game.
                 "ucinewgame" => {
-                    let mut engine_lock = engine.lock().unwrap();
-                    engine_lock.clear_history();
-                    engine_lock.tt.clear();
-                }
-                "position" => {
-                    let mut new_board = if parts.get(1) == Some(&"startpos") {
-                        Board::from_fen(
-                            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1",
-                        )
-                    } else if parts.get(1) == Some(&"fen") {
-                        let fen = parts[2..].join(" ");
-                        Board::from_fen(&fen)
+                    stop_search_thread(&mut search_thread, &stop);
+                    new_game(&engine, &mcts, parts[1..].contains(&"nobook"));
+                    board = Board::from_fen(START_FEN);
+                    ucci_state.banned_moves.clear();
+                }
+                // Sets up the position for the next `go`. A running search keeps
+                // searching its own copy.
+                // A rejected position leaves the previous one in place.
+                "position" => match parse_position(&parts) {
+                    Ok(new_board) => {
+                        board = new_board;
+                        ucci_state.banned_moves.clear();
+                    }
+                    Err(e) => output(&format!("info string position rejected: {}", e)),
+                },
+                // UCCI: moves the next `go` must not play.
+                "banmoves" => {
+                    ucci_state.banned_moves = parts[1..].iter().map(|s| s.to_string()).collect();
+                }
+                // UCCI: the hash table entry of a position, without searching it.
+                "probe" => {
+                    let probed = if parts.len() > 1 {
+                        parse_position(&parts)
                     } else {
+                        Ok(board.clone())
+                    };
+                    let reply = match probed {
+                        Ok(probed) if !search_running(&mut search_thread, &stop) => {
+                            ucci::probe(&engine.lock().unwrap(), &probed)
+                        }
+                        _ => "pophash".to_string(),
+                    };
+                    output(&reply);
+                }
+                "go" => {
+                    // Only one search runs at a time. A search is ended with
+                    // `stop` before the next one starts.
+                    if search_running(&mut search_thread, &stop) {
+                        output("info string go ignored, a search is already running");
                         continue;
+                    }
+                    let ucci = ucci_mode.load(Ordering::Relaxed);
+                    let mut limits = if ucci {
+                        let Some(limits) = ucci::parse_go_command(&board, &parts, &ucci_state)
+                        else {
+                            output("nobestmove");
+                            continue;
+                        };
+                        limits
+                    } else {
+                        parse_go_command(&board, &parts)
+                    };
+                    limits.move_overhead = settings.move_overhead_ms;
+                    let mut search_board = board.clone();
+                    let searcher: Arc<Mutex<dyn Searcher>> = match settings.backend {
+                        SearchBackend::AlphaBeta => engine.clone(),
+                        SearchBackend::Mcts => {
+                            // Options and the config file only set the engine's
+                            // config, which the MCTS backend evaluates with too.
+                            mcts.lock().unwrap().config = engine.lock().unwrap().config.clone();
+                            mcts.clone()
+                        }
                     };
+                    let output = Arc::clone(&output);
+                    stop.reset();
+                    ponderhit_signal.store(false, Ordering::Relaxed);
+                    pondering.store(limits.ponder, Ordering::Relaxed);
+                    let (pondering, stop) = (Arc::clone(&pondering), stop.clone());
 
-                    if let Some(moves_idx) = parts.iter().position(|&x| x == "moves") {
-                        for move_str in &parts[moves_idx + 1..] {
-                            if let Some(mv) = parse_uci_move(&new_board, move_str) {
-                                new_board.move_piece(mv);
-                            }
+                    search_thread = Some(thread::spawn(move || {
+                        let result = searcher.lock().unwrap().search(&mut search_board, &limits);
+                        wait_before_bestmove(&pondering, limits.infinite, &stop);
+                        // The GUI may answer the bestmove before this thread ends,
+                        // and that answer must not find the search still running.
+                        stop.stop();
+
+                        // The search returns the null move `a9a9` when there is
+                        // nothing to play, which UCCI answers with `nobestmove`.
+                        let best_move = result.best_move;
+                        if ucci && best_move.from_sq() == best_move.to_sq() {
+                            output("nobestmove");
+                            return;
                         }
+                        output(&match result.ponder_move {
+                            Some(ponder_move) => format!(
+                                "bestmove {} ponder {}",
+                                result.best_move.to_uci_string(),
+                                ponder_move.to_uci_string()
+                            ),
+                            None => format!("bestmove {}", result.best_move.to_uci_string()),
+                        });
+                    }));
+                }
+                // Not part of UCI: shows the position the next `go` searches, with
+                // the side to move and the hash key above the diagram.
+                "d" => {
+                    for line in board.to_string().lines() {
+                        output(line);
                     }
-                    board = Some(new_board);
+                    output(&format!("Fen: {}", board.to_fen()));
                 }
-                "go" => {
-                    if let Some(ref mut b) = board {
-                        let (depth, time_limit_ms) = parse_go_command(&parts, b);
-                        // writeln!(log_file, "depth: {}, time_limit_ms: {:?}", depth, time_limit_ms).unwrap();
-
-                        let mut engine_lock = engine.lock().unwrap();
-                        engine_lock.stop_search = false;
-
-                        let (best_move, best_score, searched_depth) =
-                            engine_lock.search(b, depth, time_limit_ms);
-
-                        writeln!(
-                            log_file,
-                            "bestmove {}, bestscore: {}, searched_depth: {}",
-                            best_move.to_uci_string(),
-                            -best_score,
-                            searched_depth
-                        )
-                        .unwrap();
-
-                        println!(
-                            "bestmove {}, bestscore: {}",
-                            best_move.to_uci_string(),
-                            -best_score
-                        );
+                // Not part of UCI: the static evaluation of the current position,
+                // term by term, with the engine's current settings.
+                "eval" => {
+                    if search_running(&mut search_thread, &stop) {
+                        output("info string eval ignored while searching");
+                        continue;
                     }
+                    let trace = evaluate_trace(&board, &engine.lock().unwrap().config);
+                    for line in trace.to_string().lines() {
+                        output(line);
+                    }
+                }
+                // Not part of UCI: count the leaf nodes of the legal move tree of
+                // the current position, in total or below each root move.
+                "perft" | "divide" => {
+                    let Some(depth) = parts.get(1).and_then(|s| s.parse::<u32>().ok()) else {
+                        output(&format!("info string usage: {} <depth>", command));
+                        continue;
+                    };
+                    stop_search_thread(&mut search_thread, &stop);
+
+                    let start = Instant::now();
+                    let nodes = if *command == "divide" {
+                        let divide = perft_divide(&mut board.clone(), depth);
+                        for (mv, nodes) in &divide {
+                            output(&format!("{}: {}", mv.to_uci_string(), nodes));
+                        }
+                        divide.iter().map(|(_, nodes)| nodes).sum()
+                    } else {
+                        perft_parallel(&board, depth, 0)
+                    };
+                    let elapsed_ms = start.elapsed().as_millis();
+                    output(&format!("Nodes searched: {}", nodes));
+                    output(&format!(
+                        "Nodes/second: {}",
+                        nodes as u128 * 1000 / elapsed_ms.max(1)
+                    ));
+                }
+                "bench" => {
+                    stop_search_thread(&mut search_thread, &stop);
+                    let depth = parts
+                        .get(1)
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(BENCH_DEPTH);
+
+                    let result = engine.lock().unwrap().bench(depth);
+                    output(&format!("Nodes searched: {}", result.nodes));
+                    output(&format!("Nodes/second: {}", result.nps()));
+                    output(&format!("Signature: {:016x}", result.signature));
+                }
+                // Only a ponder search has anything to do with a ponderhit; a late
+                // one must not turn the next ponder search into a normal one.
+                "ponderhit" if pondering.swap(false, Ordering::Relaxed) => {
+                    ponderhit_signal.store(true, Ordering::Relaxed);
                 }
                 "stop" => {
-                    let mut engine_lock = engine.lock().unwrap();
-                    engine_lock.stop_search = true;
+                    stop_search_thread(&mut search_thread, &stop);
                 }
                 "quit" => {
+                    if ucci_mode.load(Ordering::Relaxed) {
+                        stop_search_thread(&mut search_thread, &stop);
+                        output("bye");
+                    }
                     break;
                 }
                 _ => {}
             }
         }
     }
+
+    stop_search_thread(&mut search_thread, &stop);
 }
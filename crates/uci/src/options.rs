@@ -0,0 +1,298 @@
+//! The options the engine advertises after `uci` and applies on `setoption`.
+//!
+//! Each option is described once, with its type and range, so the advertisement and
+//! the checks on `setoption` values cannot drift apart.
+
+use crate::debug_log::DebugLog;
+use engine::config::{Config, TUNABLES};
+use engine::engine::Engine;
+use engine::opening_book::{DEFAULT_BOOK_FILE, OpeningBook};
+use engine::rules::Rules;
+use engine::searcher::SearchBackend;
+use engine::skill::{MAX_SKILL_LEVEL, MIN_SKILL_LEVEL, Skill};
+use engine::tt::MAX_SIZE_MB;
+use std::sync::Arc;
+
+/// The default and maximum size of the transposition table, in MB.
+pub const DEFAULT_HASH_MB: usize = 128;
+const MAX_HASH_MB: usize = MAX_SIZE_MB;
+
+/// The time kept back from every timed move by default, in milliseconds.
+const DEFAULT_MOVE_OVERHEAD_MS: u128 = 10;
+
+/// The type of an option, with its default and the values it takes.
+pub enum OptionKind {
+    Check(bool),
+    Spin {
+        default: i32,
+        min: i32,
+        max: i32,
+    },
+    Combo {
+        default: &'static str,
+        vars: &'static [&'static str],
+    },
+    String(&'static str),
+    Button,
+}
+
+pub struct UciOption {
+    pub name: &'static str,
+    pub kind: OptionKind,
+}
+
+impl UciOption {
+    fn spin(name: &'static str, default: i32, min: i32, max: i32) -> Self {
+        Self {
+            name,
+            kind: OptionKind::Spin { default, min, max },
+        }
+    }
+
+    /// The `option` line advertising the option.
+    pub fn to_uci_string(&self) -> String {
+        match &self.kind {
+            OptionKind::Check(default) => {
+                format!("option name {} type check default {}", self.name, default)
+            }
+            OptionKind::Spin { default, min, max } => format!(
+                "option name {} type spin default {} min {} max {}",
+                self.name, default, min, max
+            ),
+            OptionKind::Combo { default, vars } => format!(
+                "option name {} type combo default {} var {}",
+                self.name,
+                default,
+                vars.join(" var ")
+            ),
+            OptionKind::String(default) => {
+                format!("option name {} type string default {}", self.name, default)
+            }
+            OptionKind::Button => format!("option name {} type button", self.name),
+        }
+    }
+
+    /// The `option` line advertising the option under `name` in UCCI, which has no
+    /// `name` keyword and gives the default last.
+    pub fn to_ucci_string(&self, name: &str) -> String {
+        match &self.kind {
+            OptionKind::Check(default) => {
+                format!("option {} type check default {}", name, default)
+            }
+            OptionKind::Spin { default, min, max } => format!(
+                "option {} type spin min {} max {} default {}",
+                name, min, max, default
+            ),
+            OptionKind::Combo { default, vars } => format!(
+                "option {} type combo var {} default {}",
+                name,
+                vars.join(" var "),
+                default
+            ),
+            OptionKind::String(default) => {
+                format!("option {} type string default {}", name, default)
+            }
+            OptionKind::Button => format!("option {} type button", name),
+        }
+    }
+
+    /// Parses `value` as a value of this option. Spin values out of range are clamped.
+    fn parse(&self, value: &str) -> Result<OptionValue, String> {
+        let invalid = || format!("invalid value `{}` for option {}", value, self.name);
+        match &self.kind {
+            OptionKind::Check(_) => match value.to_ascii_lowercase().as_str() {
+                "true" => Ok(OptionValue::Check(true)),
+                "false" => Ok(OptionValue::Check(false)),
+                _ => Err(invalid()),
+            },
+            OptionKind::Spin { min, max, .. } => value
+                .parse::<i32>()
+                .map(|value| OptionValue::Spin(value.clamp(*min, *max)))
+                .map_err(|_| invalid()),
+            OptionKind::Combo { vars, .. } => vars
+                .iter()
+                .find(|var| var.eq_ignore_ascii_case(value))
+                .map(|var| OptionValue::Combo(var))
+                .ok_or_else(invalid),
+            // GUIs send `<empty>` for an empty string.
+            OptionKind::String(_) if value == "<empty>" => Ok(OptionValue::String(String::new())),
+            OptionKind::String(_) => Ok(OptionValue::String(value.to_string())),
+            OptionKind::Button => Ok(OptionValue::Button),
+        }
+    }
+}
+
+enum OptionValue {
+    Check(bool),
+    Spin(i32),
+    Combo(&'static str),
+    String(String),
+    Button,
+}
+
+/// All options, with the tunables' defaults taken from `config`.
+pub fn options(config: &Config) -> Vec<UciOption> {
+    let mut options = vec![
+        UciOption::spin("Hash", DEFAULT_HASH_MB as i32, 1, MAX_HASH_MB as i32),
+        UciOption::spin("MultiPV", 1, 1, 100),
+        // Tells the GUI that the engine can think on the opponent's time. The GUI
+        // decides when to, with `go ponder`.
+        UciOption {
+            name: "Ponder",
+            kind: OptionKind::Check(false),
+        },
+        UciOption {
+            name: "OwnBook",
+            kind: OptionKind::Check(true),
+        },
+        UciOption {
+            name: "BookFile",
+            kind: OptionKind::String(DEFAULT_BOOK_FILE),
+        },
+        UciOption {
+            name: "BookMirror",
+            kind: OptionKind::Check(true),
+        },
+        UciOption::spin("MoveOverhead", DEFAULT_MOVE_OVERHEAD_MS as i32, 0, 5000),
+        UciOption::spin(
+            "Skill Level",
+            MAX_SKILL_LEVEL as i32,
+            MIN_SKILL_LEVEL as i32,
+            MAX_SKILL_LEVEL as i32,
+        ),
+        UciOption::spin("EvalNoise", 0, 0, 100),
+        UciOption::spin("EvalNoiseMoves", 10, 0, 100),
+        UciOption {
+            name: "SearchBackend",
+            kind: OptionKind::Combo {
+                default: SearchBackend::default().name(),
+                vars: &SearchBackend::NAMES,
+            },
+        },
+        UciOption {
+            name: "Rules",
+            kind: OptionKind::Combo {
+                default: Rules::default().name(),
+                vars: &Rules::NAMES,
+            },
+        },
+        // Where the protocol traffic is logged, see `DebugLog`. Empty for no log.
+        UciOption {
+            name: "Debug Log File",
+            kind: OptionKind::String("<empty>"),
+        },
+        // The file `Save Hash` writes the transposition table to and `Load Hash`
+        // reads it back from, with the size it was saved with.
+        UciOption {
+            name: "Hash File",
+            kind: OptionKind::String("<empty>"),
+        },
+        UciOption {
+            name: "Save Hash",
+            kind: OptionKind::Button,
+        },
+        UciOption {
+            name: "Load Hash",
+            kind: OptionKind::Button,
+        },
+    ];
+    options.extend(TUNABLES.iter().map(|tunable| {
+        UciOption::spin(
+            tunable.name,
+            config.get(tunable.name).unwrap(),
+            tunable.min,
+            tunable.max,
+        )
+    }));
+    options
+}
+
+/// The settings options change outside the engine, which apply to every backend.
+pub struct Settings {
+    pub backend: SearchBackend,
+    pub move_overhead_ms: u128,
+    pub debug_log: DebugLog,
+    pub hash_file: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            backend: SearchBackend::default(),
+            move_overhead_ms: DEFAULT_MOVE_OVERHEAD_MS,
+            debug_log: DebugLog::default(),
+            hash_file: String::new(),
+        }
+    }
+}
+
+/// Applies `setoption name <name> value <value>`. Option names are matched ignoring
+/// case.
+pub fn set_option(
+    engine: &mut Engine,
+    settings: &mut Settings,
+    name: &str,
+    value: &str,
+) -> Result<(), String> {
+    // The search runs on one thread, so Threads is not advertised. GUIs send it
+    // anyway, and it is accepted without effect.
+    if name.eq_ignore_ascii_case("Threads") {
+        return Ok(());
+    }
+    let options = options(&engine.config);
+    let option = options
+        .iter()
+        .find(|option| option.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("unknown option {}", name))?;
+
+    match (option.name, option.parse(value)?) {
+        ("Hash", OptionValue::Spin(size_mb)) => engine.tt.resize(size_mb as usize),
+        ("Ponder", _) => {}
+        ("MultiPV", OptionValue::Spin(multi_pv)) => engine.multi_pv = multi_pv as usize,
+        ("OwnBook", OptionValue::Check(own_book)) => engine.own_book = own_book,
+        // An empty path leaves the engine without a book.
+        ("BookFile", OptionValue::String(path)) if path.is_empty() => {
+            engine.book = Some(Arc::new(OpeningBook::default()));
+        }
+        ("BookFile", OptionValue::String(path)) => {
+            let book = OpeningBook::load(&path)
+                .map_err(|e| format!("could not load opening book {}: {}", path, e))?;
+            engine.book = Some(Arc::new(book));
+        }
+        ("BookMirror", OptionValue::Check(book_mirror)) => engine.book_mirror = book_mirror,
+        ("MoveOverhead", OptionValue::Spin(ms)) => settings.move_overhead_ms = ms as u128,
+        ("Skill Level", OptionValue::Spin(level)) => engine.skill = Skill::new(level as u8),
+        ("EvalNoise", OptionValue::Spin(noise)) => engine.eval_noise = noise,
+        ("EvalNoiseMoves", OptionValue::Spin(moves)) => engine.eval_noise_moves = moves as usize,
+        ("SearchBackend", OptionValue::Combo(backend)) => {
+            settings.backend = SearchBackend::from_name(backend).unwrap();
+        }
+        ("Rules", OptionValue::Combo(rules)) => {
+            engine.config.rules = Rules::from_name(rules).unwrap();
+        }
+        ("Debug Log File", OptionValue::String(path)) => settings
+            .debug_log
+            .open(&path)
+            .map_err(|e| format!("could not open log file {}: {}", path, e))?,
+        ("Hash File", OptionValue::String(path)) => settings.hash_file = path,
+        ("Save Hash", OptionValue::Button) => engine
+            .tt
+            .save(hash_file(settings)?)
+            .map_err(|e| format!("could not save hash file {}: {}", settings.hash_file, e))?,
+        ("Load Hash", OptionValue::Button) => engine
+            .tt
+            .load(hash_file(settings)?)
+            .map_err(|e| format!("could not load hash file {}: {}", settings.hash_file, e))?,
+        (tunable, OptionValue::Spin(value)) => engine.config.set(tunable, value)?,
+        _ => unreachable!("option {} has no handler for its type", option.name),
+    }
+    Ok(())
+}
+
+/// The path `Save Hash` and `Load Hash` use.
+fn hash_file(settings: &Settings) -> Result<&str, String> {
+    if settings.hash_file.is_empty() {
+        return Err("no Hash File set".to_string());
+    }
+    Ok(&settings.hash_file)
+}
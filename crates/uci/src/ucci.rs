@@ -0,0 +1,243 @@
+//! UCCI, the protocol most Chinese GUIs speak instead of UCI.
+//!
+//! UCCI shares `isready`, `position`, `stop`, `ponderhit` and `quit` with UCI, but
+//! starts with `ucci`, sets options without the `name` and `value` keywords, gives
+//! times in seconds, forbids moves with `banmoves`, can ask for the hash table entry
+//! of a position with `probe` and reports plain scores. The search itself speaks UCI,
+//! so its lines are translated on the way out.
+
+use crate::options::{self, Settings, UciOption};
+use engine::bitboard::Board;
+use engine::constants::{MATE_VALUE, Player};
+use engine::engine::{Engine, score_from_tt};
+use engine::movelist::MoveList;
+use engine::search_limits::SearchLimits;
+use engine::tt::TtFlag;
+use std::borrow::Cow;
+
+/// UCCI option names and the options they stand for.
+const ALIASES: [(&str, &str); 5] = [
+    ("hashsize", "Hash"),
+    ("threads", "Threads"),
+    ("usebook", "OwnBook"),
+    ("bookfiles", "BookFile"),
+    ("ponder", "Ponder"),
+];
+
+/// Standard UCCI options the engine has nothing to do with. They are accepted
+/// without a reply, as every GUI sends some of them.
+const IGNORED_OPTIONS: [&str; 11] = [
+    "batch",
+    "debug",
+    "idle",
+    "promotion",
+    "useegtb",
+    "egtbpaths",
+    "evalapi",
+    "knowledge",
+    "pruning",
+    "randomness",
+    "style",
+];
+
+/// What the UCCI session keeps besides the UCI settings.
+#[derive(Debug, Default)]
+pub struct UcciState {
+    /// Whether `go` times are in milliseconds rather than seconds.
+    pub use_millisec: bool,
+    /// The moves the next `go` must not play, set by `banmoves` until the next
+    /// `position`.
+    pub banned_moves: Vec<String>,
+}
+
+/// The reply to `ucci`: the engine's name and `uci_options`, then `ucciok`.
+pub fn handshake(uci_options: &[UciOption]) -> Vec<String> {
+    let mut lines = vec![
+        "id name Xiangqi".to_string(),
+        "id author Hezhaoyun".to_string(),
+        "option usemillisec type check default false".to_string(),
+        "option newgame type button".to_string(),
+    ];
+    for option in uci_options {
+        let alias = ALIASES.iter().find(|(_, name)| *name == option.name);
+        // Option values are separated by spaces, so names with spaces cannot be set.
+        match alias {
+            Some((alias, _)) => lines.push(option.to_ucci_string(alias)),
+            None if !option.name.contains(' ') => lines.push(option.to_ucci_string(option.name)),
+            None => {}
+        }
+    }
+    lines.push("ucciok".to_string());
+    lines
+}
+
+/// Applies `setoption <name> [<value>]`. Returns `Ok(true)` for `newgame [nobook]`,
+/// which the caller handles.
+pub fn set_option(
+    engine: &mut Engine,
+    settings: &mut Settings,
+    state: &mut UcciState,
+    parts: &[&str],
+) -> Result<bool, String> {
+    let Some(&name) = parts.get(1) else {
+        return Err("setoption without an option".to_string());
+    };
+    let value = parts[2..].join(" ");
+    if name.eq_ignore_ascii_case("newgame") {
+        return Ok(true);
+    }
+    if name.eq_ignore_ascii_case("usemillisec") {
+        state.use_millisec = value.eq_ignore_ascii_case("true");
+    } else if !IGNORED_OPTIONS
+        .iter()
+        .any(|ignored| ignored.eq_ignore_ascii_case(name))
+    {
+        let name = ALIASES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+            .map_or(name, |(_, name)| name);
+        options::set_option(engine, settings, name, &value)?;
+    }
+    Ok(false)
+}
+
+/// Parses `go [ponder | draw] [depth <d> | nodes <n> | time <t> [movestogo <m> |
+/// increment <i>] [opptime <t> [oppmovestogo <m> | oppincrement <i>]]]`, where
+/// times are in seconds unless `usemillisec` is set. Banned moves are left out of
+/// the root moves; `None` means every legal move is banned and there is nothing to
+/// search.
+pub fn parse_go_command(board: &Board, parts: &[&str], state: &UcciState) -> Option<SearchLimits> {
+    let mut limits = SearchLimits::default();
+    let to_ms = |value: Option<&&str>| {
+        let value: u128 = value?.parse().ok()?;
+        Some(if state.use_millisec {
+            value
+        } else {
+            value * 1000
+        })
+    };
+    // UCCI times are the side to move's and its opponent's, not Red's and Black's.
+    let red_to_move = board.player_to_move == Player::Red;
+
+    let mut i = 1;
+    while i < parts.len() {
+        let value = parts.get(i + 1);
+        match parts[i] {
+            "ponder" => limits.ponder = true,
+            "draw" => {}
+            "depth" if value == Some(&"infinite") => {
+                limits.infinite = true;
+                i += 1;
+            }
+            "depth" => {
+                limits.depth = value.and_then(|s| s.parse().ok());
+                i += 1;
+            }
+            "nodes" => {
+                limits.nodes = value.and_then(|s| s.parse().ok());
+                i += 1;
+            }
+            "time" | "opptime" => {
+                let time = to_ms(value);
+                if (parts[i] == "time") == red_to_move {
+                    limits.wtime = time;
+                } else {
+                    limits.btime = time;
+                }
+                i += 1;
+            }
+            "increment" | "oppincrement" => {
+                let increment = to_ms(value);
+                if (parts[i] == "increment") == red_to_move {
+                    limits.winc = increment;
+                } else {
+                    limits.binc = increment;
+                }
+                i += 1;
+            }
+            "movestogo" => {
+                limits.movestogo = value.and_then(|s| s.parse().ok());
+                i += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if !state.banned_moves.is_empty() {
+        let mut board = board.clone();
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+        limits.searchmoves = legal_moves
+            .as_slice()
+            .iter()
+            .copied()
+            .filter(|mv| !state.banned_moves.contains(&mv.to_uci_string()))
+            .collect();
+        // An empty `searchmoves` would let the search play any move.
+        if limits.searchmoves.is_empty() {
+            return None;
+        }
+    }
+    Some(limits)
+}
+
+/// The reply to `probe`: what the hash table knows about `board`, as `pophash
+/// [bestmove <move>] [lowerbound <score> depth <d>] [upperbound <score> depth <d>]`.
+pub fn probe(engine: &Engine, board: &Board) -> String {
+    let mut line = String::from("pophash");
+    if let Some(entry) = engine.tt.probe(board.hash_key) {
+        // The probed position is the root, as if a search had started there.
+        let score = score_from_tt(entry.score, 0);
+        if entry.best_move.from_sq() != entry.best_move.to_sq() {
+            line.push_str(&format!(" bestmove {}", entry.best_move.to_uci_string()));
+        }
+        if entry.flag != TtFlag::UpperBound {
+            line.push_str(&format!(" lowerbound {} depth {}", score, entry.depth));
+        }
+        if entry.flag != TtFlag::LowerBound {
+            line.push_str(&format!(" upperbound {} depth {}", score, entry.depth));
+        }
+    }
+    line
+}
+
+/// Translates a line of the search's UCI output to UCCI. `info` lines keep the
+/// depth, score, time, nodes, move being searched and principal variation, with the
+/// score in plain points and mates as scores near `MATE_VALUE`. Other lines pass
+/// unchanged.
+pub fn translate(line: &str) -> Cow<'_, str> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.first() != Some(&"info") || parts.get(1) == Some(&"string") {
+        return Cow::Borrowed(line);
+    }
+
+    let mut fields = vec!["info".to_string()];
+    let mut i = 1;
+    while i < parts.len() {
+        match parts[i] {
+            "depth" | "time" | "nodes" | "currmove" => {
+                fields.push(format!("{} {}", parts[i], parts.get(i + 1).unwrap_or(&"0")));
+                i += 2;
+            }
+            "score" => {
+                let value: i32 = parts.get(i + 2).and_then(|s| s.parse().ok()).unwrap_or(0);
+                let score = match parts.get(i + 1) {
+                    // Mates are given in moves: a mate in n is 2n - 1 plies away.
+                    Some(&"mate") if value > 0 => MATE_VALUE - (2 * value - 1),
+                    Some(&"mate") => -MATE_VALUE + 2 * -value,
+                    _ => value,
+                };
+                fields.push(format!("score {}", score));
+                i += 3;
+            }
+            "pv" => {
+                fields.push(parts[i..].join(" "));
+                break;
+            }
+            // `nps`, `hashfull`, `multipv` and the like have no UCCI counterpart.
+            _ => i += 2,
+        }
+    }
+    Cow::Owned(fields.join(" "))
+}
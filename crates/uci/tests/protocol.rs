@@ -0,0 +1,231 @@
+//! Runs the engine binary on scripted protocol sessions.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+
+const BOOK_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../opening_book.bin");
+
+/// Sends `commands` to a fresh engine and returns everything it printed. After a
+/// `go`, waits for the bestmove or nobestmove, as most commands would stop the search.
+fn run(commands: &[&str]) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_uci"))
+        .env_remove("XIANGQI_LOG_FILE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        // The default book is not in the test's directory, which the engine warns about.
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut output = String::new();
+    for command in commands {
+        writeln!(stdin, "{}", command).unwrap();
+        if command.starts_with("go") {
+            let mut line = String::new();
+            while stdout.read_line(&mut line).unwrap() > 0
+                && !line.starts_with("bestmove")
+                && !line.starts_with("nobestmove")
+            {
+                output.push_str(&line);
+                line.clear();
+            }
+            output.push_str(&line);
+        }
+    }
+    drop(stdin);
+    stdout.read_to_string(&mut output).unwrap();
+    child.wait().unwrap();
+    output
+}
+
+#[test]
+fn test_nobook_game() {
+    let set_book = format!("setoption name BookFile value {}", BOOK_FILE);
+    let game = |new_game| {
+        run(&[
+            &set_book,
+            new_game,
+            "position startpos",
+            "go depth 1",
+            "isready",
+            "quit",
+        ])
+    };
+    let output = game("ucinewgame");
+    assert!(output.contains("info string book move"), "{}", output);
+    let output = game("ucinewgame nobook");
+    assert!(!output.contains("info string book move"), "{}", output);
+    assert!(output.contains("bestmove"), "{}", output);
+
+    let set_book = format!("setoption bookfiles {}", BOOK_FILE);
+    let ucci_game = |new_game| {
+        run(&[
+            "ucci",
+            &set_book,
+            new_game,
+            "position startpos",
+            "go depth 1",
+            "isready",
+            "quit",
+        ])
+    };
+    let output = ucci_game("setoption newgame");
+    assert!(output.contains("info string book move"), "{}", output);
+    let output = ucci_game("setoption newgame nobook");
+    assert!(!output.contains("info string book move"), "{}", output);
+    assert!(output.contains("bestmove"), "{}", output);
+}
+
+#[test]
+fn test_hash_file() {
+    let path = std::env::temp_dir().join(format!("test_hash_file_{}.bin", std::process::id()));
+    let set_file = format!("setoption name Hash File value {}", path.display());
+    let fen = "r1bakabr1/9/1cn4cn/p1p1p1p1p/9/9/P1P1P1P1P/1CN4CN/9/R1BAKABR1 w - - 0 1";
+    let position = format!("position fen {}", fen);
+    let probe = format!("probe fen {}", fen);
+    let output = run(&[
+        "setoption name Hash value 1",
+        "setoption name Save Hash",
+        &set_file,
+        &position,
+        "go depth 3",
+        "setoption name Save Hash",
+        "ucinewgame",
+        &probe,
+        "setoption name Load Hash",
+        &probe,
+        "quit",
+    ]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(
+        output.contains("info string no Hash File set"),
+        "{}",
+        output
+    );
+    let pophash: Vec<&str> = output
+        .lines()
+        .filter(|line| line.starts_with("pophash"))
+        .collect();
+    assert_eq!(pophash.len(), 2, "{}", output);
+    assert_eq!(pophash[0], "pophash");
+    assert!(pophash[1].starts_with("pophash bestmove"), "{}", output);
+}
+
+#[test]
+fn test_ucci() {
+    let output = run(&[
+        "ucci",
+        "setoption hashsize 16",
+        "setoption batch on",
+        "setoption nosuchoption 1",
+        "position fen 3k5/9/9/9/9/9/9/9/R8/R3K4 w - - 0 1",
+        "go depth 3",
+        "probe",
+        "banmoves a0d0 a1d1",
+        "go depth 3",
+        "position fen 3k5/9/9/9/9/9/9/9/9/4K4 w - - 0 1",
+        "banmoves e0e1 e0f0",
+        "go depth 1",
+        "quit",
+    ]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(
+        lines.contains(&"option hashsize type spin min 1 max 4096 default 128"),
+        "{}",
+        output
+    );
+    assert!(!output.contains("Hash File"), "{}", output);
+    assert!(lines.contains(&"ucciok"), "{}", output);
+    // Standard options the engine ignores are accepted without a reply.
+    let info: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|line| line.starts_with("info string"))
+        .collect();
+    assert_eq!(
+        info,
+        ["info string unknown option nosuchoption"],
+        "{}",
+        output
+    );
+
+    // Scores are plain points, mates included, and there is no `nps`.
+    let info: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|line| line.starts_with("info depth"))
+        .collect();
+    assert!(
+        info.iter()
+            .all(|line| !line.contains(" cp ") && !line.contains("mate") && !line.contains("nps")),
+        "{}",
+        output
+    );
+    assert!(
+        info.iter().any(|line| line.contains(" score 9999 ")),
+        "{}",
+        output
+    );
+
+    let bestmoves: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|line| line.contains("bestmove"))
+        .collect();
+    assert_eq!(bestmoves[0], "bestmove a0d0");
+    assert_eq!(
+        bestmoves[1],
+        "pophash bestmove a0d0 lowerbound 9999 depth 2 upperbound 9999 depth 2"
+    );
+    // Both mates are banned.
+    assert!(
+        bestmoves[2].starts_with("bestmove ")
+            && !["bestmove a0d0", "bestmove a1d1"].contains(&bestmoves[2]),
+        "{}",
+        output
+    );
+    // Every move of the king is banned.
+    assert_eq!(bestmoves[3], "nobestmove");
+}
+
+#[test]
+fn test_setoption() {
+    let output = run(&[
+        "setoption name Hash value 16",
+        "setoption name Hash value lots",
+        "setoption name Threads value 4",
+        "setoption name NoSuchOption value 1",
+        "setoption name tempo_bonus_mg value 30",
+        "eval",
+        // Out of range spin values are clamped.
+        "setoption name tempo_bonus_mg value 5000",
+        "eval",
+        "setoption name Rules value NoSuchRules",
+        "quit",
+    ]);
+    let info: Vec<&str> = output
+        .lines()
+        .filter(|line| line.starts_with("info string"))
+        .collect();
+    assert_eq!(
+        info,
+        [
+            "info string invalid value `lots` for option Hash",
+            "info string unknown option NoSuchOption",
+            "info string invalid value `NoSuchRules` for option Rules",
+        ],
+        "{}",
+        output
+    );
+    // The start position is all midgame, so only the midgame tempo counts.
+    let tempo: Vec<Vec<&str>> = output
+        .lines()
+        .filter(|line| line.starts_with("tempo"))
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(
+        tempo,
+        [["tempo", "30", "0", "30"], ["tempo", "1000", "0", "1000"]]
+    );
+}